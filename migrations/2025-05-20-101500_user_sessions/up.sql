@@ -0,0 +1,8 @@
+-- сессии пользователей (токен в заголовке secret)
+CREATE TABLE user_sessions (
+    id       TEXT PRIMARY KEY,
+    user_id  VARCHAR(100) NOT NULL,
+    created  TIMESTAMP NOT NULL,
+    expires  TIMESTAMP NOT NULL
+);
+CREATE INDEX user_sessions_user_idx ON user_sessions (user_id);
@@ -0,0 +1,3 @@
+-- подтверждение почты; уже зарегистрированные аккаунты считаем подтверждёнными
+ALTER TABLE users ADD COLUMN verified BOOLEAN NOT NULL DEFAULT false;
+UPDATE users SET verified = true;
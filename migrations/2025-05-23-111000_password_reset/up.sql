@@ -0,0 +1,7 @@
+-- одноразовые токены сброса пароля, не больше одного на пользователя
+CREATE TABLE password_reset_tokens (
+    id         BYTEA PRIMARY KEY,
+    user_id    VARCHAR(100) UNIQUE NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
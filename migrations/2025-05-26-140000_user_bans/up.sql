@@ -0,0 +1,23 @@
+/*
+история блокировок пользователей
+expires - NULL бессрочно
+lifted  - когда снята (вручную или по истечении срока)
+prev_perm - роль до блокировки, восстанавливается при снятии
+*/
+CREATE TABLE user_bans (
+    id           TEXT PRIMARY KEY,
+    user_id      VARCHAR(100) NOT NULL,
+    moderator_id VARCHAR(100) NOT NULL,
+    reason       VARCHAR(500) NOT NULL,
+    prev_perm    SMALLINT NOT NULL,
+    created      TIMESTAMP NOT NULL,
+    expires      TIMESTAMP,
+    lifted       TIMESTAMP,
+    lifted_by    VARCHAR(100)
+);
+CREATE INDEX user_bans_user_idx ON user_bans (user_id);
+
+-- уже заблокированным без истории заводим бессрочную запись
+INSERT INTO user_bans (id, user_id, moderator_id, reason, prev_perm, created)
+SELECT md5(id || 'ban'), id, '', '', 1, now()
+FROM users WHERE perm = 21;
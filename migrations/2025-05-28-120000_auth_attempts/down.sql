@@ -0,0 +1,2 @@
+DROP TABLE auth_lockouts;
+DROP TABLE auth_attempts;
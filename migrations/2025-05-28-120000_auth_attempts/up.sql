@@ -0,0 +1,23 @@
+-- журнал попыток входа
+CREATE TABLE auth_attempts (
+    id         TEXT PRIMARY KEY,
+    email      VARCHAR(100) NOT NULL,
+    user_id    VARCHAR(100),
+    ip         VARCHAR(100) NOT NULL,
+    user_agent VARCHAR(500) NOT NULL,
+    success    BOOLEAN NOT NULL,
+    created    TIMESTAMP NOT NULL
+);
+CREATE INDEX auth_attempts_email_idx ON auth_attempts (email, created);
+CREATE INDEX auth_attempts_ip_idx ON auth_attempts (ip, created);
+
+/*
+счётчики неудачных входов и временные блокировки
+key - "email:<почта>" или "ip:<адрес>"
+*/
+CREATE TABLE auth_lockouts (
+    key          VARCHAR(200) PRIMARY KEY,
+    failures     INT NOT NULL,
+    locked_until TIMESTAMP,
+    updated      TIMESTAMP NOT NULL
+);
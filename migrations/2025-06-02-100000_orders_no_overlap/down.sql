@@ -0,0 +1,3 @@
+DROP TRIGGER orders_no_overlap ON orders;
+DROP FUNCTION orders_no_overlap();
+DROP INDEX orders_object_time_idx;
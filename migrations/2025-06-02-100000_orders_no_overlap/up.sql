@@ -0,0 +1,30 @@
+/*
+защита от двойного бронирования: один модуль нельзя забронировать
+на пересекающееся время. Время хранится строкой "%Y-%m-%d %H:%M:%S",
+поэтому строки сравниваются как даты.
+Параллельные вставки по одному модулю выстраиваются в очередь
+через advisory-блокировку по object_id (её же берёт Order::create).
+*/
+CREATE INDEX orders_object_time_idx ON orders (object_id, time_start, time_end);
+
+CREATE FUNCTION orders_no_overlap() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock(hashtext(NEW.object_id));
+    IF EXISTS (
+        SELECT 1 FROM orders
+        WHERE object_id = NEW.object_id
+          AND place_id = NEW.place_id
+          AND id <> NEW.id
+          AND time_start < NEW.time_end
+          AND time_end > NEW.time_start
+    ) THEN
+        RAISE EXCEPTION 'Модуль % уже забронирован на это время', NEW.object_id
+            USING ERRCODE = 'exclusion_violation', CONSTRAINT = 'orders_no_overlap';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER orders_no_overlap
+    BEFORE INSERT OR UPDATE OF object_id, place_id, time_start, time_end ON orders
+    FOR EACH ROW EXECUTE PROCEDURE orders_no_overlap();
@@ -0,0 +1,41 @@
+/*
+статус брони, коды см. models/order_status.rs.
+Уже существующие брони считаем подтверждёнными, новые создаются ожидающими.
+*/
+ALTER TABLE orders ADD COLUMN status SMALLINT NOT NULL DEFAULT 2;
+ALTER TABLE orders ALTER COLUMN status SET DEFAULT 1;
+ALTER TABLE orders ADD COLUMN status_changed TIMESTAMP NOT NULL DEFAULT now();
+
+-- история переходов: кто, когда и из какого статуса в какой
+CREATE TABLE order_status_logs (
+    id          TEXT PRIMARY KEY,
+    order_id    VARCHAR(100) NOT NULL,
+    user_id     VARCHAR(100) NOT NULL,
+    status_from SMALLINT NOT NULL,
+    status_to   SMALLINT NOT NULL,
+    created     TIMESTAMP NOT NULL
+);
+CREATE INDEX order_status_logs_order_idx ON order_status_logs (order_id, created);
+
+-- отменённые брони и неявки время не занимают
+CREATE OR REPLACE FUNCTION orders_no_overlap() RETURNS trigger AS $$
+BEGIN
+    IF NEW.status IN (6, 7, 8) THEN
+        RETURN NEW;
+    END IF;
+    PERFORM pg_advisory_xact_lock(hashtext(NEW.object_id));
+    IF EXISTS (
+        SELECT 1 FROM orders
+        WHERE object_id = NEW.object_id
+          AND place_id = NEW.place_id
+          AND id <> NEW.id
+          AND status NOT IN (6, 7, 8)
+          AND time_start < NEW.time_end
+          AND time_end > NEW.time_start
+    ) THEN
+        RAISE EXCEPTION 'Модуль % уже забронирован на это время', NEW.object_id
+            USING ERRCODE = 'exclusion_violation', CONSTRAINT = 'orders_no_overlap';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
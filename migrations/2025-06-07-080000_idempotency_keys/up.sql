@@ -0,0 +1,15 @@
+/*
+ключи идемпотентности: повтор запроса с тем же Idempotency-Key
+возвращает сохранённый ответ, а не создаёт заказ заново.
+request_hash - sha256 тела запроса, чтобы ключ нельзя было
+использовать для другого заказа
+*/
+CREATE TABLE idempotency_keys (
+    key          VARCHAR(200) NOT NULL,
+    user_id      VARCHAR(100) NOT NULL,
+    request_hash VARCHAR(64) NOT NULL,
+    response     JSONB NOT NULL,
+    created      TIMESTAMP NOT NULL,
+    PRIMARY KEY (user_id, key)
+);
+CREATE INDEX idempotency_keys_created_idx ON idempotency_keys (created);
@@ -0,0 +1,26 @@
+DROP TRIGGER order_holds_no_overlap ON order_holds;
+DROP FUNCTION order_holds_no_overlap();
+
+CREATE OR REPLACE FUNCTION orders_no_overlap() RETURNS trigger AS $$
+BEGIN
+    IF NEW.status IN (6, 7, 8) THEN
+        RETURN NEW;
+    END IF;
+    PERFORM pg_advisory_xact_lock(hashtext(NEW.object_id));
+    IF EXISTS (
+        SELECT 1 FROM orders
+        WHERE object_id = NEW.object_id
+          AND place_id = NEW.place_id
+          AND id <> NEW.id
+          AND status NOT IN (6, 7, 8)
+          AND time_start < NEW.time_end
+          AND time_end > NEW.time_start
+    ) THEN
+        RAISE EXCEPTION 'Модуль % уже забронирован на это время', NEW.object_id
+            USING ERRCODE = 'exclusion_violation', CONSTRAINT = 'orders_no_overlap';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TABLE order_holds;
@@ -0,0 +1,81 @@
+/*
+временные холды модулей на время оформления заказа.
+Холд занимает время модуля для всех, кроме своего пользователя,
+пока не истёк expires; истёкшие удаляет фоновая задача.
+*/
+CREATE TABLE order_holds (
+    id         TEXT PRIMARY KEY,
+    user_id    VARCHAR(100) NOT NULL,
+    place_id   VARCHAR(100) NOT NULL,
+    object_id  VARCHAR(100) NOT NULL,
+    event_id   VARCHAR(100),
+    time_start VARCHAR(100) NOT NULL,
+    time_end   VARCHAR(100) NOT NULL,
+    expires    TIMESTAMP NOT NULL,
+    created    TIMESTAMP NOT NULL
+);
+CREATE INDEX order_holds_object_time_idx ON order_holds (object_id, time_start, time_end);
+CREATE INDEX order_holds_expires_idx ON order_holds (expires);
+CREATE INDEX order_holds_user_idx ON order_holds (user_id);
+
+-- бронь не может перекрыть чужой действующий холд
+CREATE OR REPLACE FUNCTION orders_no_overlap() RETURNS trigger AS $$
+BEGIN
+    IF NEW.status IN (6, 7, 8) THEN
+        RETURN NEW;
+    END IF;
+    PERFORM pg_advisory_xact_lock(hashtext(NEW.object_id));
+    IF EXISTS (
+        SELECT 1 FROM orders
+        WHERE object_id = NEW.object_id
+          AND place_id = NEW.place_id
+          AND id <> NEW.id
+          AND status NOT IN (6, 7, 8)
+          AND time_start < NEW.time_end
+          AND time_end > NEW.time_start
+    ) OR EXISTS (
+        SELECT 1 FROM order_holds
+        WHERE object_id = NEW.object_id
+          AND place_id = NEW.place_id
+          AND user_id <> NEW.user_id
+          AND expires > now() AT TIME ZONE 'UTC'
+          AND time_start < NEW.time_end
+          AND time_end > NEW.time_start
+    ) THEN
+        RAISE EXCEPTION 'Модуль % уже забронирован на это время', NEW.object_id
+            USING ERRCODE = 'exclusion_violation', CONSTRAINT = 'orders_no_overlap';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- холд не может перекрыть бронь или другой действующий холд
+CREATE FUNCTION order_holds_no_overlap() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock(hashtext(NEW.object_id));
+    IF EXISTS (
+        SELECT 1 FROM orders
+        WHERE object_id = NEW.object_id
+          AND place_id = NEW.place_id
+          AND status NOT IN (6, 7, 8)
+          AND time_start < NEW.time_end
+          AND time_end > NEW.time_start
+    ) OR EXISTS (
+        SELECT 1 FROM order_holds
+        WHERE object_id = NEW.object_id
+          AND place_id = NEW.place_id
+          AND id <> NEW.id
+          AND expires > now() AT TIME ZONE 'UTC'
+          AND time_start < NEW.time_end
+          AND time_end > NEW.time_start
+    ) THEN
+        RAISE EXCEPTION 'Модуль % уже забронирован на это время', NEW.object_id
+            USING ERRCODE = 'exclusion_violation', CONSTRAINT = 'orders_no_overlap';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER order_holds_no_overlap
+    BEFORE INSERT OR UPDATE OF object_id, place_id, time_start, time_end ON order_holds
+    FOR EACH ROW EXECUTE PROCEDURE order_holds_no_overlap();
@@ -0,0 +1,2 @@
+DROP TABLE payment_orders;
+DROP TABLE payments;
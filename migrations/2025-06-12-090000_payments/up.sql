@@ -0,0 +1,23 @@
+/*
+платежи: одна попытка оплаты может покрывать несколько броней одного заказа.
+status: 1 ожидает, 2 оплачен, 3 ошибка, 4 отменён (см. models/payment.rs)
+*/
+CREATE TABLE payments (
+    id          TEXT PRIMARY KEY,
+    user_id     VARCHAR(100) NOT NULL,
+    provider    VARCHAR(50) NOT NULL,
+    external_id VARCHAR(200),
+    amount      INT NOT NULL,
+    status      SMALLINT NOT NULL,
+    created     TIMESTAMP NOT NULL,
+    updated     TIMESTAMP NOT NULL
+);
+CREATE UNIQUE INDEX payments_external_idx ON payments (provider, external_id);
+CREATE INDEX payments_user_idx ON payments (user_id);
+
+CREATE TABLE payment_orders (
+    payment_id VARCHAR(100) NOT NULL,
+    order_id   VARCHAR(100) NOT NULL,
+    PRIMARY KEY (payment_id, order_id)
+);
+CREATE INDEX payment_orders_order_idx ON payment_orders (order_id);
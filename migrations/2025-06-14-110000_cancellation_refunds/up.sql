@@ -0,0 +1,30 @@
+/*
+правила отмены брони объекта:
+- раньше чем за free_hours до начала - полный возврат;
+- раньше чем за fee_hours - возврат за вычетом fee_percent;
+- позже - без возврата.
+Без записи для объекта возврат полный в любой момент.
+*/
+CREATE TABLE cancellation_policies (
+    place_id    VARCHAR(100) PRIMARY KEY,
+    free_hours  INT NOT NULL,
+    fee_hours   INT NOT NULL,
+    fee_percent SMALLINT NOT NULL,
+    updated     TIMESTAMP NOT NULL,
+    CHECK (free_hours >= fee_hours AND fee_hours >= 0),
+    CHECK (fee_percent BETWEEN 0 AND 100)
+);
+
+-- возвраты по отменённым броням; status: 1 ожидает, 2 проведён, 3 ошибка
+CREATE TABLE refunds (
+    id          TEXT PRIMARY KEY,
+    payment_id  VARCHAR(100) NOT NULL,
+    order_id    VARCHAR(100) NOT NULL,
+    amount      INT NOT NULL,
+    fee         INT NOT NULL,
+    status      SMALLINT NOT NULL,
+    external_id VARCHAR(200),
+    created     TIMESTAMP NOT NULL
+);
+CREATE INDEX refunds_order_idx ON refunds (order_id);
+CREATE INDEX refunds_payment_idx ON refunds (payment_id);
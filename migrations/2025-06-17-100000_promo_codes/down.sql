@@ -0,0 +1,4 @@
+ALTER TABLE orders DROP COLUMN discount;
+ALTER TABLE orders DROP COLUMN promo_code_id;
+DROP TABLE promo_code_uses;
+DROP TABLE promo_codes;
@@ -0,0 +1,40 @@
+/*
+промокоды, code хранится в верхнем регистре. scope - на что действует код:
+1 объект (scope_id - id объекта)
+2 тип модуля (scope_id - id типа модуля)
+3 партнёр, все его объекты (scope_id - id пользователя-владельца)
+kind: 1 скидка в процентах, 2 фиксированная скидка в рублях
+*/
+CREATE TABLE promo_codes (
+    id            TEXT PRIMARY KEY,
+    code          VARCHAR(50) NOT NULL UNIQUE,
+    scope         SMALLINT NOT NULL,
+    scope_id      VARCHAR(100) NOT NULL,
+    kind          SMALLINT NOT NULL,
+    value         INT NOT NULL,
+    min_order     INT NOT NULL DEFAULT 0,
+    valid_from    TIMESTAMP,
+    valid_to      TIMESTAMP,
+    max_uses      INT,
+    max_uses_user INT,
+    is_active     BOOLEAN NOT NULL DEFAULT TRUE,
+    user_id       VARCHAR(100) NOT NULL,
+    created       TIMESTAMP NOT NULL,
+    CHECK (value > 0),
+    CHECK (kind <> 1 OR value <= 100),
+    CHECK (min_order >= 0)
+);
+
+-- применения кода: одно на оформленный заказ
+CREATE TABLE promo_code_uses (
+    id            TEXT PRIMARY KEY,
+    promo_code_id VARCHAR(100) NOT NULL,
+    user_id       VARCHAR(100) NOT NULL,
+    discount      INT NOT NULL,
+    created       TIMESTAMP NOT NULL
+);
+CREATE INDEX promo_code_uses_code_idx ON promo_code_uses (promo_code_id, user_id);
+
+-- price брони - к оплате, discount - сколько из цены позиции списал промокод
+ALTER TABLE orders ADD COLUMN promo_code_id VARCHAR(100);
+ALTER TABLE orders ADD COLUMN discount INT NOT NULL DEFAULT 0;
@@ -0,0 +1,28 @@
+/*
+правила цены типа модуля. Проверяются по убыванию priority, срабатывает первое подошедшее:
+- weekdays - битовая маска дней недели, 1 - понедельник ... 64 - воскресенье, 127 - все дни;
+- time_from / time_to - часы суток, если time_to <= time_from - через полночь;
+- date_from / date_to - даты включительно (сезон, праздник);
+- min_hours / max_hours - длительность брони в часах (полдня, целый день).
+kind: 1 price за каждый час, 2 price за всю бронь
+*/
+CREATE TABLE price_rules (
+    id             TEXT PRIMARY KEY,
+    module_type_id VARCHAR(100) NOT NULL,
+    title          VARCHAR(100) NOT NULL,
+    priority       INT NOT NULL DEFAULT 0,
+    kind           SMALLINT NOT NULL,
+    price          INT NOT NULL,
+    weekdays       SMALLINT NOT NULL DEFAULT 127,
+    time_from      TIME,
+    time_to        TIME,
+    date_from      DATE,
+    date_to        DATE,
+    min_hours      INT,
+    max_hours      INT,
+    created        TIMESTAMP NOT NULL,
+    CHECK (price >= 0),
+    CHECK (weekdays BETWEEN 1 AND 127),
+    CHECK ((time_from IS NULL) = (time_to IS NULL))
+);
+CREATE INDEX price_rules_module_type_idx ON price_rules (module_type_id, priority DESC);
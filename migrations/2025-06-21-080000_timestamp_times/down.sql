@@ -0,0 +1,21 @@
+DROP TRIGGER orders_no_overlap ON orders;
+DROP TRIGGER order_holds_no_overlap ON order_holds;
+DROP INDEX events_place_time_idx;
+DROP INDEX orders_place_time_idx;
+
+ALTER TABLE orders
+    ALTER COLUMN time_start TYPE VARCHAR(100) USING to_char(time_start, 'YYYY-MM-DD HH24:MI:SS'),
+    ALTER COLUMN time_end TYPE VARCHAR(100) USING to_char(time_end, 'YYYY-MM-DD HH24:MI:SS');
+ALTER TABLE order_holds
+    ALTER COLUMN time_start TYPE VARCHAR(100) USING to_char(time_start, 'YYYY-MM-DD HH24:MI:SS'),
+    ALTER COLUMN time_end TYPE VARCHAR(100) USING to_char(time_end, 'YYYY-MM-DD HH24:MI:SS');
+ALTER TABLE events
+    ALTER COLUMN time_start TYPE VARCHAR(100) USING to_char(time_start, 'YYYY-MM-DD HH24:MI:SS'),
+    ALTER COLUMN time_end TYPE VARCHAR(100) USING to_char(time_end, 'YYYY-MM-DD HH24:MI:SS');
+
+CREATE TRIGGER orders_no_overlap
+    BEFORE INSERT OR UPDATE OF object_id, place_id, time_start, time_end ON orders
+    FOR EACH ROW EXECUTE PROCEDURE orders_no_overlap();
+CREATE TRIGGER order_holds_no_overlap
+    BEFORE INSERT OR UPDATE OF object_id, place_id, time_start, time_end ON order_holds
+    FOR EACH ROW EXECUTE PROCEDURE order_holds_no_overlap();
@@ -0,0 +1,30 @@
+/*
+время броней, холдов и событий - настоящие TIMESTAMP вместо строк "%Y-%m-%d %H:%M:%S".
+Строки переносятся в колонки того же имени; таблица times больше не пополняется.
+Колонку из списка UPDATE OF триггера нельзя сменить тип, поэтому триггеры
+пересоздаются, функции остаются прежними.
+*/
+DROP TRIGGER orders_no_overlap ON orders;
+DROP TRIGGER order_holds_no_overlap ON order_holds;
+
+ALTER TABLE orders
+    ALTER COLUMN time_start TYPE TIMESTAMP USING time_start::timestamp,
+    ALTER COLUMN time_end TYPE TIMESTAMP USING time_end::timestamp;
+ALTER TABLE order_holds
+    ALTER COLUMN time_start TYPE TIMESTAMP USING time_start::timestamp,
+    ALTER COLUMN time_end TYPE TIMESTAMP USING time_end::timestamp;
+ALTER TABLE events
+    ALTER COLUMN time_start TYPE TIMESTAMP USING time_start::timestamp,
+    ALTER COLUMN time_end TYPE TIMESTAMP USING time_end::timestamp;
+
+-- orders_object_time_idx и order_holds_object_time_idx перестраиваются сами;
+-- для выборок по объекту и периоду
+CREATE INDEX orders_place_time_idx ON orders (place_id, time_start, time_end);
+CREATE INDEX events_place_time_idx ON events (place_id, time_start, time_end);
+
+CREATE TRIGGER orders_no_overlap
+    BEFORE INSERT OR UPDATE OF object_id, place_id, time_start, time_end ON orders
+    FOR EACH ROW EXECUTE PROCEDURE orders_no_overlap();
+CREATE TRIGGER order_holds_no_overlap
+    BEFORE INSERT OR UPDATE OF object_id, place_id, time_start, time_end ON order_holds
+    FOR EACH ROW EXECUTE PROCEDURE order_holds_no_overlap();
@@ -0,0 +1,21 @@
+UPDATE logs SET created = created + interval '3 hours';
+UPDATE places SET created = created + interval '3 hours';
+
+ALTER TABLE orders DISABLE TRIGGER orders_no_overlap;
+ALTER TABLE order_holds DISABLE TRIGGER order_holds_no_overlap;
+UPDATE orders SET
+    time_start = time_start + make_interval(mins => place_utc_offset(place_id)),
+    time_end = time_end + make_interval(mins => place_utc_offset(place_id));
+UPDATE order_holds SET
+    time_start = time_start + make_interval(mins => place_utc_offset(place_id)),
+    time_end = time_end + make_interval(mins => place_utc_offset(place_id));
+UPDATE events SET
+    time_start = time_start + make_interval(mins => place_utc_offset(place_id)),
+    time_end = time_end + make_interval(mins => place_utc_offset(place_id));
+ALTER TABLE orders ENABLE TRIGGER orders_no_overlap;
+ALTER TABLE order_holds ENABLE TRIGGER order_holds_no_overlap;
+
+DROP FUNCTION place_utc_offset(VARCHAR);
+-- сброшенные пояса регионов не восстанавливаются
+ALTER TABLE regions DROP CONSTRAINT regions_timezone_fk;
+DROP TABLE timezones;
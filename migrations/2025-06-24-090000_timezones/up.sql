@@ -0,0 +1,66 @@
+/*
+часовые пояса. utc_offset - смещение от UTC в минутах; в России нет
+перехода на летнее время, так что смещения хватает.
+Пояс объекта: places.city_id -> cities.region_id -> regions.timezone_id,
+без него - Москва, как считалось раньше.
+*/
+CREATE TABLE timezones (
+    id         SERIAL PRIMARY KEY,
+    name       VARCHAR(100) NOT NULL UNIQUE,
+    utc_offset INT NOT NULL
+);
+INSERT INTO timezones (name, utc_offset) VALUES
+    ('Europe/Kaliningrad', 120),
+    ('Europe/Moscow', 180),
+    ('Europe/Samara', 240),
+    ('Asia/Yekaterinburg', 300),
+    ('Asia/Omsk', 360),
+    ('Asia/Krasnoyarsk', 420),
+    ('Asia/Irkutsk', 480),
+    ('Asia/Yakutsk', 540),
+    ('Asia/Vladivostok', 600),
+    ('Asia/Magadan', 660),
+    ('Asia/Kamchatka', 720);
+
+/*
+regions.timezone_id заполнялся, когда таблицы поясов ещё не было, и его значения
+ни на что не ссылаются: совпадение с новыми id дало бы объекту чужой пояс.
+Пояса регионов сбрасываются до пересчёта броней (без пояса - Москва, как и было)
+и задаются заново через /edit_region/.
+*/
+UPDATE regions SET timezone_id = NULL;
+ALTER TABLE regions ADD CONSTRAINT regions_timezone_fk
+    FOREIGN KEY (timezone_id) REFERENCES timezones (id) ON DELETE SET NULL;
+
+/*
+время броней, холдов и событий приходило в местном времени объекта и так и хранилось,
+теперь в базе UTC. Сдвиг одинаков для всех броней модуля, но построчный UPDATE
+мог бы на время задеть соседние брони, поэтому триггеры пересечений выключаются.
+*/
+CREATE FUNCTION place_utc_offset(place VARCHAR) RETURNS INT AS $$
+    SELECT COALESCE((
+        SELECT t.utc_offset FROM places p
+        JOIN cities c ON c.id = p.city_id
+        JOIN regions r ON r.id = c.region_id
+        JOIN timezones t ON t.id = r.timezone_id
+        WHERE p.id = place
+    ), 180);
+$$ LANGUAGE sql STABLE;
+
+ALTER TABLE orders DISABLE TRIGGER orders_no_overlap;
+ALTER TABLE order_holds DISABLE TRIGGER order_holds_no_overlap;
+UPDATE orders SET
+    time_start = time_start - make_interval(mins => place_utc_offset(place_id)),
+    time_end = time_end - make_interval(mins => place_utc_offset(place_id));
+UPDATE order_holds SET
+    time_start = time_start - make_interval(mins => place_utc_offset(place_id)),
+    time_end = time_end - make_interval(mins => place_utc_offset(place_id));
+UPDATE events SET
+    time_start = time_start - make_interval(mins => place_utc_offset(place_id)),
+    time_end = time_end - make_interval(mins => place_utc_offset(place_id));
+ALTER TABLE orders ENABLE TRIGGER orders_no_overlap;
+ALTER TABLE order_holds ENABLE TRIGGER order_holds_no_overlap;
+
+-- created объектов и логов писался по Москве (UTC + 3 часа)
+UPDATE places SET created = created - interval '3 hours';
+UPDATE logs SET created = created - interval '3 hours';
@@ -0,0 +1,3 @@
+DROP TABLE place_closures;
+DROP TABLE place_seasons;
+DROP TABLE place_hours;
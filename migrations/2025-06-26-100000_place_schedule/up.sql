@@ -0,0 +1,38 @@
+/*
+часы работы объекта по дням недели: weekday 0 - понедельник ... 6 - воскресенье,
+время местное; time_close = 00:00 - до полуночи.
+Нет ни одной строки - объект работает круглосуточно; есть, но нет дня - выходной.
+*/
+CREATE TABLE place_hours (
+    place_id   VARCHAR(100) NOT NULL,
+    weekday    SMALLINT NOT NULL,
+    time_open  TIME NOT NULL,
+    time_close TIME NOT NULL,
+    PRIMARY KEY (place_id, weekday),
+    CHECK (weekday BETWEEN 0 AND 6),
+    CHECK (time_close > time_open OR time_close = '00:00')
+);
+
+-- сезоны, даты местные включительно; без сезонов объект работает круглый год
+CREATE TABLE place_seasons (
+    id         TEXT PRIMARY KEY,
+    place_id   VARCHAR(100) NOT NULL,
+    date_start DATE NOT NULL,
+    date_end   DATE NOT NULL,
+    CHECK (date_end >= date_start)
+);
+CREATE INDEX place_seasons_place_idx ON place_seasons (place_id);
+
+-- разовые закрытия (шторм, праздник), время в UTC
+CREATE TABLE place_closures (
+    id           TEXT PRIMARY KEY,
+    place_id     VARCHAR(100) NOT NULL,
+    time_start   TIMESTAMP NOT NULL,
+    time_end     TIMESTAMP NOT NULL,
+    reason       VARCHAR(500) NOT NULL,
+    is_emergency BOOLEAN NOT NULL DEFAULT FALSE,
+    user_id      VARCHAR(100) NOT NULL,
+    created      TIMESTAMP NOT NULL,
+    CHECK (time_end > time_start)
+);
+CREATE INDEX place_closures_place_time_idx ON place_closures (place_id, time_start, time_end);
@@ -0,0 +1,2 @@
+DROP TABLE slot_template_items;
+DROP TABLE slot_templates;
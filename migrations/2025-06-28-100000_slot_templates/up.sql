@@ -0,0 +1,29 @@
+/*
+шаблоны слотов: на весь объект (module_type_id IS NULL) или на тип модуля,
+шаблон типа модуля важнее шаблона объекта. Нет шаблона - время брони свободное.
+- step_minutes - шаг брони: начало и длительность кратны шагу от полуночи;
+- строки slot_template_items - фиксированные слоты (утро, день, весь день),
+  если они есть, бронь должна совпасть с одним из них, step_minutes не проверяется.
+*/
+CREATE TABLE slot_templates (
+    id             TEXT PRIMARY KEY,
+    place_id       VARCHAR(100) NOT NULL,
+    module_type_id VARCHAR(100),
+    step_minutes   INT,
+    created        TIMESTAMP NOT NULL,
+    CHECK (step_minutes IS NULL OR step_minutes BETWEEN 5 AND 1440)
+);
+CREATE UNIQUE INDEX slot_templates_place_idx ON slot_templates (place_id) WHERE module_type_id IS NULL;
+CREATE UNIQUE INDEX slot_templates_module_type_idx ON slot_templates (module_type_id) WHERE module_type_id IS NOT NULL;
+
+-- фиксированные слоты шаблона, время местное; time_end = 00:00 - до полуночи
+CREATE TABLE slot_template_items (
+    id          TEXT PRIMARY KEY,
+    template_id VARCHAR(100) NOT NULL,
+    title       VARCHAR(100) NOT NULL,
+    time_start  TIME NOT NULL,
+    time_end    TIME NOT NULL,
+    position    SMALLINT NOT NULL DEFAULT 0,
+    CHECK (time_end > time_start OR time_end = '00:00')
+);
+CREATE INDEX slot_template_items_template_idx ON slot_template_items (template_id, position);
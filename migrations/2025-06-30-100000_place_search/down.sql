@@ -0,0 +1,3 @@
+DROP TABLE place_reviews;
+DROP TABLE place_amenities;
+DROP TABLE amenities;
@@ -0,0 +1,24 @@
+-- удобства объектов (парковка, душ, прокат) для фильтра поиска
+CREATE TABLE amenities (
+    id   SERIAL PRIMARY KEY,
+    name VARCHAR(100) NOT NULL UNIQUE
+);
+CREATE TABLE place_amenities (
+    place_id   VARCHAR(100) NOT NULL,
+    amenity_id INT NOT NULL,
+    PRIMARY KEY (place_id, amenity_id)
+);
+CREATE INDEX place_amenities_amenity_idx ON place_amenities (amenity_id);
+
+-- отзывы клиентов по завершённым броням, один на бронь; из них считается рейтинг объекта
+CREATE TABLE place_reviews (
+    id       TEXT PRIMARY KEY,
+    place_id VARCHAR(100) NOT NULL,
+    order_id VARCHAR(100) NOT NULL UNIQUE,
+    user_id  VARCHAR(100) NOT NULL,
+    rating   SMALLINT NOT NULL,
+    text     VARCHAR(2000) NOT NULL DEFAULT '',
+    created  TIMESTAMP NOT NULL,
+    CHECK (rating BETWEEN 1 AND 5)
+);
+CREATE INDEX place_reviews_place_idx ON place_reviews (place_id, created DESC);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS module_types_place_idx;
+DROP FUNCTION place_has_free_time(VARCHAR, DATE);
+DROP FUNCTION place_distance_km(VARCHAR, FLOAT8, FLOAT8);
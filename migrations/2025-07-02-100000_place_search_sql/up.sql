@@ -0,0 +1,92 @@
+/*
+сортировка и фильтр по дате поиска объектов считаются в запросе, чтобы
+страница и total не зависели от того, сколько объектов подошло под фильтры.
+*/
+
+-- расстояние в км от lat / lon до cord объекта ("широта,долгота"); NULL, если cord не координаты
+CREATE FUNCTION place_distance_km(cord VARCHAR, lat FLOAT8, lon FLOAT8) RETURNS FLOAT8 AS $$
+    SELECT 6371.0 * 2.0 * asin(LEAST(1.0, sqrt(
+        power(sin(radians(p.lat - place_distance_km.lat) / 2), 2)
+        + cos(radians(place_distance_km.lat)) * cos(radians(p.lat))
+        * power(sin(radians(p.lon - place_distance_km.lon) / 2), 2)
+    )))
+    FROM (
+        SELECT split_part(cord, ',', 1)::FLOAT8 AS lat, split_part(cord, ',', 2)::FLOAT8 AS lon
+        WHERE cord ~ '^\s*-?[0-9]+(\.[0-9]+)?\s*,\s*-?[0-9]+(\.[0-9]+)?\s*$'
+    ) p
+    WHERE p.lat BETWEEN -90 AND 90 AND p.lon BETWEEN -180 AND 180;
+$$ LANGUAGE sql IMMUTABLE;
+
+/*
+есть ли у объекта модуль со свободным временем в местный день day - то же, что
+Place::get_availability за этот день от текущего момента:
+- день вне сезона или выходной по часам работы закрыт для обычных модулей;
+- время до открытия и после закрытия, закрытия, брони и действующие холды заняты;
+- модуль события свободен только в пересечении дня со временем события, для него важны только закрытия.
+Свободный момент модуля, если он есть, - начало окна или конец одного из занятых промежутков.
+*/
+CREATE FUNCTION place_has_free_time(place VARCHAR, day DATE) RETURNS BOOLEAN AS $$
+    WITH win AS (
+        SELECT day_start,
+               GREATEST(day_start, now() AT TIME ZONE 'UTC') AS w_start,
+               day_start + interval '1 day' AS w_end
+        FROM (SELECT day::TIMESTAMP - make_interval(mins => place_utc_offset(place)) AS day_start) b
+    ),
+    open_day AS (
+        SELECT (NOT EXISTS (SELECT 1 FROM place_seasons WHERE place_id = place)
+                OR EXISTS (SELECT 1 FROM place_seasons WHERE place_id = place AND day BETWEEN date_start AND date_end))
+           AND (NOT EXISTS (SELECT 1 FROM place_hours WHERE place_id = place)
+                OR EXISTS (SELECT 1 FROM place_hours WHERE place_id = place AND weekday = EXTRACT(ISODOW FROM day) - 1))
+           AS is_open
+    ),
+    mods AS (
+        SELECT m.id,
+               m.event_id IS NOT NULL AS is_event,
+               CASE WHEN m.event_id IS NULL THEN w.w_start ELSE GREATEST(w.w_start, e.time_start) END AS s,
+               CASE WHEN m.event_id IS NULL THEN w.w_end ELSE LEAST(w.w_end, e.time_end) END AS f
+        FROM modules m
+        CROSS JOIN win w
+        LEFT JOIN events e ON e.id = m.event_id
+        WHERE m.place_id = place AND m.types = 1 AND (m.event_id IS NULL OR e.id IS NOT NULL)
+    ),
+    taken AS (
+        SELECT o.object_id AS module_id, o.time_start AS s, o.time_end AS f
+        FROM orders o, win w
+        WHERE o.place_id = place AND o.status NOT IN (6, 7, 8)
+          AND o.time_start < w.w_end AND o.time_end > w.w_start
+        UNION ALL
+        SELECT h.object_id, h.time_start, h.time_end
+        FROM order_holds h, win w
+        WHERE h.place_id = place AND h.expires > now() AT TIME ZONE 'UTC'
+          AND h.time_start < w.w_end AND h.time_end > w.w_start
+        UNION ALL
+        SELECT m.id, c.time_start, c.time_end
+        FROM place_closures c, mods m, win w
+        WHERE c.place_id = place AND c.time_start < w.w_end AND c.time_end > w.w_start
+        UNION ALL
+        SELECT m.id, w.day_start, w.day_start + ph.time_open::INTERVAL
+        FROM place_hours ph, mods m, win w
+        WHERE ph.place_id = place AND ph.weekday = EXTRACT(ISODOW FROM day) - 1
+          AND ph.time_open > '00:00' AND NOT m.is_event
+        UNION ALL
+        SELECT m.id, w.day_start + ph.time_close::INTERVAL, w.w_end
+        FROM place_hours ph, mods m, win w
+        WHERE ph.place_id = place AND ph.weekday = EXTRACT(ISODOW FROM day) - 1
+          AND ph.time_close > '00:00' AND NOT m.is_event
+    )
+    SELECT EXISTS (
+        SELECT 1 FROM mods m, open_day od
+        WHERE m.s < m.f AND (m.is_event OR od.is_open)
+          AND EXISTS (
+              SELECT 1 FROM (
+                  SELECT m.s AS t
+                  UNION ALL
+                  SELECT t.f FROM taken t WHERE t.module_id = m.id
+              ) c
+              WHERE c.t >= m.s AND c.t < m.f
+                AND NOT EXISTS (SELECT 1 FROM taken t WHERE t.module_id = m.id AND t.s <= c.t AND t.f > c.t)
+          )
+    );
+$$ LANGUAGE sql STABLE;
+
+CREATE INDEX IF NOT EXISTS module_types_place_idx ON module_types (place_id);
@@ -1,7 +1,8 @@
 use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
-use actix_web::error::Error as ActixError;
+use actix_web::error::{BlockingError, Error as ActixError};
 use diesel::result::Error as DieselError;
+use diesel::r2d2::PoolError;
 use serde::Deserialize;
 use serde_json::json;
 use std::fmt;
@@ -34,6 +35,18 @@ impl From<DieselError> for ApiError {
     }
 }
 
+impl From<PoolError> for ApiError {
+    fn from(error: PoolError) -> ApiError {
+        ApiError::new(500, error.to_string())
+    }
+}
+
+impl From<BlockingError> for ApiError {
+    fn from(_error: BlockingError) -> ApiError {
+        ApiError::new(500, "Thread blocking error")
+    }
+}
+
 impl From<ActixError> for ApiError {
     fn from(error: ActixError) -> ApiError {
         ApiError::new(500, error.to_string())
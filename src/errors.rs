@@ -4,6 +4,7 @@ use actix_web::{
 };
 use derive_more::Display;
 use diesel::result::{DatabaseErrorKind, Error as DBError};
+use diesel::r2d2::PoolError;
 use std::convert::From;
 use serde::{Deserialize, Serialize};
 
@@ -19,12 +20,18 @@ pub enum AuthError {
     #[display(fmt = "NotFound: {}", _0)]
     NotFound(String),
 
-    //#[display(fmt = "ProcessError: {}", _0)]
-    //ProcessError(String),
+    #[display(fmt = "ProcessError: {}", _0)]
+    ProcessError(String),
 
     #[display(fmt = "AuthenticationError: {}", _0)]
     AuthenticationError(String),
 
+    #[display(fmt = "Banned: {}", _0)]
+    Banned(String),
+
+    #[display(fmt = "TooManyAttempts: {}", _0)]
+    TooManyAttempts(String),
+
     #[display(fmt = "GenericError: {}", _0)]
     GenericError(String),
 }
@@ -37,11 +44,15 @@ impl ResponseError for AuthError {
 
             AuthError::NotFound(ref message) => HttpResponse::NotFound().json(message),
 
-            //AuthError::ProcessError(ref message) => HttpResponse::InternalServerError().json(message),
+            AuthError::ProcessError(ref message) => HttpResponse::InternalServerError().json(message),
 
             AuthError::AuthenticationError(ref message) => HttpResponse::Unauthorized().json(message),
 
-            AuthError::DuplicateValue(ref message) => HttpResponse::BadRequest().json(message),
+            AuthError::Banned(ref message) => HttpResponse::Forbidden().json(message),
+
+            AuthError::TooManyAttempts(ref message) => HttpResponse::TooManyRequests().json(message),
+
+            AuthError::DuplicateValue(ref message) => HttpResponse::Conflict().json(message),
 
             AuthError::GenericError(ref message) => HttpResponse::BadRequest().json(message),
         }
@@ -57,14 +68,26 @@ impl From<DBError> for AuthError {
 
                 match kind {
                     DatabaseErrorKind::UniqueViolation => AuthError::DuplicateValue(message),
-                    _ => AuthError::GenericError(message)
+                    _ => AuthError::ProcessError(message)
                 }
             }
-            _ => AuthError::GenericError(String::from("Some database error occured")),
+            _ => AuthError::ProcessError(String::from("Some database error occured")),
         }
     }
 }
 
+impl From<PoolError> for AuthError {
+    fn from(error: PoolError) -> AuthError {
+        AuthError::ProcessError(error.to_string())
+    }
+}
+
+impl From<BlockingError> for AuthError {
+    fn from(_error: BlockingError) -> AuthError {
+        AuthError::ProcessError(String::from("Thread blocking error"))
+    }
+}
+
 
 #[derive(Debug, Display, PartialEq)]
 pub enum Error {
@@ -72,8 +95,23 @@ pub enum Error {
     InternalServerError(String),
     Unauthorized,
     Forbidden,
+    // почта не подтверждена - бронировать нельзя
+    Unverified,
     NotFound(String),
     BlockingError(String),
+    #[display(fmt = "Conflict: {}", _0)]
+    Conflict(String, Vec<BookingConflict>),
+}
+
+/// занятый интервал, из-за которого не прошло бронирование
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BookingConflict {
+    pub object_id:  String,
+    pub title:      String,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_start: chrono::NaiveDateTime,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_end:   chrono::NaiveDateTime,
 }
 
 impl ResponseError for Error {
@@ -91,6 +129,15 @@ impl ResponseError for Error {
                 let error: ErrorResponse = "Forbidden".into();
                 HttpResponse::Forbidden().json(error)
             }
+            Error::Unverified => {
+                let error: ErrorResponse = "Подтвердите почту, чтобы бронировать".into();
+                HttpResponse::Forbidden().json(error)
+            }
+            Error::Conflict(message, conflicts) => {
+                let mut error: ErrorResponse = message.into();
+                error.conflicts = conflicts.clone();
+                HttpResponse::Conflict().json(error)
+            }
             _ => {
                 //error!("Internal server error: {:?}", self);
                 let error: ErrorResponse = "Internal Server Error".into();
@@ -102,13 +149,16 @@ impl ResponseError for Error {
 // User-friendly error messages
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ErrorResponse {
-    pub errors: Vec<String>,
+    pub errors:    Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<BookingConflict>,
 }
 
 impl From<&str> for ErrorResponse {
     fn from(error: &str) -> Self {
         ErrorResponse {
-            errors: vec![error.into()],
+            errors:    vec![error.into()],
+            conflicts: Vec::new(),
         }
     }
 }
@@ -116,14 +166,15 @@ impl From<&str> for ErrorResponse {
 impl From<&String> for ErrorResponse {
     fn from(error: &String) -> Self {
         ErrorResponse {
-            errors: vec![error.into()],
+            errors:    vec![error.into()],
+            conflicts: Vec::new(),
         }
     }
 }
 
 impl From<Vec<String>> for ErrorResponse {
     fn from(error: Vec<String>) -> Self {
-        ErrorResponse { errors: error }
+        ErrorResponse { errors: error, conflicts: Vec::new() }
     }
 }
 
@@ -138,6 +189,10 @@ impl From<DBError> for Error {
                     let message = info.details().unwrap_or_else(|| info.message()).to_string();
                     return Error::BadRequest(message);
                 }
+                // триггер orders_no_overlap: бронь пересеклась с параллельной вставкой
+                if info.constraint_name() == Some("orders_no_overlap") {
+                    return Error::Conflict(info.message().to_string(), Vec::new());
+                }
                 Error::InternalServerError("Unknown database error".into())
             }
             DBError::NotFound => Error::NotFound("Record not found".into()),
@@ -153,6 +208,12 @@ impl From<BlockingError> for Error {
     }
 }
 
+impl From<PoolError> for Error {
+    fn from(error: PoolError) -> Error {
+        Error::InternalServerError(error.to_string())
+    }
+}
+
 impl From<ActixError> for Error {
     fn from(error: ActixError) -> Error {
         Error::InternalServerError(error.to_string())
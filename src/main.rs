@@ -28,12 +28,23 @@ mod views;
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
+    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|a| a.as_str()) == Some("create_superuser") {
+        return utils::create_superuser_command(&args[2..]);
+    }
+    utils::check_payment_config();
+    let pool = utils::create_pool();
+    utils::bootstrap_superuser_from_env(&pool);
+    utils::spawn_hold_sweeper(&pool);
+
     //let server = websocket::Server::new().start();
     let secret_key = Key::generate();
 
     HttpServer::new(move || {
         let cors = Cors::permissive();
         App::new() 
+            .app_data(web::Data::new(pool.clone()))
             .wrap(Compress::default())
             .wrap(cors)
             .wrap(
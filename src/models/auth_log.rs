@@ -0,0 +1,299 @@
+use crate::schema;
+use crate::schema::{
+    auth_attempts,
+    auth_lockouts,
+};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    OptionalExtension,
+    Connection,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use crate::errors::Error;
+
+
+// после стольких неудач подряд ключ блокируется
+const EMAIL_MAX_FAILURES: i32 = 5;
+const IP_MAX_FAILURES: i32 = 20;
+// неудачи старше окна не считаются
+const FAILURES_WINDOW_MINUTES: i64 = 15;
+// блокировка растёт вдвое с каждой лишней неудачей, но не дольше суток
+const MAX_LOCKOUT_MINUTES: i64 = 24 * 60;
+
+
+#[derive(Debug, Queryable, Serialize, Identifiable, Insertable)]
+#[table_name="auth_attempts"]
+pub struct AuthAttempt {
+    pub id:         String,
+    pub email:      String,
+    pub user_id:    Option<String>,
+    pub ip:         String,
+    pub user_agent: String,
+    pub success:    bool,
+    pub created:    chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct AuthAttemptsParams {
+    pub email:   Option<String>,
+    pub ip:      Option<String>,
+    pub success: Option<bool>,
+    pub page:    Option<i64>,
+    pub limit:   Option<i64>,
+}
+
+impl AuthAttempt {
+    pub fn create (
+        _connection: &PgConnection,
+        email:      &String,
+        user_id:    Option<String>,
+        ip:         &String,
+        user_agent: &String,
+        success:    bool,
+    ) -> Result<(), Error> {
+        let new_attempt = AuthAttempt {
+            id:         uuid::Uuid::new_v4().to_string(),
+            email:      email.to_lowercase().chars().take(100).collect(),
+            user_id:    user_id,
+            ip:         ip.chars().take(100).collect(),
+            user_agent: user_agent.chars().take(500).collect(),
+            success:    success,
+            created:    chrono::Utc::now().naive_utc(),
+        };
+        diesel::insert_into(schema::auth_attempts::table)
+            .values(&new_attempt)
+            .execute(_connection)?;
+        Ok(())
+    }
+
+    pub fn get_list(_connection: &PgConnection, params: &AuthAttemptsParams) -> Result<Vec<AuthAttempt>, Error> {
+        let limit = crate::utils::get_limit(params.limit, 50);
+        let page = params.page.unwrap_or(1).max(1);
+
+        let mut query = schema::auth_attempts::table.into_boxed();
+        if let Some(email) = &params.email {
+            query = query.filter(schema::auth_attempts::email.eq(email.to_lowercase()));
+        }
+        if let Some(ip) = &params.ip {
+            query = query.filter(schema::auth_attempts::ip.eq(ip));
+        }
+        if let Some(success) = params.success {
+            query = query.filter(schema::auth_attempts::success.eq(success));
+        }
+        return Ok(query
+            .order(schema::auth_attempts::created.desc())
+            .limit(limit)
+            .offset((page - 1) * limit)
+            .load::<AuthAttempt>(_connection)?);
+    }
+}
+
+
+#[derive(Debug, Queryable, Serialize, Insertable)]
+#[table_name="auth_lockouts"]
+pub struct AuthLockout {
+    pub key:          String,
+    pub failures:     i32,
+    pub locked_until: Option<chrono::NaiveDateTime>,
+    pub updated:      chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct ClearLockoutJson {
+    pub email: Option<String>,
+    pub ip:    Option<String>,
+}
+
+impl AuthLockout {
+    pub fn email_key(email: &String) -> String {
+        format!("email:{}", email.to_lowercase().chars().take(100).collect::<String>())
+    }
+    pub fn ip_key(ip: &String) -> String {
+        format!("ip:{}", ip.chars().take(100).collect::<String>())
+    }
+
+    /// строка счётчика, заблокированная до конца транзакции; нет строки - заводим пустую
+    fn lock_row(_connection: &PgConnection, key: &String, now: chrono::NaiveDateTime) -> Result<AuthLockout, Error> {
+        diesel::insert_into(schema::auth_lockouts::table)
+            .values(&AuthLockout {
+                key:          key.clone(),
+                failures:     0,
+                locked_until: None,
+                updated:      now,
+            })
+            .on_conflict(schema::auth_lockouts::key)
+            .do_nothing()
+            .execute(_connection)?;
+        return Ok(schema::auth_lockouts::table
+            .filter(schema::auth_lockouts::key.eq(key))
+            .for_update()
+            .first::<AuthLockout>(_connection)?);
+    }
+
+    /// Попытка входа заранее считается неудачей почты и адреса, до проверки пароля.
+    /// Строки счётчиков блокируются, поэтому параллельные попытки считаются по одной
+    /// и пачка одновременных подборов упирается в лимит так же, как последовательные.
+    /// Возвращает, до какого момента вход заблокирован (самая поздняя из блокировок);
+    /// при блокировке попытка не считается.
+    pub fn reserve_attempt(_connection: &PgConnection, email: &String, ip: &String) -> Result<Option<chrono::NaiveDateTime>, Error> {
+        let now = chrono::Utc::now().naive_utc();
+        _connection.transaction(|| {
+            // порядок ключей постоянный ("email:" < "ip:"), чтобы попытки не ждали друг друга по кругу
+            let email_row = AuthLockout::lock_row(_connection, &AuthLockout::email_key(email), now)?;
+            let ip_row = AuthLockout::lock_row(_connection, &AuthLockout::ip_key(ip), now)?;
+            let locked_until = vec![email_row.locked_until, ip_row.locked_until]
+                .into_iter()
+                .flatten()
+                .filter(|until| *until > now)
+                .max();
+            if locked_until.is_some() {
+                return Ok(locked_until);
+            }
+            AuthLockout::add_failure(_connection, email_row, EMAIL_MAX_FAILURES, now)?;
+            AuthLockout::add_failure(_connection, ip_row, IP_MAX_FAILURES, now)?;
+            Ok(None)
+        })
+    }
+
+    /// Удачный вход сбрасывает счётчик почты; с адреса снимается только неудача,
+    /// зарезервированная этой попыткой, иначе перебор с одного адреса можно "разбавлять" своим аккаунтом
+    pub fn register_success(_connection: &PgConnection, email: &String, ip: &String) -> Result<(), Error> {
+        AuthLockout::clear(_connection, &AuthLockout::email_key(email))?;
+        AuthLockout::release(_connection, &AuthLockout::ip_key(ip), IP_MAX_FAILURES)
+    }
+
+    /// попытка закончилась не подбором (например, ошибкой базы) - неудачу не считаем
+    pub fn release_attempt(_connection: &PgConnection, email: &String, ip: &String) -> Result<(), Error> {
+        AuthLockout::release(_connection, &AuthLockout::email_key(email), EMAIL_MAX_FAILURES)?;
+        AuthLockout::release(_connection, &AuthLockout::ip_key(ip), IP_MAX_FAILURES)
+    }
+
+    fn add_failure(_connection: &PgConnection, prev: AuthLockout, max_failures: i32, now: chrono::NaiveDateTime) -> Result<(), Error> {
+        // пока идёт окно (или недавно закончилась блокировка) неудачи копятся
+        let window_start = now - chrono::Duration::minutes(FAILURES_WINDOW_MINUTES);
+        let failures = match prev.updated > window_start
+            || prev.locked_until.map_or(false, |until| until > window_start) {
+            true => prev.failures + 1,
+            false => 1,
+        };
+        let locked_until = match failures >= max_failures {
+            true => {
+                let power = (failures - max_failures).min(20) as u32;
+                let minutes = 2i64.pow(power).min(MAX_LOCKOUT_MINUTES);
+                Some(now + chrono::Duration::minutes(minutes))
+            },
+            false => None,
+        };
+        diesel::update(schema::auth_lockouts::table.filter(schema::auth_lockouts::key.eq(&prev.key)))
+            .set((
+                schema::auth_lockouts::failures.eq(failures),
+                schema::auth_lockouts::locked_until.eq(locked_until),
+                schema::auth_lockouts::updated.eq(now),
+            ))
+            .execute(_connection)?;
+        Ok(())
+    }
+
+    /// снимает одну зарезервированную неудачу; ниже лимита блокировка тоже снимается
+    fn release(_connection: &PgConnection, key: &String, max_failures: i32) -> Result<(), Error> {
+        _connection.transaction(|| {
+            let row = match schema::auth_lockouts::table
+                .filter(schema::auth_lockouts::key.eq(key))
+                .for_update()
+                .first::<AuthLockout>(_connection)
+                .optional()? {
+                    Some(row) => row,
+                    None => return Ok(()),
+            };
+            let failures = (row.failures - 1).max(0);
+            diesel::update(schema::auth_lockouts::table.filter(schema::auth_lockouts::key.eq(key)))
+                .set((
+                    schema::auth_lockouts::failures.eq(failures),
+                    schema::auth_lockouts::locked_until.eq(if failures >= max_failures { row.locked_until } else { None }),
+                ))
+                .execute(_connection)?;
+            Ok(())
+        })
+    }
+
+    pub fn clear(_connection: &PgConnection, key: &String) -> Result<(), Error> {
+        diesel::delete (
+                schema::auth_lockouts::table
+                    .filter(schema::auth_lockouts::key.eq(key))
+            )
+            .execute(_connection)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// база с применёнными миграциями из TEST_DATABASE_URL; без неё тест пропускается
+    fn test_url() -> Option<String> {
+        std::env::var("TEST_DATABASE_URL").ok()
+    }
+    fn connect(url: &String) -> PgConnection {
+        PgConnection::establish(url).expect("TEST_DATABASE_URL is not reachable")
+    }
+
+    #[test]
+    fn parallel_attempts_are_counted_one_by_one() {
+        let url = match test_url() {
+            Some(url) => url,
+            None => return,
+        };
+        // попытки идут из разных соединений, поэтому без test_transaction - ключи уникальные и чистятся в конце
+        let email = format!("{}@lockout.test", uuid::Uuid::new_v4());
+        let ip = uuid::Uuid::new_v4().to_string();
+        let threads: Vec<_> = (0..3 * EMAIL_MAX_FAILURES)
+            .map(|_| {
+                let (url, email, ip) = (url.clone(), email.clone(), ip.clone());
+                std::thread::spawn(move || AuthLockout::reserve_attempt(&connect(&url), &email, &ip).unwrap())
+            })
+            .collect();
+        let allowed = threads.into_iter()
+            .map(|t| t.join().unwrap())
+            .filter(|locked_until| locked_until.is_none())
+            .count();
+
+        let _connection = connect(&url);
+        let _row = schema::auth_lockouts::table
+            .filter(schema::auth_lockouts::key.eq(AuthLockout::email_key(&email)))
+            .first::<AuthLockout>(&_connection)
+            .unwrap();
+        AuthLockout::clear(&_connection, &AuthLockout::email_key(&email)).unwrap();
+        AuthLockout::clear(&_connection, &AuthLockout::ip_key(&ip)).unwrap();
+        assert_eq!(allowed, EMAIL_MAX_FAILURES as usize);
+        assert_eq!(_row.failures, EMAIL_MAX_FAILURES);
+        assert!(_row.locked_until.is_some());
+    }
+
+    #[test]
+    fn released_attempts_do_not_lock() {
+        let url = match test_url() {
+            Some(url) => url,
+            None => return,
+        };
+        let _connection = connect(&url);
+        _connection.test_transaction::<_, Error, _>(|| {
+            let email = String::from("release@lockout.test");
+            let ip = String::from("192.0.2.1");
+            for _ in 0..2 * EMAIL_MAX_FAILURES {
+                assert_eq!(AuthLockout::reserve_attempt(&_connection, &email, &ip)?, None);
+                AuthLockout::release_attempt(&_connection, &email, &ip)?;
+            }
+            for _ in 0..EMAIL_MAX_FAILURES {
+                assert_eq!(AuthLockout::reserve_attempt(&_connection, &email, &ip)?, None);
+            }
+            assert!(AuthLockout::reserve_attempt(&_connection, &email, &ip)?.is_some());
+            Ok(())
+        });
+    }
+}
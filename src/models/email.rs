@@ -1,10 +1,11 @@
 use crate::api_error::ApiError;
-use crate::schema::email_verification_token;
+use crate::schema::{email_verification_token, password_reset_tokens};
 use chrono::{NaiveDateTime, Utc, Duration};
 use diesel::prelude::*;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use crate::utils::establish_connection;
+use crate::utils::get_mailer;
+use crate::vars;
 
 
 #[derive(Deserialize, Clone)]
@@ -23,19 +24,15 @@ pub struct EmailVerificationToken {
 }
 
 impl EmailVerificationToken {
-    pub fn find(id: &Vec<u8>) -> Result<Self, ApiError> {
-        let conn = establish_connection();
-
+    pub fn find(conn: &PgConnection, id: &Vec<u8>) -> Result<Self, ApiError> {
         let token = email_verification_token::table
             .filter(email_verification_token::id.eq(id))
-            .first(&conn)?;
+            .first(conn)?;
 
         Ok(token)
     }
 
-    pub fn create(body: EmailVerificationTokenMessage) -> Result<Self, ApiError> {
-        let conn = establish_connection();
-
+    pub fn create(conn: &PgConnection, body: EmailVerificationTokenMessage) -> Result<Self, ApiError> {
         let id = rand::thread_rng().gen::<[u8; 32]>().to_vec();
         let email = body.email;
         let created_at = Utc::now().naive_utc();
@@ -51,20 +48,111 @@ impl EmailVerificationToken {
                 email_verification_token::created_at.eq(&token.created_at),
                 email_verification_token::expires_at.eq(&token.expires_at),
             ))
-            .get_result(&conn)?;
+            .get_result(conn)?;
 
         Ok(token)
     }
 
-    pub fn delete(id: &Vec<u8>) -> Result<usize, ApiError> {
-        let conn = establish_connection();
-
+    pub fn delete(conn: &PgConnection, id: &Vec<u8>) -> Result<usize, ApiError> {
         let res = diesel::delete(
                 email_verification_token::table
                     .filter(email_verification_token::id.eq(id))
             )
-            .execute(&conn)?;
+            .execute(conn)?;
+
+        Ok(res)
+    }
+
+    /// выпускаем токен и отправляем письмо со ссылкой подтверждения
+    pub fn send(conn: &PgConnection, email: &String) -> Result<(), ApiError> {
+        let token = EmailVerificationToken::create(conn, EmailVerificationTokenMessage {
+            id:    None,
+            email: email.clone(),
+        })?;
+        let link = format!("{}/verify_email/{}/", vars::site_url(), hex::encode(&token.id));
+
+        get_mailer()
+            .send(
+                email,
+                "Подтверждение почты",
+                &format!("Для подтверждения почты перейдите по ссылке: {}", link),
+            )
+            .map_err(|e| ApiError::new(500, e.to_string()))
+    }
+}
+
+
+#[derive(Deserialize, Serialize, Queryable, Insertable)]
+#[table_name = "password_reset_tokens"]
+pub struct PasswordResetToken {
+    pub id: Vec<u8>,
+    pub user_id: String,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+impl PasswordResetToken {
+    pub fn find(conn: &PgConnection, id: &Vec<u8>) -> Result<Self, ApiError> {
+        let token = password_reset_tokens::table
+            .filter(password_reset_tokens::id.eq(id))
+            .first(conn)?;
+
+        Ok(token)
+    }
+
+    /// новый токен заменяет ранее выданный этому пользователю
+    pub fn create(conn: &PgConnection, user_id: &String) -> Result<Self, ApiError> {
+        let id = rand::thread_rng().gen::<[u8; 32]>().to_vec();
+        let created_at = Utc::now().naive_utc();
+        let expires_at = created_at + Duration::hours(1);
+        let token = PasswordResetToken { id, user_id: user_id.clone(), expires_at, created_at };
+
+        let token = diesel::insert_into(password_reset_tokens::table)
+            .values(&token)
+            .on_conflict(password_reset_tokens::user_id)
+            .do_update()
+            .set((
+                password_reset_tokens::id.eq(&token.id),
+                password_reset_tokens::created_at.eq(&token.created_at),
+                password_reset_tokens::expires_at.eq(&token.expires_at),
+            ))
+            .get_result(conn)?;
+
+        Ok(token)
+    }
+
+    pub fn delete(conn: &PgConnection, id: &Vec<u8>) -> Result<usize, ApiError> {
+        let res = diesel::delete(
+                password_reset_tokens::table
+                    .filter(password_reset_tokens::id.eq(id))
+            )
+            .execute(conn)?;
 
         Ok(res)
     }
-}
\ No newline at end of file
+
+    /// забирает токен: удаляет его и возвращает удалённую строку.
+    /// Из двух параллельных запросов строку получит только один, второй - 404
+    pub fn take(conn: &PgConnection, id: &Vec<u8>) -> Result<Self, ApiError> {
+        let token = diesel::delete(
+                password_reset_tokens::table
+                    .filter(password_reset_tokens::id.eq(id))
+            )
+            .get_result(conn)?;
+
+        Ok(token)
+    }
+
+    pub fn send(conn: &PgConnection, user_id: &String, email: &String) -> Result<(), ApiError> {
+        let token = PasswordResetToken::create(conn, user_id)?;
+        let link = format!("{}/reset_password/{}/", vars::site_url(), hex::encode(&token.id));
+
+        get_mailer()
+            .send(
+                email,
+                "Восстановление пароля",
+                &format!("Для смены пароля перейдите по ссылке: {}\nСсылка действует один час.", link),
+            )
+            .map_err(|e| ApiError::new(500, e.to_string()))
+    }
+}
@@ -0,0 +1,178 @@
+use crate::schema;
+use crate::schema::order_holds;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    Connection,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use crate::errors::Error;
+use crate::models::PlaceTz;
+use crate::models::{Order, OrderJson};
+use crate::vars;
+
+
+/// Временный холд модуля, пока пользователь оформляет заказ.
+/// Занимает время модуля для остальных до expires.
+#[derive(Debug, Queryable, Serialize, Deserialize, Identifiable, Insertable)]
+#[table_name="order_holds"]
+pub struct OrderHold {
+    pub id:         String,
+    pub user_id:    String,
+    pub place_id:   String,
+    pub object_id:  String,
+    pub event_id:   Option<String>,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_start: chrono::NaiveDateTime,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_end:   chrono::NaiveDateTime,
+    pub expires:    chrono::NaiveDateTime,
+    pub created:    chrono::NaiveDateTime,
+}
+
+impl OrderHold {
+    pub fn get_for_user(_connection: &PgConnection, user_id: &String) -> Result<Vec<OrderHold>, Error> {
+        return Ok(schema::order_holds::table
+            .filter(schema::order_holds::user_id.eq(user_id))
+            .filter(schema::order_holds::expires.gt(chrono::Utc::now().naive_utc()))
+            .order(schema::order_holds::created.asc())
+            .load::<OrderHold>(_connection)?);
+    }
+    fn get_own(_connection: &PgConnection, id: &String, user_id: &String) -> Result<OrderHold, Error> {
+        schema::order_holds::table
+            .filter(schema::order_holds::id.eq(id))
+            .filter(schema::order_holds::user_id.eq(user_id))
+            .filter(schema::order_holds::expires.gt(chrono::Utc::now().naive_utc()))
+            .first::<OrderHold>(_connection)
+            .map_err(|_| Error::NotFound(String::from("Холд не найден или истёк")))
+    }
+
+    /// холды на все позиции сразу; занятое время возвращается как 409
+    pub fn create(_connection: &PgConnection, user_id: &String, form: &Vec<OrderJson>) -> Result<Vec<OrderHold>, Error> {
+        _connection.transaction(|| {
+            let mut object_ids: Vec<&String> = form.iter().map(|i| &i.object_id).collect();
+            object_ids.sort();
+            object_ids.dedup();
+            for object_id in object_ids {
+                Order::lock_object(_connection, object_id)?;
+            }
+            let conflicts = Order::find_conflicts(_connection, form, None)?;
+            if !conflicts.is_empty() {
+                return Err(Error::Conflict(String::from("Выбранное время уже занято"), conflicts));
+            }
+
+            let now = chrono::Utc::now().naive_utc();
+            let mut stack = Vec::new();
+            for i in form.iter() {
+                let (time_start, time_end) = Order::check_item(_connection, i)?;
+                let new_hold = OrderHold {
+                    id:         uuid::Uuid::new_v4().to_string(),
+                    user_id:    user_id.clone(),
+                    place_id:   i.place_id.clone(),
+                    object_id:  i.object_id.clone(),
+                    event_id:   i.event_id.clone(),
+                    time_start: time_start,
+                    time_end:   time_end,
+                    expires:    now + chrono::Duration::minutes(vars::hold_minutes()),
+                    created:    now,
+                };
+                diesel::insert_into(schema::order_holds::table)
+                    .values(&new_hold)
+                    .execute(_connection)?;
+                stack.push(new_hold);
+            }
+            Ok(stack)
+        })
+    }
+
+    /// продлевает холд ещё на hold_minutes, но не дальше hold_max_minutes от создания
+    pub fn extend(_connection: &PgConnection, id: &String, user_id: &String) -> Result<OrderHold, Error> {
+        let _hold = OrderHold::get_own(_connection, id, user_id)?;
+        let limit = _hold.created + chrono::Duration::minutes(vars::hold_max_minutes());
+        let expires = std::cmp::min(
+            chrono::Utc::now().naive_utc() + chrono::Duration::minutes(vars::hold_minutes()),
+            limit,
+        );
+        if expires <= _hold.expires {
+            return Err(Error::BadRequest(String::from("Холд больше нельзя продлить")));
+        }
+        return Ok(diesel::update(&_hold)
+            .set(schema::order_holds::expires.eq(expires))
+            .get_result::<OrderHold>(_connection)?);
+    }
+
+    pub fn release(_connection: &PgConnection, id: &String, user_id: &String) -> Result<(), Error> {
+        diesel::delete (
+            schema::order_holds::table
+                .filter(schema::order_holds::id.eq(id))
+                .filter(schema::order_holds::user_id.eq(user_id))
+        )
+        .execute(_connection)?;
+        Ok(())
+    }
+
+    /// снимает холды пользователя, перекрытые оформленными позициями
+    pub fn release_covered(_connection: &PgConnection, user_id: &String, form: &Vec<OrderJson>) -> Result<(), Error> {
+        for i in form.iter() {
+            let tz = PlaceTz::for_place(_connection, &i.place_id)?;
+            let time_start = tz.parse(&i.time_start, "time_start")?;
+            let time_end = tz.parse(&i.time_end, "time_end")?;
+            diesel::delete (
+                schema::order_holds::table
+                    .filter(schema::order_holds::user_id.eq(user_id))
+                    .filter(schema::order_holds::object_id.eq(&i.object_id))
+                    .filter(schema::order_holds::time_start.lt(time_end))
+                    .filter(schema::order_holds::time_end.gt(time_start))
+            )
+            .execute(_connection)?;
+        }
+        Ok(())
+    }
+
+    /// позиции заказа из действующих холдов пользователя
+    pub fn to_order_json(_connection: &PgConnection, user_id: &String) -> Result<Vec<OrderJson>, Error> {
+        let _holds = OrderHold::get_for_user(_connection, user_id)?;
+        if _holds.is_empty() {
+            return Err(Error::BadRequest(String::from("Нет действующих холдов")));
+        }
+        let mut stack = Vec::new();
+        for i in _holds {
+            let title = schema::modules::table
+                .filter(schema::modules::id.eq(&i.object_id))
+                .select(schema::modules::title)
+                .first::<String>(_connection)?;
+            let tz = PlaceTz::for_place(_connection, &i.place_id)?;
+            stack.push(OrderJson {
+                title:      title,
+                place_id:   i.place_id,
+                object_id:  i.object_id,
+                event_id:   i.event_id,
+                price:      None,
+                time_start: tz.format(i.time_start),
+                time_end:   tz.format(i.time_end),
+            });
+        }
+        Ok(stack)
+    }
+
+    /// время холда из UTC в местное время объекта - перед отдачей в API
+    pub fn localize(mut self, _connection: &PgConnection) -> Result<OrderHold, Error> {
+        let tz = PlaceTz::for_place(_connection, &self.place_id)?;
+        self.time_start = tz.to_local(self.time_start);
+        self.time_end = tz.to_local(self.time_end);
+        Ok(self)
+    }
+
+    /// для фоновой задачи: удаляет истёкшие холды
+    pub fn delete_expired(_connection: &PgConnection) -> Result<usize, Error> {
+        return Ok(diesel::delete (
+            schema::order_holds::table
+                .filter(schema::order_holds::expires.le(chrono::Utc::now().naive_utc()))
+        )
+        .execute(_connection)?);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::schema;
+use crate::schema::idempotency_keys;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    Connection,
+};
+use serde::{Serialize, de::DeserializeOwned};
+use diesel::PgConnection;
+use sha2::{Digest, Sha256};
+use crate::errors::Error;
+
+
+#[derive(Debug, Queryable, Insertable)]
+#[table_name="idempotency_keys"]
+pub struct IdempotencyKey {
+    pub key:          String,
+    pub user_id:      String,
+    pub request_hash: String,
+    pub response:     serde_json::Value,
+    pub created:      chrono::NaiveDateTime,
+}
+
+impl IdempotencyKey {
+    /// сколько часов хранится ответ по ключу
+    const TTL_HOURS: i64 = 24;
+
+    pub fn request_hash<T: Serialize>(body: &T) -> Result<String, Error> {
+        let body = serde_json::to_vec(body)
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        Ok(hex::encode(Sha256::digest(&body)))
+    }
+
+    /// Выполняет f один раз на пару (пользователь, ключ).
+    /// Повтор с тем же телом получает сохранённый ответ, с другим телом - 400.
+    /// Параллельные повторы ждут первый запрос на advisory-блокировке.
+    pub fn run<T, F>(
+        _connection:  &PgConnection,
+        user_id:      &String,
+        key:          &String,
+        request_hash: String,
+        f:            F,
+    ) -> Result<T, Error>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Result<T, Error>,
+    {
+        if key.is_empty() || key.chars().count() > 200 {
+            return Err(Error::BadRequest(String::from("Idempotency-Key должен быть от 1 до 200 символов")));
+        }
+        _connection.transaction(|| {
+            diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+                .bind::<diesel::sql_types::Text, _>(format!("idempotency:{}:{}", user_id, key))
+                .execute(_connection)?;
+
+            let expired = chrono::Utc::now().naive_utc() - chrono::Duration::hours(Self::TTL_HOURS);
+            diesel::delete (
+                schema::idempotency_keys::table
+                    .filter(schema::idempotency_keys::created.lt(expired))
+            )
+            .execute(_connection)?;
+
+            let saved = schema::idempotency_keys::table
+                .filter(schema::idempotency_keys::user_id.eq(user_id))
+                .filter(schema::idempotency_keys::key.eq(key))
+                .first::<IdempotencyKey>(_connection)
+                .ok();
+            if let Some(saved) = saved {
+                if saved.request_hash != request_hash {
+                    return Err(Error::BadRequest(String::from("Idempotency-Key уже использован для другого запроса")));
+                }
+                return serde_json::from_value(saved.response)
+                    .map_err(|e| Error::InternalServerError(e.to_string()));
+            }
+
+            let result = f()?;
+            let new_key = IdempotencyKey {
+                key:          key.clone(),
+                user_id:      user_id.clone(),
+                request_hash: request_hash,
+                response:     serde_json::to_value(&result)
+                    .map_err(|e| Error::InternalServerError(e.to_string()))?,
+                created:      chrono::Utc::now().naive_utc(),
+            };
+            diesel::insert_into(schema::idempotency_keys::table)
+                .values(&new_key)
+                .execute(_connection)?;
+            Ok(result)
+        })
+    }
+}
@@ -2,10 +2,40 @@ mod user;
 mod places;
 mod other;
 mod email;
+mod session;
+mod role;
+mod auth_log;
+mod order_status;
+mod pricing;
+mod idempotency;
+mod hold;
+mod payment;
+mod refund;
+mod promo;
+mod price_rule;
+mod timezone;
+mod schedule;
+mod slot;
+mod search;
 
 pub use self::{
     user::*,
     places::*,
     other::*,
     email::*,
+    session::*,
+    role::*,
+    auth_log::*,
+    order_status::*,
+    pricing::*,
+    idempotency::*,
+    hold::*,
+    payment::*,
+    refund::*,
+    promo::*,
+    price_rule::*,
+    timezone::*,
+    schedule::*,
+    slot::*,
+    search::*,
 };
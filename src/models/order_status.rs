@@ -0,0 +1,137 @@
+use crate::schema;
+use crate::schema::order_status_logs;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use crate::errors::Error;
+
+
+/*
+Статус брони, хранится в orders.status:
+1 ожидает подтверждения
+2 подтверждена
+3 оплачена
+4 клиент пришёл
+5 завершена
+6 отменена клиентом
+7 отменена объектом
+8 неявка
+6, 7, 8 освобождают время модуля
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    Pending,
+    Confirmed,
+    Paid,
+    CheckedIn,
+    Completed,
+    CancelledByUser,
+    CancelledByPlace,
+    NoShow,
+}
+
+impl OrderStatus {
+    pub fn from_code(code: i16) -> OrderStatus {
+        match code {
+            2 => OrderStatus::Confirmed,
+            3 => OrderStatus::Paid,
+            4 => OrderStatus::CheckedIn,
+            5 => OrderStatus::Completed,
+            6 => OrderStatus::CancelledByUser,
+            7 => OrderStatus::CancelledByPlace,
+            8 => OrderStatus::NoShow,
+            _ => OrderStatus::Pending,
+        }
+    }
+    pub fn code(&self) -> i16 {
+        match self {
+            OrderStatus::Pending          => 1,
+            OrderStatus::Confirmed        => 2,
+            OrderStatus::Paid             => 3,
+            OrderStatus::CheckedIn        => 4,
+            OrderStatus::Completed        => 5,
+            OrderStatus::CancelledByUser  => 6,
+            OrderStatus::CancelledByPlace => 7,
+            OrderStatus::NoShow           => 8,
+        }
+    }
+    /// коды статусов, в которых бронь не занимает время модуля
+    pub fn releasing_codes() -> Vec<i16> {
+        vec![
+            OrderStatus::CancelledByUser.code(),
+            OrderStatus::CancelledByPlace.code(),
+            OrderStatus::NoShow.code(),
+        ]
+    }
+
+    pub fn can_become(&self, to: OrderStatus) -> bool {
+        match (self, to) {
+            (OrderStatus::Pending, OrderStatus::Confirmed)
+            | (OrderStatus::Pending, OrderStatus::Paid)
+            | (OrderStatus::Confirmed, OrderStatus::Paid)
+            | (OrderStatus::Confirmed, OrderStatus::CheckedIn)
+            | (OrderStatus::Paid, OrderStatus::CheckedIn)
+            | (OrderStatus::CheckedIn, OrderStatus::Completed) => true,
+            (OrderStatus::Pending, OrderStatus::CancelledByUser)
+            | (OrderStatus::Confirmed, OrderStatus::CancelledByUser)
+            | (OrderStatus::Paid, OrderStatus::CancelledByUser) => true,
+            (OrderStatus::Pending, OrderStatus::CancelledByPlace)
+            | (OrderStatus::Confirmed, OrderStatus::CancelledByPlace)
+            | (OrderStatus::Paid, OrderStatus::CancelledByPlace) => true,
+            (OrderStatus::Confirmed, OrderStatus::NoShow)
+            | (OrderStatus::Paid, OrderStatus::NoShow) => true,
+            _ => false,
+        }
+    }
+    /// отменить бронь от своего имени может только клиент,
+    /// остальные переходы делает владелец или менеджер объекта
+    pub fn by_customer(&self) -> bool {
+        *self == OrderStatus::CancelledByUser
+    }
+}
+
+#[derive(Debug, Queryable, Serialize, Deserialize, Insertable)]
+#[table_name="order_status_logs"]
+pub struct OrderStatusLog {
+    pub id:          String,
+    pub order_id:    String,
+    pub user_id:     String,
+    pub status_from: i16,
+    pub status_to:   i16,
+    pub created:     chrono::NaiveDateTime,
+}
+
+impl OrderStatusLog {
+    pub fn create (
+        _connection: &PgConnection,
+        order_id:    &String,
+        user_id:     &String,
+        status_from: OrderStatus,
+        status_to:   OrderStatus,
+    ) -> Result<(), Error> {
+        let new_log = OrderStatusLog {
+            id:          uuid::Uuid::new_v4().to_string(),
+            order_id:    order_id.clone(),
+            user_id:     user_id.clone(),
+            status_from: status_from.code(),
+            status_to:   status_to.code(),
+            created:     chrono::Utc::now().naive_utc(),
+        };
+        diesel::insert_into(schema::order_status_logs::table)
+            .values(&new_log)
+            .execute(_connection)?;
+        Ok(())
+    }
+    pub fn get_for_order(_connection: &PgConnection, order_id: &String) -> Result<Vec<OrderStatusLog>, Error> {
+        return Ok(schema::order_status_logs::table
+            .filter(schema::order_status_logs::order_id.eq(order_id))
+            .order(schema::order_status_logs::created.asc())
+            .load::<OrderStatusLog>(_connection)?);
+    }
+}
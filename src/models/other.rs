@@ -16,10 +16,11 @@ use crate::diesel::{
     Connection,
 };
 use serde::{Serialize, Deserialize};
-use crate::utils::establish_connection;
-use crate::errors::Error;
+use crate::utils::parse_field;
+use diesel::PgConnection;
+use crate::errors::{Error, BookingConflict};
 use actix_web::web::Json;
-use crate::models::UserJson;
+use crate::models::{UserJson, User, OrderStatus, OrderStatusLog, OrderPrice, OrderHold, PromoCode, PlaceTz, PlaceSchedule, SlotTemplate, quote_order, check_event_item, get_item_module};
 
 
 #[derive(Debug, Queryable, Deserialize, Serialize, Identifiable, Insertable)]
@@ -38,25 +39,21 @@ pub struct FeedbackJson {
 }
 
 impl Feedback {
-    pub fn get_all() -> Json<Vec<Feedback>> {
-        let _connection = establish_connection();
-        return Json(schema::feedbacks::table
-            .load::<Feedback>(&_connection)
-            .expect("E"));
-    }
-    pub fn create(form: Json<FeedbackJson>) -> i16 {
-        let _connection = establish_connection();
+    pub fn get_all(_connection: &PgConnection) -> Result<Json<Vec<Feedback>>, Error> {
+        return Ok(Json(schema::feedbacks::table
+            .load::<Feedback>(_connection)?));
+    }
+    pub fn create(_connection: &PgConnection, form: Json<FeedbackJson>) -> Result<(), Error> {
         let new_feedback = Feedback {
             id:       uuid::Uuid::new_v4().to_string(),
             username: form.username.clone(),
             email:    form.email.clone(),
             message:  form.message.clone()
         }; 
-        let _new_feedback = diesel::insert_into(schema::feedbacks::table)
+        diesel::insert_into(schema::feedbacks::table)
             .values(&new_feedback)
-            .execute(&_connection)
-            .expect("E.");
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
 }
 
@@ -72,122 +69,272 @@ pub struct Order {
     pub created:    chrono::NaiveDateTime,
     pub user_id:    String,
     pub price:      i32,
-    pub time_start: String,
-    pub time_end:   String,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_start: chrono::NaiveDateTime,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_end:   chrono::NaiveDateTime,
+    pub status:         i16,
+    pub status_changed: chrono::NaiveDateTime,
+    pub promo_code_id:  Option<String>,
+    pub discount:       i32,
 }
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct OrderJson { 
     pub title:      String,
     pub place_id:   String,
     pub object_id:  String,
     pub event_id:   Option<String>,
-    pub price:      i32,
+    // цену считает сервер; если клиент её прислал, она должна совпасть
+    pub price:      Option<i32>,
     pub time_start: String,
     pub time_end:   String, 
 } 
+#[derive(Serialize, Deserialize)]
+pub struct CreatedOrderJson {
+    pub ids:   Vec<String>,
+    pub price: OrderPrice,
+}
 
 impl Order {
-    pub fn get_client(&self) -> UserJson {
-        let _connection = establish_connection();
-        return schema::users::table
+    pub fn get_client(&self, _connection: &PgConnection) -> Result<UserJson, Error> {
+        return Ok(schema::users::table
             .filter(schema::users::id.eq(self.user_id.clone()))
             .select((
                 schema::users::id,
                 schema::users::first_name,
                 schema::users::last_name,
                 schema::users::email,
-                schema::users::level,
+                schema::users::perm,
                 schema::users::image,
             ))
-            .first::<UserJson>(&_connection)
-            .expect("E");
+            .first::<UserJson>(_connection)?);
     }
-    pub fn get_for_place(id: String) -> Json<Vec<Order>> {
-        let _connection = establish_connection();
-        return Json(schema::orders::table
+    pub fn get_for_place(_connection: &PgConnection, id: String) -> Result<Json<Vec<Order>>, Error> {
+        let tz = PlaceTz::for_place(_connection, &id)?;
+        return Ok(Json(schema::orders::table
             .filter(schema::orders::place_id.eq(id))
-            .load::<Order>(&_connection)
-            .expect("E"));
+            .load::<Order>(_connection)?
+            .into_iter()
+            .map(|o| o.localize(tz))
+            .collect()));
     } 
-    pub fn create(user_id: String, form: Json<Vec<OrderJson>>) -> i16 {
-        let _connection = establish_connection();
+    /// время брони из UTC в местное время объекта - перед отдачей в API
+    pub fn localize(mut self, tz: PlaceTz) -> Order {
+        self.time_start = tz.to_local(self.time_start);
+        self.time_end = tz.to_local(self.time_end);
+        self
+    }
+    /// advisory-блокировка модуля до конца транзакции; её же берёт триггер orders_no_overlap
+    pub fn lock_object(_connection: &PgConnection, object_id: &String) -> Result<(), Error> {
+        diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind::<diesel::sql_types::Text, _>(object_id)
+            .execute(_connection)?;
+        Ok(())
+    }
 
+    /// пересечения позиций заказа с существующими бронями, действующими холдами и друг с другом;
+    /// холды пользователя own_user_id конфликтом не считаются. Время конфликтов - местное
+    pub fn find_conflicts(_connection: &PgConnection, form: &Vec<OrderJson>, own_user_id: Option<&String>) -> Result<Vec<BookingConflict>, Error> {
+        let mut conflicts = Vec::new();
+        let mut checked: Vec<(&String, chrono::NaiveDateTime, chrono::NaiveDateTime)> = Vec::new();
         for i in form.iter() {
-            let time_start: String;
-            let time_end: String; 
-            let format_start = chrono::NaiveDateTime::parse_from_str(&i.time_start, "%Y-%m-%d %H:%M:%S").unwrap();
-            let format_end = chrono::NaiveDateTime::parse_from_str(&i.time_end, "%Y-%m-%d %H:%M:%S").unwrap();
+            let tz = PlaceTz::for_place(_connection, &i.place_id)?;
+            let time_start = tz.parse(&i.time_start, "time_start")?;
+            let time_end = tz.parse(&i.time_end, "time_end")?;
+            if time_end <= time_start {
+                return Err(Error::BadRequest(String::from("Время окончания должно быть позже времени начала")));
+            }
 
-            if schema::times::table
-                .filter(schema::times::time.eq(format_start))
-                .select(schema::times::id)
-                .first::<String>(&_connection)
-                .is_ok() {
-                    time_start = i.time_start.clone();
+            let _orders = schema::orders::table
+                .filter(schema::orders::object_id.eq(&i.object_id))
+                .filter(schema::orders::place_id.eq(&i.place_id))
+                .filter(schema::orders::status.ne_all(OrderStatus::releasing_codes()))
+                .filter(schema::orders::time_start.lt(time_end))
+                .filter(schema::orders::time_end.gt(time_start))
+                .select((
+                    schema::orders::title,
+                    schema::orders::time_start,
+                    schema::orders::time_end,
+                ))
+                .load::<(String, chrono::NaiveDateTime, chrono::NaiveDateTime)>(_connection)?;
+            for (title, start, end) in _orders {
+                conflicts.push(BookingConflict {
+                    object_id:  i.object_id.clone(),
+                    title:      title,
+                    time_start: tz.to_local(start),
+                    time_end:   tz.to_local(end),
+                });
             }
-            else {
-                let new = Time {
-                    id:   uuid::Uuid::new_v4().to_string(),
-                    time: format_start + chrono::Duration::hours(3),
-                }; 
-                let _new_time = diesel::insert_into(schema::times::table)
-                    .values(&new)
-                    .execute(&_connection)
-                    .expect("E.");
-                time_start = i.time_start.clone();
+            let mut _holds = schema::order_holds::table
+                .filter(schema::order_holds::object_id.eq(&i.object_id))
+                .filter(schema::order_holds::place_id.eq(&i.place_id))
+                .filter(schema::order_holds::expires.gt(chrono::Utc::now().naive_utc()))
+                .filter(schema::order_holds::time_start.lt(time_end))
+                .filter(schema::order_holds::time_end.gt(time_start))
+                .into_boxed();
+            if let Some(user_id) = own_user_id {
+                _holds = _holds.filter(schema::order_holds::user_id.ne(user_id));
+            }
+            let _holds = _holds
+                .select((schema::order_holds::time_start, schema::order_holds::time_end))
+                .load::<(chrono::NaiveDateTime, chrono::NaiveDateTime)>(_connection)?;
+            for (start, end) in _holds {
+                conflicts.push(BookingConflict {
+                    object_id:  i.object_id.clone(),
+                    title:      String::from("Модуль выбран другим пользователем"),
+                    time_start: tz.to_local(start),
+                    time_end:   tz.to_local(end),
+                });
             }
+            // две позиции одного заказа на тот же модуль и время
+            if checked.iter().any(|(object_id, start, end)| {
+                *object_id == &i.object_id && *start < time_end && *end > time_start
+            }) {
+                conflicts.push(BookingConflict {
+                    object_id:  i.object_id.clone(),
+                    title:      i.title.clone(),
+                    time_start: tz.to_local(time_start),
+                    time_end:   tz.to_local(time_end),
+                });
+            }
+            checked.push((&i.object_id, time_start, time_end));
+        }
+        Ok(conflicts)
+    }
 
-            if schema::times::table
-                .filter(schema::times::time.eq(format_end))
-                .select(schema::times::id)
-                .first::<String>(&_connection)
-                .is_ok() {
-                    time_end = i.time_end.clone();
+    /// Позиция заказа или холда до расчёта цены: модуль объекта, событие, длительность,
+    /// расписание и слоты. Возвращает время позиции в UTC
+    pub fn check_item(_connection: &PgConnection, i: &OrderJson) -> Result<(chrono::NaiveDateTime, chrono::NaiveDateTime), Error> {
+        // на событие - только модулем события и ровно на время события
+        check_event_item(_connection, i, &get_item_module(_connection, i)?)?;
+        let tz = PlaceTz::for_place(_connection, &i.place_id)?;
+        let time_start = tz.parse(&i.time_start, "time_start")?;
+        let time_end = tz.parse(&i.time_end, "time_end")?;
+        PlaceSchedule::check_booking(_connection, &i.place_id, time_start, time_end, i.event_id.is_some())?;
+        if i.event_id.is_none() {
+            SlotTemplate::check_booking(_connection, &i.place_id, &i.object_id, tz, time_start, time_end)?;
+        }
+        Ok((time_start, time_end))
+    }
+
+    pub fn create(_connection: &PgConnection, user_id: String, form: Json<Vec<OrderJson>>, promo_code: Option<String>) -> Result<CreatedOrderJson, Error> {
+        // заказ из нескольких позиций создаётся целиком или не создаётся вовсе
+        _connection.transaction(|| {
+            // сначала проверяем позиции, цена и промокод - только для допустимого заказа
+            let mut times = Vec::new();
+            for i in form.iter() {
+                times.push(Order::check_item(_connection, i)?);
             }
-            else {
-                let new = Time {
-                    id:   uuid::Uuid::new_v4().to_string(),
-                    time: format_end + chrono::Duration::hours(3),
+            // блокируем модули в одном порядке, чтобы параллельные заказы не взаимоблокировались
+            let mut object_ids: Vec<&String> = form.iter().map(|i| &i.object_id).collect();
+            object_ids.sort();
+            object_ids.dedup();
+            for object_id in object_ids {
+                Order::lock_object(_connection, object_id)?;
+            }
+            let conflicts = Order::find_conflicts(_connection, &form, Some(&user_id))?;
+            if !conflicts.is_empty() {
+                return Err(Error::Conflict(String::from("Выбранное время уже занято"), conflicts));
+            }
+            let promo = match &promo_code {
+                Some(code) => Some(PromoCode::find_valid(_connection, code, Some(&user_id))?),
+                None => None,
+            };
+            let price = quote_order(_connection, &form, promo.as_ref())?;
+
+            let mut ids = Vec::new();
+            for ((i, item_price), (time_start, time_end)) in form.iter().zip(price.items.iter()).zip(times.into_iter()) {
+                let new_order = Order {
+                    id:         uuid::Uuid::new_v4().to_string(),
+                    title:      i.title.clone(),
+                    types:      1,
+                    place_id:   i.place_id.clone(),
+                    object_id:  i.object_id.clone(),
+                    event_id:   i.event_id.clone(),
+                    created:    chrono::Local::now().naive_utc(),
+                    user_id:    user_id.clone(),
+                    price:      item_price.total - item_price.discount,
+                    time_start: time_start,
+                    time_end:   time_end, 
+                    status:         OrderStatus::Pending.code(),
+                    status_changed: chrono::Utc::now().naive_utc(),
+                    promo_code_id:  promo.as_ref().map(|p| p.id.clone()),
+                    discount:       item_price.discount,
                 }; 
-                let _new_time = diesel::insert_into(schema::times::table)
-                    .values(&new)
-                    .execute(&_connection)
-                    .expect("E.");
-                time_end = i.time_end.clone();
+                diesel::insert_into(schema::orders::table)
+                    .values(&new_order)
+                    .execute(_connection)?;
+                ids.push(new_order.id);
             }
+            if let Some(promo) = &promo {
+                promo.register_use(_connection, &user_id, price.discount)?;
+            }
+            // оформленные позиции больше не нужно держать
+            OrderHold::release_covered(_connection, &user_id, &form)?;
+            Ok(CreatedOrderJson {
+                ids:   ids,
+                price: price,
+            })
+        })
+    }
 
-            let new_order = Order {
-                id:         uuid::Uuid::new_v4().to_string(),
-                title:      i.title.clone(),
-                types:      1,
-                place_id:   i.place_id.clone(),
-                object_id:  i.object_id.clone(),
-                event_id:   i.event_id.clone(),
-                created:    chrono::Local::now().naive_utc(),
-                user_id:    user_id.clone(),
-                price:      i.price,
-                time_start: time_start,
-                time_end:   time_end, 
-            }; 
-            let _new_order = diesel::insert_into(schema::orders::table)
-                .values(&new_order)
-                .execute(&_connection)
-                .expect("E.");
-        }
-        return 1;
+
+    pub fn status(&self) -> OrderStatus {
+        return OrderStatus::from_code(self.status);
+    }
+    pub fn get(_connection: &PgConnection, id: &String) -> Result<Order, Error> {
+        return Ok(schema::orders::table
+            .filter(schema::orders::id.eq(id))
+            .first::<Order>(_connection)?);
+    }
+    /// может ли пользователь видеть бронь и её историю: клиент или сотрудник объекта
+    pub fn is_visible_to(&self, _connection: &PgConnection, user: &User) -> bool {
+        self.user_id == user.id || user.is_can_work_in_object_with_id(_connection, &self.place_id)
     }
 
+    /// переход брони в новый статус с проверкой прав и записью в историю
+    pub fn change_status(_connection: &PgConnection, id: &String, user: &User, to: OrderStatus) -> Result<Order, Error> {
+        _connection.transaction(|| {
+            let _order = schema::orders::table
+                .filter(schema::orders::id.eq(id))
+                .for_update()
+                .first::<Order>(_connection)?;
+            if to.by_customer() {
+                if _order.user_id != user.id {
+                    return Err(Error::Forbidden);
+                }
+            }
+            else if !user.is_can_work_in_object_with_id(_connection, &_order.place_id) {
+                return Err(Error::Forbidden);
+            }
+            Order::apply_status(_connection, _order, &user.id, to)
+        })
+    }
+    /// переход без проверки прав - для системных событий (оплата, возврат);
+    /// actor попадает в историю вместо id пользователя
+    pub fn change_status_by_system(_connection: &PgConnection, id: &String, actor: &str, to: OrderStatus) -> Result<Order, Error> {
+        _connection.transaction(|| {
+            let _order = schema::orders::table
+                .filter(schema::orders::id.eq(id))
+                .for_update()
+                .first::<Order>(_connection)?;
+            Order::apply_status(_connection, _order, &actor.to_string(), to)
+        })
+    }
+    fn apply_status(_connection: &PgConnection, _order: Order, actor_id: &String, to: OrderStatus) -> Result<Order, Error> {
+        let from = _order.status();
+        if !from.can_become(to) {
+            return Err(Error::BadRequest(format!("Нельзя перевести бронь из статуса {:?} в {:?}", from, to)));
+        }
 
-    pub fn delete(user_id: String, data: Json<crate::views::OrderIdsJson>) -> i16 {
-        let _connection = establish_connection();
-        diesel::delete (
-            schema::orders::table
-                .filter(schema::orders::user_id.eq(user_id))
-                .filter(schema::orders::id.eq_any(data.ids.clone()))
-        )
-        .execute(&_connection)
-        .expect("E");
-        return 1;
+        let _order = diesel::update(&_order)
+            .set((
+                schema::orders::status.eq(to.code()),
+                schema::orders::status_changed.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .get_result::<Order>(_connection)?;
+        OrderStatusLog::create(_connection, &_order.id, actor_id, from, to)?;
+        Ok(_order)
     }
 
 }
@@ -211,27 +358,23 @@ pub struct LogJson {
 }
 
 impl Log {
-    pub fn get_all() -> Json<Vec<Log>> {
-        let _connection = establish_connection();
-        return Json(schema::logs::table
-            .load::<Log>(&_connection)
-            .expect("E"));
-    }
-    pub fn create(form: Json<LogJson>) -> i16 {
-        let _connection = establish_connection();
+    pub fn get_all(_connection: &PgConnection) -> Result<Json<Vec<Log>>, Error> {
+        return Ok(Json(schema::logs::table
+            .load::<Log>(_connection)?));
+    }
+    pub fn create(_connection: &PgConnection, form: Json<LogJson>) -> Result<(), Error> {
         let new_log = Log {
             id:       uuid::Uuid::new_v4().to_string(),
             user_id:  form.user_id.clone(),
             text:     form.text.clone(),
             order_id: form.order_id.clone(),
             place_id: form.user_id.clone(),
-            created:  chrono::Local::now().naive_utc() + chrono::Duration::hours(3),
+            created:  chrono::Utc::now().naive_utc(),
         }; 
-        let _new_log = diesel::insert_into(schema::logs::table)
+        diesel::insert_into(schema::logs::table)
             .values(&new_log)
-            .execute(&_connection)
-            .expect("E.");
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
 }
 
@@ -248,33 +391,29 @@ pub struct TimeJson {
 }
 
 impl Time {
-    pub fn get_all() -> Json<Vec<chrono::NaiveDateTime>> {
-        let _connection = establish_connection();
-        return Json(schema::times::table
+    pub fn get_all(_connection: &PgConnection) -> Result<Json<Vec<chrono::NaiveDateTime>>, Error> {
+        return Ok(Json(schema::times::table
             .order(schema::times::time.asc())
             .select(schema::times::time)
-            .load::<chrono::NaiveDateTime>(&_connection)
-            .expect("E"));
+            .load::<chrono::NaiveDateTime>(_connection)?));
     }
-    pub fn create(form: Json<TimeJson>) -> i16 {
-        let _connection = establish_connection();
+    pub fn create(_connection: &PgConnection, form: Json<TimeJson>) -> Result<(), Error> {
         if schema::times::table
             .filter(schema::times::time.eq(form.time.clone()))
             .select(schema::times::id)
-            .first::<String>(&_connection)
+            .first::<String>(_connection)
             .is_ok() {
-                return 0;
+                return Err(Error::BadRequest("Такое время уже есть".to_string()));
         }
 
         let new_time = Time {
             id:   uuid::Uuid::new_v4().to_string(),
             time: form.time.clone(),
         }; 
-        let _new_time = diesel::insert_into(schema::times::table)
+        diesel::insert_into(schema::times::table)
             .values(&new_time)
-            .execute(&_connection)
-            .expect("E.");
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
 }
 
@@ -290,77 +429,81 @@ pub struct Region {
     pub cord:        Option<String>,
 }
 impl Region { 
-    pub fn get_country_all(id: i32) -> Vec<Region> {
-        let _connection = establish_connection();
-        return schema::regions::table
+    pub fn get_country_all(_connection: &PgConnection, id: i32) -> Result<Vec<Region>, Error> {
+        return Ok(schema::regions::table
             .filter(schema::regions::country_id.eq(id))
-            .load::<Region>(&_connection)
-            .expect("E");
-    }
-    pub fn get_all() -> Vec<Region> {
-        let _connection = establish_connection();
-        return schema::regions::table
-            .load::<Region>(&_connection)
-            .expect("E");
-    }
-    pub fn get(id: i32) -> Region {
-        let _connection = establish_connection();
-        return schema::regions::table
+            .load::<Region>(_connection)?);
+    }
+    pub fn get_all(_connection: &PgConnection) -> Result<Vec<Region>, Error> {
+        return Ok(schema::regions::table
+            .load::<Region>(_connection)?);
+    }
+    pub fn get(_connection: &PgConnection, id: i32) -> Result<Region, Error> {
+        return Ok(schema::regions::table
             .filter(schema::regions::id.eq(id))
-            .first::<Region>(&_connection)
-            .expect("E");
+            .first::<Region>(_connection)?);
+    }
+    /// пояс региона должен быть из справочника timezones
+    fn check_timezone(_connection: &PgConnection, timezone_id: Option<i32>) -> Result<(), Error> {
+        if let Some(timezone_id) = timezone_id {
+            if schema::timezones::table
+                .filter(schema::timezones::id.eq(timezone_id))
+                .select(schema::timezones::id)
+                .first::<i32>(_connection)
+                .is_err() {
+                    return Err(Error::BadRequest(String::from("Неизвестный часовой пояс")));
+            }
+        }
+        Ok(())
     }
     pub fn create (
+        _connection: &PgConnection,
         //country_id: i32,
-        name:       String,
-        cord:       Option<String>,
-    ) -> i16 {
-        let _connection = establish_connection();
+        name:        String,
+        cord:        Option<String>,
+        timezone_id: Option<i32>,
+    ) -> Result<(), Error> {
+        Region::check_timezone(_connection, timezone_id)?;
         let new_form = NewRegion {
             name:         name,
             geo_id:       None,
             country_id:   1,
-            timezone_id:  None,
+            timezone_id:  timezone_id,
             cord:         cord,
         };
-        let _new = diesel::insert_into(schema::regions::table)
+        diesel::insert_into(schema::regions::table)
             .values(&new_form)
-            .execute(&_connection)
-            .expect("Error.");
-        
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
-    pub fn edit ( 
+    pub fn edit (_connection: &PgConnection,  
         id:   i32,
         //country_id: i32,
         name: String,
         cord: Option<String>,
-    ) -> i16 {
-        let _connection = establish_connection();
+        timezone_id: Option<i32>,
+    ) -> Result<(), Error> {
+        Region::check_timezone(_connection, timezone_id)?;
         let _region = schema::regions::table
             .filter(schema::regions::id.eq(id))
-            .first::<Region>(&_connection)
-            .expect("E.");
+            .first::<Region>(_connection)?;
         diesel::update(&_region)
             .set((
                 schema::regions::name.eq(name),
                 //schema::regions::country_id.eq(country_id),
                 schema::regions::cord.eq(cord),
+                schema::regions::timezone_id.eq(timezone_id),
             ))
-            .execute(&_connection)
-            .expect("Error.");
-        
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
-    pub fn delete(id: i32) -> i16 {
-        let _connection = establish_connection();
+    pub fn delete(_connection: &PgConnection, id: i32) -> Result<(), Error> {
         diesel::delete (
             schema::regions::table
                 .filter(schema::regions::id.eq(&id))
         )
-        .execute(&_connection)
-        .expect("E");
-        return 1;
+        .execute(_connection)?;
+        Ok(())
     }
 }
 
@@ -384,47 +527,36 @@ pub struct Citie {
     pub cord:       Option<String>,
 }
 impl Citie {
-    pub fn get_all() -> Vec<Citie> {
-        let _connection = establish_connection();
-        return schema::cities::table
-            .load::<Citie>(&_connection)
-            .expect("E");
-    }
-    pub fn get(id: i32) -> Citie {
-        let _connection = establish_connection();
-        return schema::cities::table
+    pub fn get_all(_connection: &PgConnection) -> Result<Vec<Citie>, Error> {
+        return Ok(schema::cities::table
+            .load::<Citie>(_connection)?);
+    }
+    pub fn get(_connection: &PgConnection, id: i32) -> Result<Citie, Error> {
+        return Ok(schema::cities::table
             .filter(schema::cities::id.eq(id))
-            .first::<Citie>(&_connection)
-            .expect("E");
+            .first::<Citie>(_connection)?);
     }
-    pub fn get_region_all(id: i32) -> Vec<Citie> {
-        let _connection = establish_connection();
-        return schema::cities::table
+    pub fn get_region_all(_connection: &PgConnection, id: i32) -> Result<Vec<Citie>, Error> {
+        return Ok(schema::cities::table
             .filter(schema::cities::region_id.eq(id))
-            .load::<Citie>(&_connection)
-            .expect("E");
+            .load::<Citie>(_connection)?);
     }
-    pub fn get_country_all(id: i32) -> Vec<Citie> {
-        let _connection = establish_connection();
-        return schema::cities::table
+    pub fn get_country_all(_connection: &PgConnection, id: i32) -> Result<Vec<Citie>, Error> {
+        return Ok(schema::cities::table
             .filter(schema::cities::country_id.eq(id))
-            .load::<Citie>(&_connection)
-            .expect("E");
+            .load::<Citie>(_connection)?);
     }
     pub fn create (
+        _connection: &PgConnection,
         region_id:  Option<String>,
         //country_id: i32,
         name:       String,
         cord:       Option<String>,
-    ) -> i16 {
-        let _connection = establish_connection();
-        let _id: Option<i32>;
-        if region_id.is_some() {
-            _id = Some(region_id.as_deref().unwrap().parse().unwrap());
-        }
-        else {
-            _id = None;
-        }
+    ) -> Result<(), Error> {
+        let _id: Option<i32> = match region_id {
+            Some(region_id) => Some(parse_field(&region_id, "region_id")?),
+            None => None,
+        };
         let new_form = NewCitie {
             name:         name,
             geo_id:       None,
@@ -432,34 +564,26 @@ impl Citie {
             country_id:   1,
             cord:         cord,
         };
-        let _new = diesel::insert_into(schema::cities::table)
+        diesel::insert_into(schema::cities::table)
             .values(&new_form)
-            .execute(&_connection)
-            .expect("Error.");
-        
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
-    pub fn edit ( 
+    pub fn edit (_connection: &PgConnection,  
         id:        i32,
         region_id: Option<String>,
         //country_id: i32,
         name:      String,
         cord:      Option<String>,
-    ) -> i16 {
-        let _connection = establish_connection();
-
-        let _id: Option<i32>;
-        if region_id.is_some() {
-            _id = Some(region_id.as_deref().unwrap().parse().unwrap());
-        }
-        else {
-            _id = None;
-        }
+    ) -> Result<(), Error> {
+        let _id: Option<i32> = match region_id {
+            Some(region_id) => Some(parse_field(&region_id, "region_id")?),
+            None => None,
+        };
 
         let _city = schema::cities::table
             .filter(schema::cities::id.eq(id))
-            .first::<Citie>(&_connection)
-            .expect("E.");
+            .first::<Citie>(_connection)?;
         diesel::update(&_city)
             .set((
                 schema::cities::name.eq(name),
@@ -467,20 +591,16 @@ impl Citie {
                 //schema::cities::country_id.eq(country_id),
                 schema::cities::cord.eq(cord),
             ))
-            .execute(&_connection)
-            .expect("Error.");
-        
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
-    pub fn delete(id: i32) -> i16 {
-        let _connection = establish_connection();
+    pub fn delete(_connection: &PgConnection, id: i32) -> Result<(), Error> {
         diesel::delete (
             schema::cities::table
                 .filter(schema::cities::id.eq(&id))
         )
-        .execute(&_connection)
-        .expect("E");
-        return 1;
+        .execute(_connection)?;
+        Ok(())
     }
 }
 
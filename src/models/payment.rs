@@ -0,0 +1,313 @@
+use crate::schema;
+use crate::schema::{payments, payment_orders};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    Connection,
+    JoinOnDsl,
+    OptionalExtension,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use crate::errors::Error;
+use crate::models::{Order, OrderStatus};
+use crate::utils::{PaymentProvider, WebhookEvent};
+
+
+/*
+Статус платежа, хранится в payments.status:
+1 ожидает оплаты
+2 оплачен
+3 ошибка оплаты
+4 отменён
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaymentStatus {
+    Pending,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl PaymentStatus {
+    pub fn from_code(code: i16) -> PaymentStatus {
+        match code {
+            2 => PaymentStatus::Succeeded,
+            3 => PaymentStatus::Failed,
+            4 => PaymentStatus::Cancelled,
+            _ => PaymentStatus::Pending,
+        }
+    }
+    pub fn code(&self) -> i16 {
+        match self {
+            PaymentStatus::Pending   => 1,
+            PaymentStatus::Succeeded => 2,
+            PaymentStatus::Failed    => 3,
+            PaymentStatus::Cancelled => 4,
+        }
+    }
+    /// статус из вебхука шлюза
+    pub fn from_webhook(status: &str) -> Option<PaymentStatus> {
+        match status {
+            "succeeded" => Some(PaymentStatus::Succeeded),
+            "failed"    => Some(PaymentStatus::Failed),
+            "cancelled" => Some(PaymentStatus::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Queryable, Serialize, Deserialize, Identifiable, Insertable)]
+#[table_name="payments"]
+pub struct Payment {
+    pub id:          String,
+    pub user_id:     String,
+    pub provider:    String,
+    pub external_id: Option<String>,
+    pub amount:      i32,
+    pub status:      i16,
+    pub created:     chrono::NaiveDateTime,
+    pub updated:     chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Queryable, Insertable)]
+#[table_name="payment_orders"]
+pub struct PaymentOrder {
+    pub payment_id: String,
+    pub order_id:   String,
+}
+
+#[derive(Deserialize)]
+pub struct CreatePaymentJson {
+    pub order_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct PaymentRespJson {
+    pub payment:          Payment,
+    pub order_ids:        Vec<String>,
+    pub confirmation_url: Option<String>,
+}
+
+impl Payment {
+    pub fn status(&self) -> PaymentStatus {
+        return PaymentStatus::from_code(self.status);
+    }
+    pub fn get_order_ids(&self, _connection: &PgConnection) -> Result<Vec<String>, Error> {
+        return Ok(schema::payment_orders::table
+            .filter(schema::payment_orders::payment_id.eq(&self.id))
+            .select(schema::payment_orders::order_id)
+            .load::<String>(_connection)?);
+    }
+    pub fn get_amount_by_external_id(_connection: &PgConnection, provider: &str, external_id: &String) -> Result<i32, Error> {
+        return Ok(schema::payments::table
+            .filter(schema::payments::provider.eq(provider))
+            .filter(schema::payments::external_id.eq(external_id))
+            .select(schema::payments::amount)
+            .first::<i32>(_connection)?);
+    }
+    pub fn get_own(_connection: &PgConnection, id: &String, user_id: &String) -> Result<PaymentRespJson, Error> {
+        let _payment = schema::payments::table
+            .filter(schema::payments::id.eq(id))
+            .filter(schema::payments::user_id.eq(user_id))
+            .first::<Payment>(_connection)?;
+        Ok(PaymentRespJson {
+            order_ids:        _payment.get_order_ids(_connection)?,
+            payment:          _payment,
+            confirmation_url: None,
+        })
+    }
+
+    /// платёж за свои неоплаченные брони; сумма берётся из orders.price
+    pub fn create(
+        _connection: &PgConnection,
+        provider:    &dyn PaymentProvider,
+        user_id:     &String,
+        order_ids:   &Vec<String>,
+    ) -> Result<PaymentRespJson, Error> {
+        if order_ids.is_empty() {
+            return Err(Error::BadRequest(String::from("Не выбраны брони для оплаты")));
+        }
+        _connection.transaction(|| {
+            let _orders = schema::orders::table
+                .filter(schema::orders::id.eq_any(order_ids))
+                .filter(schema::orders::user_id.eq(user_id))
+                .for_update()
+                .load::<Order>(_connection)?;
+            if _orders.len() != order_ids.len() {
+                return Err(Error::NotFound(String::from("Бронь не найдена")));
+            }
+            for _order in _orders.iter() {
+                if !_order.status().can_become(OrderStatus::Paid) {
+                    return Err(Error::BadRequest(format!("Бронь {} нельзя оплатить", _order.id)));
+                }
+            }
+            // бронь в ожидающем или проведённом платеже второй раз не оплачивается
+            let busy = schema::payment_orders::table
+                .inner_join(schema::payments::table.on(schema::payments::id.eq(schema::payment_orders::payment_id)))
+                .filter(schema::payment_orders::order_id.eq_any(order_ids))
+                .filter(schema::payments::status.eq_any(vec![
+                    PaymentStatus::Pending.code(),
+                    PaymentStatus::Succeeded.code(),
+                ]))
+                .select(schema::payment_orders::order_id)
+                .first::<String>(_connection)
+                .optional()?;
+            if let Some(order_id) = busy {
+                return Err(Error::BadRequest(format!("Бронь {} уже в другом платеже", order_id)));
+            }
+
+            let now = chrono::Utc::now().naive_utc();
+            let new_payment = Payment {
+                id:          uuid::Uuid::new_v4().to_string(),
+                user_id:     user_id.clone(),
+                provider:    provider.name().to_string(),
+                external_id: None,
+                amount:      _orders.iter().map(|o| o.price).sum(),
+                status:      PaymentStatus::Pending.code(),
+                created:     now,
+                updated:     now,
+            };
+            diesel::insert_into(schema::payments::table)
+                .values(&new_payment)
+                .execute(_connection)?;
+            for _order in _orders.iter() {
+                diesel::insert_into(schema::payment_orders::table)
+                    .values(&PaymentOrder {
+                        payment_id: new_payment.id.clone(),
+                        order_id:   _order.id.clone(),
+                    })
+                    .execute(_connection)?;
+            }
+
+            let created = provider.create_payment(
+                &new_payment.id,
+                new_payment.amount,
+                &format!("Бронирование: {} поз.", _orders.len()),
+            )?;
+            let _payment = diesel::update(&new_payment)
+                .set(schema::payments::external_id.eq(&created.external_id))
+                .get_result::<Payment>(_connection)?;
+            Ok(PaymentRespJson {
+                order_ids:        order_ids.clone(),
+                payment:          _payment,
+                confirmation_url: Some(created.confirmation_url),
+            })
+        })
+    }
+
+    /// Применяет проверенный вебхук. Повторы того же уведомления ничего не меняют;
+    /// при успешной оплате брони переходят в «оплачена».
+    pub fn apply_webhook(_connection: &PgConnection, provider: &dyn PaymentProvider, event: &WebhookEvent) -> Result<(), Error> {
+        let to = PaymentStatus::from_webhook(&event.status)
+            .ok_or(Error::BadRequest(format!("Неизвестный статус платежа: {}", event.status)))?;
+        _connection.transaction(|| {
+            let _payment = schema::payments::table
+                .filter(schema::payments::provider.eq(provider.name()))
+                .filter(schema::payments::external_id.eq(&event.external_id))
+                .for_update()
+                .first::<Payment>(_connection)?;
+            if _payment.status() != PaymentStatus::Pending {
+                return Ok(());
+            }
+            if to == PaymentStatus::Succeeded && event.amount != _payment.amount {
+                return Err(Error::BadRequest(String::from("Сумма платежа не совпадает")));
+            }
+
+            diesel::update(&_payment)
+                .set((
+                    schema::payments::status.eq(to.code()),
+                    schema::payments::updated.eq(chrono::Utc::now().naive_utc()),
+                ))
+                .execute(_connection)?;
+            if to == PaymentStatus::Succeeded {
+                for order_id in _payment.get_order_ids(_connection)? {
+                    // деньги уже получены: бронь, которую успели отменить, остаётся как есть
+                    if let Err(err) = Order::change_status_by_system(_connection, &order_id, "payments", OrderStatus::Paid) {
+                        log::error!("payment {}: order {} not paid: {}", _payment.id, order_id, err);
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::MockPaymentProvider;
+
+    /// база с применёнными миграциями из TEST_DATABASE_URL; без неё тест пропускается
+    fn test_connection() -> Option<PgConnection> {
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+        Some(PgConnection::establish(&url).expect("TEST_DATABASE_URL is not reachable"))
+    }
+
+    #[test]
+    fn mock_payment_round_trip_marks_orders_paid() {
+        let _connection = match test_connection() {
+            Some(_connection) => _connection,
+            None => return,
+        };
+        _connection.test_transaction::<_, Error, _>(|| {
+            let provider = MockPaymentProvider { secret: String::from("test-secret") };
+            let user_id = uuid::Uuid::new_v4().to_string();
+            let now = chrono::Utc::now().naive_utc();
+            let _order = Order {
+                id:             uuid::Uuid::new_v4().to_string(),
+                title:          String::from("test"),
+                types:          1,
+                place_id:       uuid::Uuid::new_v4().to_string(),
+                object_id:      uuid::Uuid::new_v4().to_string(),
+                event_id:       None,
+                created:        now,
+                user_id:        user_id.clone(),
+                price:          1500,
+                time_start:     now + chrono::Duration::days(1),
+                time_end:       now + chrono::Duration::days(1) + chrono::Duration::hours(2),
+                status:         OrderStatus::Pending.code(),
+                status_changed: now,
+                promo_code_id:  None,
+                discount:       0,
+            };
+            diesel::insert_into(schema::orders::table)
+                .values(&_order)
+                .execute(&_connection)?;
+            let order_ids = vec![_order.id.clone()];
+
+            let created = Payment::create(&_connection, &provider, &user_id, &order_ids)?;
+            assert_eq!(created.payment.amount, 1500);
+            assert!(created.confirmation_url.is_some());
+            // пока платёж ждёт оплаты, второй на ту же бронь не создаётся
+            assert!(Payment::create(&_connection, &provider, &user_id, &order_ids).is_err());
+
+            let (body, signature) = provider.signed_webhook(&WebhookEvent {
+                external_id: created.payment.external_id.clone().unwrap(),
+                status:      String::from("succeeded"),
+                amount:      1500,
+            });
+            let event = provider.verify_webhook(&signature, &body)?;
+            Payment::apply_webhook(&_connection, &provider, &event)?;
+            // повтор вебхука ничего не меняет
+            Payment::apply_webhook(&_connection, &provider, &event)?;
+
+            let status = schema::orders::table
+                .filter(schema::orders::id.eq(&_order.id))
+                .select(schema::orders::status)
+                .first::<i16>(&_connection)?;
+            assert_eq!(OrderStatus::from_code(status), OrderStatus::Paid);
+            let payment_status = schema::payments::table
+                .filter(schema::payments::id.eq(&created.payment.id))
+                .select(schema::payments::status)
+                .first::<i16>(&_connection)?;
+            assert_eq!(PaymentStatus::from_code(payment_status), PaymentStatus::Succeeded);
+            // оплаченную бронь нельзя оплатить ещё раз
+            assert!(Payment::create(&_connection, &provider, &user_id, &order_ids).is_err());
+            Ok(())
+        });
+    }
+}
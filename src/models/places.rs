@@ -16,10 +16,11 @@ use crate::diesel::{
     Connection,
 };
 use serde::{Serialize, Deserialize};
-use crate::utils::{establish_connection, Info};
+use crate::utils::{parse_field, Info};
+use diesel::PgConnection;
 use crate::errors::Error;
 use actix_web::web::Json;
-use crate::models::{Order, Time, UserJson};
+use crate::models::{Order, UserJson, PlaceTz, PlaceSchedule, ClosedInterval};
 
 
 #[derive(Debug, Queryable, Deserialize, Serialize, Identifiable, Insertable)]
@@ -34,54 +35,44 @@ pub struct PlaceTypeJson {
 }
 
 impl PlaceType {
-    pub fn get_all() -> Json<Vec<PlaceType>> {
-        let _connection = establish_connection();
-        return Json(schema::place_types::table
-            .load::<PlaceType>(&_connection)
-            .expect("E"));
+    pub fn get_all(_connection: &PgConnection) -> Result<Json<Vec<PlaceType>>, Error> {
+        return Ok(Json(schema::place_types::table
+            .load::<PlaceType>(_connection)?));
     }
-    pub fn create(form: Json<PlaceTypeJson>) -> i16 {
-        let _connection = establish_connection();
-
+    pub fn create(_connection: &PgConnection, form: Json<PlaceTypeJson>) -> Result<(), Error> {
         if schema::place_types::table
             .filter(schema::place_types::title.eq(form.title.clone()))
             .select(schema::place_types::id)
-            .first::<String>(&_connection)
+            .first::<String>(_connection)
             .is_ok() {
-                return 0;
+                return Err(Error::BadRequest("Такой тип уже есть".to_string()));
         }
 
         let new_place_type = PlaceType {
             id:    uuid::Uuid::new_v4().to_string(),
             title: form.title.clone(),
         }; 
-        let _place_type = diesel::insert_into(schema::place_types::table)
+        diesel::insert_into(schema::place_types::table)
             .values(&new_place_type)
-            .execute(&_connection)
-            .expect("E.");
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
-    pub fn edit(id: String, form: Json<PlaceTypeJson>) -> i16 {
-        let _connection = establish_connection();
+    pub fn edit(_connection: &PgConnection, id: String, form: Json<PlaceTypeJson>) -> Result<(), Error> {
         let _type = schema::place_types::table
             .filter(schema::place_types::id.eq(id))
-            .first::<PlaceType>(&_connection)
-            .expect("E.");
+            .first::<PlaceType>(_connection)?;
         diesel::update(&_type)
                 .set(schema::place_types::title.eq(&form.title.clone()))
-                .execute(&_connection)
-                .expect("E");
-        return 1;
+                .execute(_connection)?;
+        Ok(())
     }
-    pub fn delete(id: String) -> i16 {
-        let _connection = establish_connection();
+    pub fn delete(_connection: &PgConnection, id: String) -> Result<(), Error> {
         diesel::delete (
             schema::place_types::table
                 .filter(schema::place_types::id.eq(&id))
         )
-        .execute(&_connection)
-        .expect("E");
-        return 1;
+        .execute(_connection)?;
+        Ok(())
     }
 }
 
@@ -116,50 +107,44 @@ pub struct ModuleEditTypeJson {
 }
 
 impl ModuleType {
-    pub fn get(id: String) -> ModuleType {
-        let _connection = establish_connection();
-        return schema::module_types::table
+    pub fn get(_connection: &PgConnection, id: String) -> Result<ModuleType, Error> {
+        return Ok(schema::module_types::table
             .filter(schema::module_types::id.eq(id))
-            .first::<ModuleType>(&_connection)
-            .expect("E");
+            .first::<ModuleType>(_connection)?);
     }
-    pub fn get_all_for_place(place_id: String) -> Json<Vec<ModuleType>> {
-        let _connection = establish_connection();
-        return Json(schema::module_types::table
+    pub fn get_all_for_place(_connection: &PgConnection, place_id: String) -> Result<Json<Vec<ModuleType>>, Error> {
+        return Ok(Json(schema::module_types::table
             .filter(schema::module_types::place_id.eq(&place_id))
-            .load::<ModuleType>(&_connection)
-            .expect("E"));
+            .load::<ModuleType>(_connection)?));
     }
-    pub fn change_image(id: String, image: Option<String>) -> Result<(), Error> {
-        let _connection = establish_connection();
-        _connection.transaction(|| Ok({
-            let _u = diesel::update(schema::module_types::table.filter(schema::module_types::id.eq(id)))
-                .set(schema::module_types::image.eq(image))
-                .execute(&_connection);
-        }))
+    pub fn change_image(_connection: &PgConnection, id: String, image: Option<String>) -> Result<(), Error> {
+        diesel::update(schema::module_types::table.filter(schema::module_types::id.eq(id)))
+            .set(schema::module_types::image.eq(image))
+            .execute(_connection)?;
+        Ok(())
     }
     pub fn create (
+        _connection: &PgConnection,
         place_id:    String,
         title:       String,
         description: String,
         types:       String,
         price:       String,
-    ) -> Info {
-        let _connection = establish_connection();
+    ) -> Result<Info, Error> {
+        let _price: i32 = parse_field(&price, "price")?;
 
         if schema::module_types::table
             .filter(schema::module_types::place_id.eq(&place_id))
             .filter(schema::module_types::title.eq(&title))
             .select(schema::module_types::id)
-            .first::<String>(&_connection)
+            .first::<String>(_connection)
             .is_ok() {
-                return Info {
+                return Ok(Info {
                     text: "".to_string(),
-                };
+                });
         }
 
         let uuid = uuid::Uuid::new_v4().to_string();
-        let _price: i32 = price.parse().unwrap();
 
         let new_place_type = ModuleType {
             id:          uuid.clone(),
@@ -170,27 +155,25 @@ impl ModuleType {
             image:       None,
             price:       _price,
         }; 
-        let _place_type = diesel::insert_into(schema::module_types::table)
+        diesel::insert_into(schema::module_types::table)
             .values(&new_place_type)
-            .execute(&_connection)
-            .expect("E.");
-        return Info {
+            .execute(_connection)?;
+        return Ok(Info {
             text: uuid,
-        };
+        });
     }
     pub fn edit (
+        _connection: &PgConnection,
         id:          String, 
         title:       String,
         description: String,
         types:       String,
         price:       String,
-    ) -> Info {
-        let _connection = establish_connection();
+    ) -> Result<Info, Error> {
+        let _price: i32 = parse_field(&price, "price")?;
         let _type = schema::module_types::table
             .filter(schema::module_types::id.eq(id))
-            .first::<ModuleType>(&_connection)
-            .expect("E.");
-        let _price: i32 = price.parse().unwrap();
+            .first::<ModuleType>(_connection)?;
         diesel::update(&_type)
                 .set((
                     schema::module_types::title.eq(title),
@@ -198,21 +181,18 @@ impl ModuleType {
                     schema::module_types::types.eq(types),
                     schema::module_types::price.eq(_price),
                 ))
-                .execute(&_connection)
-                .expect("E");
-        return Info {
+                .execute(_connection)?;
+        return Ok(Info {
             text: _type.id,
-        };
+        });
     }
-    pub fn delete(id: String) -> i16 {
-        let _connection = establish_connection();
+    pub fn delete(_connection: &PgConnection, id: String) -> Result<(), Error> {
         diesel::delete (
             schema::module_types::table
                 .filter(schema::module_types::id.eq(&id))
         )
-        .execute(&_connection)
-        .expect("E");
-        return 1;
+        .execute(_connection)?;
+        Ok(())
     }
 }
 
@@ -251,6 +231,7 @@ pub struct EditPlaceJson {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RespOrderJson {
+    pub id:         String,
     pub title:      String,
     pub place_id:   String,
     pub object_id:  String,
@@ -259,107 +240,173 @@ pub struct RespOrderJson {
     pub time_start: String,
     pub time_end:   String,
     pub created:    String,
+    pub status:     i16,
 }
 
 impl Place {
-    pub fn get_orders(&self) -> Vec<RespOrderJson> {
-        let _connection = establish_connection();
+    pub fn get_orders(&self, _connection: &PgConnection) -> Result<Vec<RespOrderJson>, Error> {
         let list = schema::orders::table
             .filter(schema::orders::place_id.eq(self.id.clone()))
             .order(schema::orders::created.desc())
-            .load::<Order>(&_connection)
-            .expect("E");  
+            .load::<Order>(_connection)?;
+        let tz = PlaceTz::for_place(_connection, &self.id)?;
         let mut stack = Vec::new();
         for i in list {
             stack.push(RespOrderJson{
+                id:         i.id.clone(),
                 title:      i.title.clone(),
                 place_id:   i.place_id.clone(),
                 object_id:  i.object_id.clone(),
-                user:       i.get_client(),
+                user:       i.get_client(_connection)?,
                 price:      i.price,
-                time_start: i.time_start.clone(),
-                time_end:   i.time_end.clone(),
+                time_start: tz.format(i.time_start),
+                time_end:   tz.format(i.time_end),
                 created:    i.created.format("%Y-%m-%d %H:%M:%S").to_string(), 
+                status:     i.status,
             });
         }
-        return stack;
+        return Ok(stack);
     }
-    pub fn get_modules(&self) -> Vec<Module> {
-        let _connection = establish_connection();
-        return schema::modules::table
+    pub fn get_modules(&self, _connection: &PgConnection) -> Result<Vec<Module>, Error> {
+        return Ok(schema::modules::table
             .filter(schema::modules::place_id.eq(self.id.clone()))
             .filter(schema::modules::types.eq(1))
-            .load::<Module>(&_connection)
-            .expect("E");
+            .load::<Module>(_connection)?);
+    }
+    /// занятость модулей объекта в окне [from, to): модули, их события и пересекающие окно брони
+    /// и действующие холды приходят одним запросом, по строке на бронь.
+    /// Окно и ответ - в местном времени объекта, по умолчанию сутки от текущего момента.
+    /// Часы работы, сезоны и закрытия объекта приходят в closed каждого модуля
+    pub fn get_availability (
+        &self,
+        _connection: &PgConnection,
+        params:      &AvailabilityParams,
+    ) -> Result<Vec<ModuleAvailability>, Error> {
+        let tz = PlaceTz::for_place(_connection, &self.id)?;
+        let from = match &params.from {
+            Some(from) => tz.parse(from, "from")?,
+            None => chrono::Utc::now().naive_utc(),
+        };
+        let to = match &params.to {
+            Some(to) => tz.parse(to, "to")?,
+            None => from + chrono::Duration::days(1),
+        };
+        if to <= from {
+            return Err(Error::BadRequest(String::from("Время окончания должно быть позже времени начала")));
+        }
+        if to - from > chrono::Duration::days(PlaceSchedule::MAX_DAYS) {
+            return Err(Error::BadRequest(format!("Окно не может быть длиннее {} дней", PlaceSchedule::MAX_DAYS)));
+        }
+        let mut schedule = PlaceSchedule::load(_connection, &self.id, from, to)?;
+        let closed = schedule.closed_intervals(from, to);
+        // у модулей событий время задаёт событие, поэтому для них важны только закрытия
+        schedule.hours.clear();
+        schedule.seasons.clear();
+        let closures = schedule.closed_intervals(from, to);
+
+        let _rows = diesel::sql_query(
+            "SELECT m.*, e.time_start AS event_start, e.time_end AS event_end, \
+                    o.time_start AS order_start, o.time_end AS order_end \
+             FROM modules m \
+             LEFT JOIN events e ON e.id = m.event_id \
+             LEFT JOIN ( \
+                 SELECT object_id, place_id, time_start, time_end FROM orders \
+                 WHERE status NOT IN (6, 7, 8) \
+                 UNION ALL \
+                 SELECT object_id, place_id, time_start, time_end FROM order_holds \
+                 WHERE expires > now() AT TIME ZONE 'UTC' \
+             ) o ON o.object_id = m.id AND o.place_id = m.place_id \
+                  AND o.time_start < $3 AND o.time_end > $2 \
+             WHERE m.place_id = $1 AND m.types = 1 \
+             ORDER BY m.z_index, m.id, o.time_start"
+        )
+            .bind::<diesel::sql_types::Text, _>(&self.id)
+            .bind::<diesel::sql_types::Timestamp, _>(from)
+            .bind::<diesel::sql_types::Timestamp, _>(to)
+            .load::<AvailabilityRow>(_connection)?;
+
+        let mut stack: Vec<ModuleAvailability> = Vec::new();
+        for row in _rows {
+            let busy = match (&row.order_start, &row.order_end) {
+                (Some(start), Some(end)) => Some(BusyInterval {
+                    time_start: *start,
+                    time_end:   *end,
+                }),
+                _ => None,
+            };
+            if stack.last().map(|a| a.module.id == row.module.id) != Some(true) {
+                stack.push(ModuleAvailability {
+                    window:    module_window(&row, from, to),
+                    closed:    if row.module.event_id.is_some() { closures.clone() } else { closed.clone() },
+                    module:    row.module,
+                    status:    String::new(),
+                    busy:      Vec::new(),
+                    next_free: None,
+                });
+            }
+            if let Some(busy) = busy {
+                stack.last_mut().unwrap().busy.push(busy);
+            }
+        }
+        for item in stack.iter_mut() {
+            item.fill_status(tz);
+        }
+        Ok(stack)
     }
     
-    pub fn get_all(type_id: i16) -> Json<Vec<Place>> {
-        let _connection = establish_connection();
-        return Json(schema::places::table
+    pub fn get_all(_connection: &PgConnection, type_id: i16) -> Result<Json<Vec<Place>>, Error> {
+        return Ok(Json(schema::places::table
             .filter(schema::places::type_id.eq(type_id))
             .filter(schema::places::types.eq(1))
-            .load::<Place>(&_connection)
-            .expect("E"));
+            .load::<Place>(_connection)?));
     }
-    pub fn all() -> Json<Vec<Place>> {
-        let _connection = establish_connection();
-        return Json(schema::places::table
+    pub fn all(_connection: &PgConnection) -> Result<Json<Vec<Place>>, Error> {
+        return Ok(Json(schema::places::table
             .filter(schema::places::types.eq(1))
-            .load::<Place>(&_connection)
-            .expect("E"));
+            .load::<Place>(_connection)?));
     }
-    pub fn get(id: String) -> Json<Place> { 
-        let _connection = establish_connection();
-        return Json(schema::places::table
+    pub fn get(_connection: &PgConnection, id: String) -> Result<Json<Place>, Error> { 
+        return Ok(Json(schema::places::table
             .filter(schema::places::id.eq(id))
             .filter(schema::places::types.eq(1))
-            .first::<Place>(&_connection)
-            .expect("E"));
+            .first::<Place>(_connection)?));
     }
-    pub fn get_place(id: String) -> Place {
-        let _connection = establish_connection();
-        return schema::places::table
+    pub fn get_place(_connection: &PgConnection, id: String) -> Result<Place, Error> {
+        return Ok(schema::places::table
             .filter(schema::places::id.eq(id))
             .filter(schema::places::types.eq(1))
-            .first::<Place>(&_connection)
-            .expect("E");
+            .first::<Place>(_connection)?);
     }
-    pub fn get_suggest() -> Json<Vec<Place>> {
-        let _connection = establish_connection();
-        return Json(schema::places::table
+    pub fn get_suggest(_connection: &PgConnection) -> Result<Json<Vec<Place>>, Error> {
+        return Ok(Json(schema::places::table
             .filter(schema::places::types.eq(0))
-            .load::<Place>(&_connection)
-            .expect("E"));
+            .load::<Place>(_connection)?));
     }
-    pub fn get_edited() -> Json<Vec<Place>> {
-        let _connection = establish_connection();
-        return Json(schema::places::table
+    pub fn get_edited(_connection: &PgConnection) -> Result<Json<Vec<Place>>, Error> {
+        return Ok(Json(schema::places::table
             .filter(schema::places::types.eq(2))
-            .load::<Place>(&_connection)
-            .expect("E"));
+            .load::<Place>(_connection)?));
     }
-    pub fn get_closed() -> Json<Vec<Place>> {
-        let _connection = establish_connection();
-        return Json(schema::places::table
+    pub fn get_closed(_connection: &PgConnection) -> Result<Json<Vec<Place>>, Error> {
+        return Ok(Json(schema::places::table
             .filter(schema::places::types.eq(3))
-            .load::<Place>(&_connection)
-            .expect("E"));
+            .load::<Place>(_connection)?));
     }
     pub fn create(
+        _connection: &PgConnection,
         title:   String,
         user_id: String,
         city_id: String,
         type_id: String, 
         cord:    Option<String>
-    ) -> i16 {
-        let _connection = establish_connection();
-        let _city_id: i32 = city_id.parse().unwrap();
-        let _type_id: i16 = type_id.parse().unwrap();
+    ) -> Result<(), Error> {
+        let _city_id: i32 = parse_field(&city_id, "city_id")?;
+        let _type_id: i16 = parse_field(&type_id, "type_id")?;
         let new_place = Place {
             id:         uuid::Uuid::new_v4().to_string(),
             title:      title,
             types:      1,
-            created:    chrono::Local::now().naive_utc() + chrono::Duration::hours(3),
+            created:    chrono::Utc::now().naive_utc(),
             user_id:    user_id,
             city_id:    _city_id,
             type_id:    _type_id,
@@ -367,68 +414,57 @@ impl Place {
             background: None, 
             cord:       cord,
         };
-        let _place = diesel::insert_into(schema::places::table)
+        diesel::insert_into(schema::places::table)
             .values(&new_place)
-            .execute(&_connection)
-            .expect("E.");
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
     pub fn edit (
+        _connection: &PgConnection,
         id:      String, 
         title:   String,
         type_id: String,
         cord:    Option<String>
-    ) -> i16 { 
-        let _connection = establish_connection();
-        let _type_id: i16 = type_id.parse().unwrap();
+    ) -> Result<(), Error> { 
+        let _type_id: i16 = parse_field(&type_id, "type_id")?;
         let _place = schema::places::table
             .filter(schema::places::id.eq(id))
-            .first::<Place>(&_connection)
-            .expect("E."); 
+            .first::<Place>(_connection)?;
         diesel::update(&_place) 
             .set((
                 schema::places::title.eq(title),
                 schema::places::type_id.eq(_type_id),
                 schema::places::cord.eq(cord),
             ))
-            .execute(&_connection)
-            .expect("E");
-        return 1;
-    }
-    pub fn change_avatar(place_id: String, image: Option<String>) -> Result<(), Error> {
-        let _connection = establish_connection();
-        _connection.transaction(|| Ok({
-            let _u = diesel::update(places::table.filter(places::id.eq(place_id)))
-                .set(schema::places::image.eq(image))
-                .execute(&_connection);
-        }))
-    }
-    pub fn change_background(place_id: String, background: Option<String>) -> Result<(), Error> {
-        let _connection = establish_connection();
-        _connection.transaction(|| Ok({
-            let _u = diesel::update(places::table.filter(places::id.eq(place_id)))
-                .set(schema::places::background.eq(background))
-                .execute(&_connection);
-        }))
-    }
-    pub fn delete(id: String) -> i16 {
-        let _connection = establish_connection();
+            .execute(_connection)?;
+        Ok(())
+    }
+    pub fn change_avatar(_connection: &PgConnection, place_id: String, image: Option<String>) -> Result<(), Error> {
+        diesel::update(places::table.filter(places::id.eq(place_id)))
+            .set(schema::places::image.eq(image))
+            .execute(_connection)?;
+        Ok(())
+    }
+    pub fn change_background(_connection: &PgConnection, place_id: String, background: Option<String>) -> Result<(), Error> {
+        diesel::update(places::table.filter(places::id.eq(place_id)))
+            .set(schema::places::background.eq(background))
+            .execute(_connection)?;
+        Ok(())
+    }
+    pub fn delete(_connection: &PgConnection, id: String) -> Result<(), Error> {
         diesel::delete (
             schema::places::table
                 .filter(schema::places::id.eq(&id))
         )
-        .execute(&_connection)
-        .expect("E");
-        return 1;
+        .execute(_connection)?;
+        Ok(())
     }
-    pub fn get_managers(&self) -> Json<Vec<crate::models::UserJson>> {
-        let _connection = establish_connection();
+    pub fn get_managers(&self, _connection: &PgConnection) -> Result<Json<Vec<crate::models::UserJson>>, Error> {
         let users_ids = schema::place_managers::table
             .filter(schema::place_managers::place_id.eq(self.id.clone()))
             .select(schema::place_managers::user_id)
-            .load::<String>(&_connection)
-            .expect("E");
-        return Json(schema::users::table
+            .load::<String>(_connection)?;
+        return Ok(Json(schema::users::table
             .filter(schema::users::id.eq_any(users_ids))
             //.filter(schema::users::perm.eq(2))
             .select((
@@ -436,11 +472,10 @@ impl Place {
                 schema::users::first_name,
                 schema::users::last_name,
                 schema::users::email,
-                schema::users::level,
+                schema::users::perm,
                 schema::users::image,
             ))
-            .load::<crate::models::UserJson>(&_connection)
-            .expect("E"));
+            .load::<crate::models::UserJson>(_connection)?));
     }
 }
 
@@ -460,34 +495,29 @@ pub struct PlaceManagerJson {
 }
 
 impl PlaceManager {
-    pub fn create(form: Json<PlaceManagerJson>) -> i16 {
-        let _connection = establish_connection();
-        
+    pub fn create(_connection: &PgConnection, form: Json<PlaceManagerJson>) -> Result<(), Error> {
         let new_place_manager = PlaceManager {
             id:       uuid::Uuid::new_v4().to_string(),
             user_id:  form.user_id.clone(),
             place_id: form.place_id.clone(),
         }; 
-        let _place_manager = diesel::insert_into(schema::place_managers::table)
+        diesel::insert_into(schema::place_managers::table)
             .values(&new_place_manager)
-            .execute(&_connection)
-            .expect("E.");
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
-    pub fn delete(id: String) -> i16 {
-        let _connection = establish_connection();
+    pub fn delete(_connection: &PgConnection, id: String) -> Result<(), Error> {
         diesel::delete (
             schema::place_managers::table
                 .filter(schema::place_managers::id.eq(&id))
         )
-        .execute(&_connection)
-        .expect("E");
-        return 1;
+        .execute(_connection)?;
+        Ok(())
     }
 }
 ///
 
-#[derive(Debug, Queryable, Deserialize, Serialize, Identifiable, Insertable)]
+#[derive(Debug, Queryable, QueryableByName, Deserialize, Serialize, Identifiable, Insertable)]
 #[table_name="modules"]
 pub struct Module {
     pub id:         String,
@@ -510,6 +540,120 @@ pub struct Module {
     pub event_id:   Option<String>,
 
 }
+#[derive(QueryableByName)]
+struct AvailabilityRow {
+    #[diesel(embed)]
+    module:      Module,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Timestamp>"]
+    event_start: Option<chrono::NaiveDateTime>,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Timestamp>"]
+    event_end:   Option<chrono::NaiveDateTime>,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Timestamp>"]
+    order_start: Option<chrono::NaiveDateTime>,
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Timestamp>"]
+    order_end:   Option<chrono::NaiveDateTime>,
+}
+
+/// окно, в котором модуль вообще можно бронировать:
+/// у модуля события это пересечение запрошенного окна со временем события
+fn module_window (
+    row:  &AvailabilityRow,
+    from: chrono::NaiveDateTime,
+    to:   chrono::NaiveDateTime,
+) -> Option<(chrono::NaiveDateTime, chrono::NaiveDateTime)> {
+    if row.module.event_id.is_none() {
+        return Some((from, to));
+    }
+    let start = std::cmp::max(from, row.event_start?);
+    let end = std::cmp::min(to, row.event_end?);
+    if start < end {
+        return Some((start, end));
+    }
+    None
+}
+
+#[derive(Deserialize)]
+pub struct AvailabilityParams {
+    pub from: Option<String>,
+    pub to:   Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BusyInterval {
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_start: chrono::NaiveDateTime,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_end:   chrono::NaiveDateTime,
+}
+
+/// status: "free" - свободен всё окно, "busy" - есть брони в окне,
+/// "closed" - объект не работает всё окно,
+/// "unavailable" - модуль события, а событие не попадает в окно.
+/// closed - когда объект не работает, next_free - первый свободный момент в окне
+#[derive(Serialize, Debug)]
+pub struct ModuleAvailability {
+    pub module:    Module,
+    pub status:    String,
+    pub busy:      Vec<BusyInterval>,
+    pub closed:    Vec<ClosedInterval>,
+    pub next_free: Option<String>,
+    #[serde(skip)]
+    window:        Option<(chrono::NaiveDateTime, chrono::NaiveDateTime)>,
+}
+
+impl ModuleAvailability {
+    /// считает по UTC, затем переводит занятость и next_free в местное время
+    fn fill_status(&mut self, tz: PlaceTz) {
+        let (start, end) = match self.window {
+            Some(window) => window,
+            None => {
+                self.status = String::from("unavailable");
+                self.closed.clear();
+                return;
+            },
+        };
+        // закрытия отсортированы по началу: если курсор прошёл всё окно, объект не работает
+        let mut cursor = start;
+        for closed in self.closed.iter() {
+            if closed.time_start <= cursor && closed.time_end > cursor {
+                cursor = closed.time_end;
+            }
+        }
+        let is_closed = cursor >= end;
+        // брони и закрытия по началу: сдвигаем курсор, пока он попадает в одно из них
+        let mut taken: Vec<(chrono::NaiveDateTime, chrono::NaiveDateTime)> = self.closed.iter()
+            .map(|c| (c.time_start, c.time_end))
+            .collect();
+        let mut has_busy = false;
+        for busy in self.busy.iter() {
+            if busy.time_end <= start || busy.time_start >= end {
+                continue;
+            }
+            has_busy = true;
+            taken.push((busy.time_start, busy.time_end));
+        }
+        taken.sort();
+        cursor = start;
+        for (time_start, time_end) in taken {
+            if time_start <= cursor && time_end > cursor {
+                cursor = time_end;
+            }
+        }
+        self.status = String::from(if is_closed { "closed" } else if has_busy { "busy" } else { "free" });
+        if cursor < end {
+            self.next_free = Some(tz.format(cursor));
+        }
+        for busy in self.busy.iter_mut() {
+            busy.time_start = tz.to_local(busy.time_start);
+            busy.time_end = tz.to_local(busy.time_end);
+        }
+        for closed in self.closed.iter_mut() {
+            closed.time_start = tz.to_local(closed.time_start);
+            closed.time_end = tz.to_local(closed.time_end);
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ModuleJson { 
     pub id:         String,
@@ -532,118 +676,109 @@ pub struct ModuleJson {
 
 #[derive(Serialize, Deserialize)]
 pub struct CreateModuleJson {
-    place_id: String,
-    modules:  Vec<ModuleJson>,
+    pub place_id: String,
+    pub modules:  Vec<ModuleJson>,
 } 
 
 impl Module {
-    pub fn get_all_for_place(place_id: String) -> Json<Vec<Module>> {
-        let _connection = establish_connection();
-        return Json(schema::modules::table
+    pub fn get_all_for_place(_connection: &PgConnection, place_id: String) -> Result<Json<Vec<Module>>, Error> {
+        return Ok(Json(schema::modules::table
             .filter(schema::modules::place_id.eq(place_id))
-            .load::<Module>(&_connection)
-            .expect("E"));
+            .load::<Module>(_connection)?));
     }
-    pub fn get_all_for_place_with_type(place_id: String, type_id: String) -> Json<Vec<Module>> {
-        let _connection = establish_connection();
-        return Json(schema::modules::table
+    pub fn get_all_for_place_with_type(_connection: &PgConnection, place_id: String, type_id: String) -> Result<Json<Vec<Module>>, Error> {
+        return Ok(Json(schema::modules::table
             .filter(schema::modules::place_id.eq(place_id))
             .filter(schema::modules::type_id.eq(type_id))
-            .load::<Module>(&_connection)
-            .expect("E"));
+            .load::<Module>(_connection)?));
     }
-    pub fn create(data: Json<CreateModuleJson>) -> i16 {
+    pub fn create(_connection: &PgConnection, data: Json<CreateModuleJson>) -> Result<(), Error> {
         let place_id = data.place_id.clone(); 
-        let _connection = establish_connection();
-
-        let mut modules_ids = schema::modules::table
-            .filter(schema::modules::place_id.eq(&data.place_id))
-            //.filter(schema::modules::types.eq(1))
-            .select(schema::modules::id)
-            .load::<String>(&_connection)
-            .expect("E");
+
+        // схема объекта сохраняется целиком или не сохраняется вовсе
+        _connection.transaction(|| {
+            let modules_ids = schema::modules::table
+                .filter(schema::modules::place_id.eq(&data.place_id))
+                //.filter(schema::modules::types.eq(1))
+                .select(schema::modules::id)
+                .load::<String>(_connection)?;
         
-        let mut new_modules_ids = Vec::new();
-
-        for i in data.modules.iter() {
-            new_modules_ids.push(&i.id);
-            if modules_ids.contains(&i.id) {
-                println!("update case");
-                let _module = schema::modules::table
-                    .filter(schema::modules::id.eq(&i.id))
-                    .first::<Module>(&_connection)
-                    .expect("E");
-                diesel::update(&_module) 
-                .set((
-                    schema::modules::title.eq(&i.title),
-                    schema::modules::label.eq(&i.label),
-                    schema::modules::type_id.eq(&i.type_id),
-                    schema::modules::price.eq(&i.price),
-                    schema::modules::z_index.eq(&i.z_index),
-                    schema::modules::_width.eq(&i.width),
-                    schema::modules::_height.eq(&i.height),
-                    schema::modules::_left.eq(&i.left),
-                    schema::modules::_top.eq(&i.top),
-                    schema::modules::_angle.eq(&i.angle),
-                    schema::modules::font_color.eq(&i.font_color),
-                    schema::modules::font_size.eq(&i.font_size),
-                    schema::modules::back_color.eq(&i.back_color),
-                    schema::modules::image.eq(&i.image),
-                    schema::modules::event_id.eq(&i.event_id),
-                ))
-                .execute(&_connection)
-                .expect("E");
-            }
-            else {
-                println!("create case");
-                let new_module = Module {
-                    id:         i.id.clone(),
-                    title:      i.title.clone(),
-                    label:      i.label.clone(),
-                    types:      1, 
-                    place_id:   place_id.clone(),
-                    type_id:    i.type_id.clone(),
-                    price:      i.price,
-                    z_index:    i.z_index,
-                    _width:     i.width,
-                    _height:    i.height,
-                    _left:      i.left,
-                    _top:       i.top,
-                    _angle:     i.angle,
-                    font_color: i.font_color.clone(),
-                    font_size:  i.font_size.clone(),
-                    back_color: i.back_color.clone(),
-                    image:      i.image.clone(),
-                    event_id:   i.event_id.clone(),
-                };  
-                let _module = diesel::insert_into(schema::modules::table)
-                    .values(&new_module)
-                    .execute(&_connection)
-                    .expect("E.");
+            let mut new_modules_ids = Vec::new();
+
+            for i in data.modules.iter() {
+                new_modules_ids.push(&i.id);
+                if modules_ids.contains(&i.id) {
+                    println!("update case");
+                    let _module = schema::modules::table
+                        .filter(schema::modules::id.eq(&i.id))
+                        .first::<Module>(_connection)?;
+                    diesel::update(&_module) 
+                    .set((
+                        schema::modules::title.eq(&i.title),
+                        schema::modules::label.eq(&i.label),
+                        schema::modules::type_id.eq(&i.type_id),
+                        schema::modules::price.eq(&i.price),
+                        schema::modules::z_index.eq(&i.z_index),
+                        schema::modules::_width.eq(&i.width),
+                        schema::modules::_height.eq(&i.height),
+                        schema::modules::_left.eq(&i.left),
+                        schema::modules::_top.eq(&i.top),
+                        schema::modules::_angle.eq(&i.angle),
+                        schema::modules::font_color.eq(&i.font_color),
+                        schema::modules::font_size.eq(&i.font_size),
+                        schema::modules::back_color.eq(&i.back_color),
+                        schema::modules::image.eq(&i.image),
+                        schema::modules::event_id.eq(&i.event_id),
+                    ))
+                    .execute(_connection)?;
+                }
+                else {
+                    println!("create case");
+                    let new_module = Module {
+                        id:         i.id.clone(),
+                        title:      i.title.clone(),
+                        label:      i.label.clone(),
+                        types:      1, 
+                        place_id:   place_id.clone(),
+                        type_id:    i.type_id.clone(),
+                        price:      i.price,
+                        z_index:    i.z_index,
+                        _width:     i.width,
+                        _height:    i.height,
+                        _left:      i.left,
+                        _top:       i.top,
+                        _angle:     i.angle,
+                        font_color: i.font_color.clone(),
+                        font_size:  i.font_size.clone(),
+                        back_color: i.back_color.clone(),
+                        image:      i.image.clone(),
+                        event_id:   i.event_id.clone(),
+                    };  
+                    diesel::insert_into(schema::modules::table)
+                        .values(&new_module)
+                        .execute(_connection)?;
+                }
+
+                //modules_ids.retain(|x| *x != i.id);
             }
 
-            //modules_ids.retain(|x| *x != i.id);
-        }
-
-        diesel::delete (
-            schema::modules::table
-                .filter(schema::modules::place_id.eq(&data.place_id))
-                .filter(schema::modules::id.ne_any(new_modules_ids))
-        )
-        .execute(&_connection)
-        .expect("E");
-        return 1;
+            diesel::delete (
+                schema::modules::table
+                    .filter(schema::modules::place_id.eq(&data.place_id))
+                    .filter(schema::modules::id.ne_any(new_modules_ids))
+            )
+            .execute(_connection)?;
+            Ok(())
+        })
     }
 
-    pub fn delete(id: String) -> i16 {
-        let _connection = establish_connection();
+    pub fn delete(_connection: &PgConnection, id: String) -> Result<(), Error> {
         diesel::delete (
             schema::modules::table
                 .filter(schema::modules::id.eq(&id))
         )
-        .execute(&_connection)
-        .expect("E");
-        return 1;
+        .execute(_connection)?;
+        Ok(())
     }
 }
 
@@ -659,35 +794,41 @@ pub struct Event {
     pub types:       i16, 
     pub created:     chrono::NaiveDateTime,
     pub price:       i32,
-    pub time_start:  String,
-    pub time_end:    String,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_start:  chrono::NaiveDateTime,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_end:    chrono::NaiveDateTime,
     pub image:       Option<String>,
 } 
 
 impl Event {
-    pub fn change_image(id: String, image: Option<String>) -> Result<(), Error> {
-        let _connection = establish_connection();
-        _connection.transaction(|| Ok({
-            let _u = diesel::update(schema::events::table.filter(schema::events::id.eq(id)))
-                .set(schema::events::image.eq(image))
-                .execute(&_connection);
-        }))
-    }
-    pub fn get(id: String) -> Event {
-        let _connection = establish_connection();
-        return schema::events::table
+    pub fn change_image(_connection: &PgConnection, id: String, image: Option<String>) -> Result<(), Error> {
+        diesel::update(schema::events::table.filter(schema::events::id.eq(id)))
+            .set(schema::events::image.eq(image))
+            .execute(_connection)?;
+        Ok(())
+    }
+    pub fn get(_connection: &PgConnection, id: String) -> Result<Event, Error> {
+        return Ok(schema::events::table
             .filter(schema::events::id.eq(id))
-            .first::<Event>(&_connection)
-            .expect("E");
+            .first::<Event>(_connection)?);
     }
-    pub fn get_all_for_place(id: String) -> Json<Vec<Event>> {
-        let _connection = establish_connection();
-        return Json(schema::events::table
+    pub fn get_all_for_place(_connection: &PgConnection, id: String) -> Result<Json<Vec<Event>>, Error> {
+        let tz = PlaceTz::for_place(_connection, &id)?;
+        return Ok(Json(schema::events::table
             .filter(schema::events::place_id.eq(id))
-            .load::<Event>(&_connection)
-            .expect("E"));
-    }
-    pub fn create ( 
+            .load::<Event>(_connection)?
+            .into_iter()
+            .map(|e| e.localize(tz))
+            .collect()));
+    }
+    /// время события из UTC в местное время объекта - перед отдачей в API
+    pub fn localize(mut self, tz: PlaceTz) -> Event {
+        self.time_start = tz.to_local(self.time_start);
+        self.time_end = tz.to_local(self.time_end);
+        self
+    }
+    pub fn create (_connection: &PgConnection,  
         user_id:     String,
         place_id:    String,
         title:       String,
@@ -695,53 +836,12 @@ impl Event {
         price:       String,
         time_start:  String,
         time_end:    String,
-    ) -> Info {
-        let _connection = establish_connection();
- 
-        let format_start = chrono::NaiveDateTime::parse_from_str(&time_start, "%Y-%m-%d %H:%M:%S").unwrap();
-        let format_end = chrono::NaiveDateTime::parse_from_str(&time_end, "%Y-%m-%d %H:%M:%S").unwrap();
-        let new_time_start: String;
-        let new_time_end: String;
-
-        if schema::times::table
-            .filter(schema::times::time.eq(format_start))
-            .select(schema::times::id)
-            .first::<String>(&_connection)
-            .is_ok() {
-                new_time_start = time_start.clone();
-        }
-        else {
-            let new = Time {
-                id:   uuid::Uuid::new_v4().to_string(),
-                time: format_start + chrono::Duration::hours(3),
-            }; 
-            let _new_time = diesel::insert_into(schema::times::table)
-                .values(&new)
-                .execute(&_connection)
-                .expect("E.");
-            new_time_start = time_start.clone();
-        }
-
-        if schema::times::table
-            .filter(schema::times::time.eq(format_end))
-            .select(schema::times::id)
-            .first::<String>(&_connection)
-            .is_ok() {
-                new_time_end = time_end.clone();
-        }
-        else {
-            let new = Time {
-                id:   uuid::Uuid::new_v4().to_string(),
-                time: format_end + chrono::Duration::hours(3),
-            }; 
-            let _new_time = diesel::insert_into(schema::times::table)
-                .values(&new)
-                .execute(&_connection)
-                .expect("E.");
-            new_time_end = time_end.clone();
-        }
+    ) -> Result<Info, Error> {
+        let tz = PlaceTz::for_place(_connection, &place_id)?;
+        let format_start = tz.parse(&time_start, "time_start")?;
+        let format_end = tz.parse(&time_end, "time_end")?;
+        let _price: i32 = parse_field(&price, "price")?;
         let uuid = uuid::Uuid::new_v4().to_string();
-        let _price: i32 = price.parse().unwrap();
 
         let new_event = Event {
             id:          uuid.clone(),
@@ -752,59 +852,56 @@ impl Event {
             types:       1,
             created:     chrono::Local::now().naive_utc(),
             price:       _price,
-            time_start:  new_time_start,
-            time_end:    new_time_end,
+            time_start:  format_start,
+            time_end:    format_end,
             image:       None,
         }; 
-        let _new_event = diesel::insert_into(schema::events::table)
+        diesel::insert_into(schema::events::table)
             .values(&new_event)
-            .execute(&_connection)
-            .expect("E.");
-        return Info {
+            .execute(_connection)?;
+        return Ok(Info {
             text: uuid,
-        };
+        });
     }
 
     pub fn edit (
+        _connection: &PgConnection,
         id:          String,
         title:       String,
         description: String,
         price:       String,
         time_start:  String,
         time_end:    String,
-    ) -> Info { 
-        let _connection = establish_connection();
-        let _price: i32 = price.parse().unwrap();
-
+    ) -> Result<Info, Error> { 
+        let _price: i32 = parse_field(&price, "price")?;
         let _event = schema::events::table
             .filter(schema::events::id.eq(id))
-            .first::<Event>(&_connection)
-            .expect("E.");
+            .first::<Event>(_connection)?;
+        let tz = PlaceTz::for_place(_connection, &_event.place_id)?;
+        let format_start = tz.parse(&time_start, "time_start")?;
+        let format_end = tz.parse(&time_end, "time_end")?;
 
         diesel::update(&_event) 
             .set((
                 schema::events::title.eq(title),
                 schema::events::description.eq(description),
                 schema::events::price.eq(_price),
-                schema::events::time_start.eq(time_start),
-                schema::events::time_end.eq(time_end),
+                schema::events::time_start.eq(format_start),
+                schema::events::time_end.eq(format_end),
             ))
-            .execute(&_connection)
-            .expect("E");
-        return Info {
+            .execute(_connection)?;
+        return Ok(Info {
             text: _event.id,
-        };
+        });
     }
 
-    pub fn delete(id: String) -> i16 {
-        let _connection = establish_connection();
+    pub fn delete(_connection: &PgConnection, id: String) -> Result<(), Error> {
         diesel::delete (
-            schema::orders::table
-                .filter(schema::orders::id.eq(id))
+            schema::events::table
+                .filter(schema::events::id.eq(id))
         )
-        .execute(&_connection)
-        .expect("E");
-        return 1;
+        .execute(_connection)?;
+        Ok(())
     }
 
 }
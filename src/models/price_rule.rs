@@ -0,0 +1,208 @@
+use crate::schema;
+use crate::schema::price_rules;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use chrono::Datelike;
+use crate::errors::Error;
+
+
+/*
+Как считается цена правила, хранится в price_rules.kind:
+1 price за каждый начатый час
+2 price за всю бронь
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PriceRuleKind {
+    PerHour,
+    Flat,
+}
+
+impl PriceRuleKind {
+    pub fn from_code(code: i16) -> Option<PriceRuleKind> {
+        match code {
+            1 => Some(PriceRuleKind::PerHour),
+            2 => Some(PriceRuleKind::Flat),
+            _ => None,
+        }
+    }
+    pub fn code(&self) -> i16 {
+        match self {
+            PriceRuleKind::PerHour => 1,
+            PriceRuleKind::Flat    => 2,
+        }
+    }
+}
+
+/// Правило цены типа модуля: выходные, праздники, часы пик, полдня / целый день.
+#[derive(Debug, Queryable, Serialize, Deserialize, Identifiable, Insertable)]
+#[table_name="price_rules"]
+pub struct PriceRule {
+    pub id:             String,
+    pub module_type_id: String,
+    pub title:          String,
+    pub priority:       i32,
+    pub kind:           i16,
+    pub price:          i32,
+    pub weekdays:       i16,
+    pub time_from:      Option<chrono::NaiveTime>,
+    pub time_to:        Option<chrono::NaiveTime>,
+    pub date_from:      Option<chrono::NaiveDate>,
+    pub date_to:        Option<chrono::NaiveDate>,
+    pub min_hours:      Option<i32>,
+    pub max_hours:      Option<i32>,
+    pub created:        chrono::NaiveDateTime,
+}
+
+/// время - "чч:мм", даты - "ГГГГ-ММ-ДД"
+#[derive(Deserialize)]
+pub struct PriceRuleJson {
+    pub title:     String,
+    pub priority:  Option<i32>,
+    pub kind:      i16,
+    pub price:     i32,
+    pub weekdays:  Option<i16>,
+    pub time_from: Option<String>,
+    pub time_to:   Option<String>,
+    pub date_from: Option<String>,
+    pub date_to:   Option<String>,
+    pub min_hours: Option<i32>,
+    pub max_hours: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct PricePreviewParams {
+    pub time_start: String,
+    pub time_end:   String,
+}
+
+fn parse_time(value: &Option<String>, field: &str) -> Result<Option<chrono::NaiveTime>, Error> {
+    match value {
+        Some(value) => chrono::NaiveTime::parse_from_str(value, "%H:%M")
+            .map(Some)
+            .map_err(|_| Error::BadRequest(format!("Поле {} должно быть в формате чч:мм", field))),
+        None => Ok(None),
+    }
+}
+fn parse_date(value: &Option<String>, field: &str) -> Result<Option<chrono::NaiveDate>, Error> {
+    match value {
+        Some(value) => chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| Error::BadRequest(format!("Поле {} должно быть в формате ГГГГ-ММ-ДД", field))),
+        None => Ok(None),
+    }
+}
+
+impl PriceRule {
+    pub fn kind(&self) -> Option<PriceRuleKind> {
+        return PriceRuleKind::from_code(self.kind);
+    }
+    pub fn get(_connection: &PgConnection, id: &String) -> Result<PriceRule, Error> {
+        return Ok(schema::price_rules::table
+            .filter(schema::price_rules::id.eq(id))
+            .first::<PriceRule>(_connection)?);
+    }
+    /// правила в порядке проверки
+    pub fn get_for_module_type(_connection: &PgConnection, module_type_id: &String) -> Result<Vec<PriceRule>, Error> {
+        return Ok(schema::price_rules::table
+            .filter(schema::price_rules::module_type_id.eq(module_type_id))
+            .order((schema::price_rules::priority.desc(), schema::price_rules::created.asc()))
+            .load::<PriceRule>(_connection)?);
+    }
+
+    pub fn create(_connection: &PgConnection, module_type_id: &String, form: &PriceRuleJson) -> Result<PriceRule, Error> {
+        let kind = PriceRuleKind::from_code(form.kind)
+            .ok_or(Error::BadRequest(String::from("Неизвестный вид правила цены")))?;
+        if form.title.trim().is_empty() {
+            return Err(Error::BadRequest(String::from("Укажите название правила")));
+        }
+        if form.price < 0 {
+            return Err(Error::BadRequest(String::from("Цена не может быть отрицательной")));
+        }
+        let weekdays = form.weekdays.unwrap_or(127);
+        if weekdays < 1 || weekdays > 127 {
+            return Err(Error::BadRequest(String::from("weekdays - маска дней недели от 1 до 127")));
+        }
+        let time_from = parse_time(&form.time_from, "time_from")?;
+        let time_to = parse_time(&form.time_to, "time_to")?;
+        if time_from.is_some() != time_to.is_some() {
+            return Err(Error::BadRequest(String::from("Укажите оба поля time_from и time_to")));
+        }
+        let date_from = parse_date(&form.date_from, "date_from")?;
+        let date_to = parse_date(&form.date_to, "date_to")?;
+        if let (Some(from), Some(to)) = (date_from, date_to) {
+            if to < from {
+                return Err(Error::BadRequest(String::from("date_to не может быть раньше date_from")));
+            }
+        }
+        if let (Some(min), Some(max)) = (form.min_hours, form.max_hours) {
+            if max < min {
+                return Err(Error::BadRequest(String::from("max_hours не может быть меньше min_hours")));
+            }
+        }
+
+        let new_rule = PriceRule {
+            id:             uuid::Uuid::new_v4().to_string(),
+            module_type_id: module_type_id.clone(),
+            title:          form.title.trim().to_string(),
+            priority:       form.priority.unwrap_or(0),
+            kind:           kind.code(),
+            price:          form.price,
+            weekdays:       weekdays,
+            time_from:      time_from,
+            time_to:        time_to,
+            date_from:      date_from,
+            date_to:        date_to,
+            min_hours:      form.min_hours,
+            max_hours:      form.max_hours,
+            created:        chrono::Utc::now().naive_utc(),
+        };
+        diesel::insert_into(schema::price_rules::table)
+            .values(&new_rule)
+            .execute(_connection)?;
+        Ok(new_rule)
+    }
+    pub fn delete(_connection: &PgConnection, id: &String) -> Result<(), Error> {
+        diesel::delete(schema::price_rules::table.filter(schema::price_rules::id.eq(id)))
+            .execute(_connection)?;
+        Ok(())
+    }
+
+    /// подходит ли правило к часу брони, начинающемуся в at; hours - длительность всей брони
+    fn matches(&self, at: chrono::NaiveDateTime, hours: i32) -> bool {
+        let day = 1 << at.weekday().num_days_from_monday();
+        if self.weekdays & day == 0 {
+            return false;
+        }
+        if self.date_from.map_or(false, |from| at.date() < from) || self.date_to.map_or(false, |to| at.date() > to) {
+            return false;
+        }
+        if let (Some(from), Some(to)) = (self.time_from, self.time_to) {
+            let time = at.time();
+            let inside = if from < to {
+                time >= from && time < to
+            }
+            else {
+                time >= from || time < to
+            };
+            if !inside {
+                return false;
+            }
+        }
+        if self.min_hours.map_or(false, |min| hours < min) || self.max_hours.map_or(false, |max| hours > max) {
+            return false;
+        }
+        true
+    }
+
+    /// первое по приоритету правило нужного вида
+    pub fn find(rules: &[PriceRule], kind: PriceRuleKind, at: chrono::NaiveDateTime, hours: i32) -> Option<&PriceRule> {
+        rules.iter().find(|r| r.kind() == Some(kind) && r.matches(at, hours))
+    }
+}
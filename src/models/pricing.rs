@@ -0,0 +1,260 @@
+use crate::schema;
+use crate::diesel::{
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use crate::errors::Error;
+use crate::utils::parse_datetime;
+use crate::models::{Module, Event, OrderJson, PromoCode, PriceRule, PriceRuleKind, PlaceTz, PlaceSchedule};
+
+
+/*
+Цена позиции считается на сервере:
+- бронь на событие (event_id) - цена события за всё время события: модуль должен
+  принадлежать событию, а время брони - совпадать со временем события;
+- правило цены типа модуля за всю бронь (полдня, целый день), если подошло;
+- иначе каждый начатый час по правилу цены за час, а без правила - по цене модуля,
+  а если у модуля цена 0 - по цене его типа.
+Промокод уменьшает итог: discount позиции - её доля скидки, total заказа - к оплате.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PriceBreakdown {
+    pub object_id:  String,
+    pub title:      String,
+    pub source:     String,
+    pub unit_price: i32,
+    pub units:      i32,
+    pub total:      i32,
+    #[serde(default)]
+    pub discount:   i32,
+    // названия сработавших правил цены
+    #[serde(default)]
+    pub rules:      Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderPrice {
+    pub items:      Vec<PriceBreakdown>,
+    #[serde(default)]
+    pub promo_code: Option<String>,
+    #[serde(default)]
+    pub discount:   i32,
+    pub total:      i32,
+}
+
+fn too_expensive() -> Error {
+    Error::BadRequest(String::from("Сумма заказа слишком велика"))
+}
+/// сумма в i32 без переполнения: в debug переполнение - паника, в release - неверная цена
+fn add_price(total: i32, price: i32) -> Result<i32, Error> {
+    total.checked_add(price).ok_or_else(too_expensive)
+}
+
+impl OrderPrice {
+    pub fn from_items(items: Vec<PriceBreakdown>) -> Result<OrderPrice, Error> {
+        let mut total = 0;
+        for i in items.iter() {
+            total = add_price(total, i.total)?;
+        }
+        Ok(OrderPrice {
+            items:      items,
+            promo_code: None,
+            discount:   0,
+            total:      total,
+        })
+    }
+}
+
+/// Сколько часов оплачивается: каждый начатый час целиком.
+/// Бронь длиннее PlaceSchedule::MAX_DAYS отклоняется до расчёта по часам -
+/// предпросмотр цены публичный, а цикл по часам идёт в пуле базы.
+fn billed_hours(time_start: &String, time_end: &String) -> Result<i32, Error> {
+    let start = parse_datetime(time_start, "time_start")?;
+    let end = parse_datetime(time_end, "time_end")?;
+    if end <= start {
+        return Err(Error::BadRequest(String::from("Время окончания должно быть позже времени начала")));
+    }
+    if end - start > chrono::Duration::days(PlaceSchedule::MAX_DAYS) {
+        return Err(Error::BadRequest(format!("Бронь не может быть длиннее {} дней", PlaceSchedule::MAX_DAYS)));
+    }
+    Ok((((end - start).num_minutes() + 59) / 60) as i32)
+}
+
+pub fn get_item_module(_connection: &PgConnection, item: &OrderJson) -> Result<Module, Error> {
+    return schema::modules::table
+        .filter(schema::modules::id.eq(&item.object_id))
+        .filter(schema::modules::place_id.eq(&item.place_id))
+        .first::<Module>(_connection)
+        .map_err(|_| Error::NotFound(String::from("Модуль не найден")));
+}
+
+/// Позиция на событие: модуль принадлежит этому событию, время брони - ровно время события.
+/// Модуль события без event_id не бронируется. Для обычной позиции - None
+pub fn check_event_item(_connection: &PgConnection, item: &OrderJson, _module: &Module) -> Result<Option<Event>, Error> {
+    let event_id = match (&item.event_id, &_module.event_id) {
+        (None, None) => return Ok(None),
+        (Some(event_id), Some(module_event_id)) if event_id == module_event_id => event_id,
+        (None, Some(_)) => return Err(Error::BadRequest(String::from("Модуль события бронируется только на событие"))),
+        _ => return Err(Error::BadRequest(String::from("Модуль не относится к этому событию"))),
+    };
+    let _event = schema::events::table
+        .filter(schema::events::id.eq(event_id))
+        .filter(schema::events::place_id.eq(&item.place_id))
+        .first::<Event>(_connection)
+        .map_err(|_| Error::NotFound(String::from("Событие не найдено")))?;
+    let tz = PlaceTz::for_place(_connection, &item.place_id)?;
+    if tz.parse(&item.time_start, "time_start")? != _event.time_start
+        || tz.parse(&item.time_end, "time_end")? != _event.time_end {
+        return Err(Error::BadRequest(format!(
+            "Время брони должно совпадать со временем события: {} - {}",
+            tz.format(_event.time_start),
+            tz.format(_event.time_end),
+        )));
+    }
+    Ok(Some(_event))
+}
+
+pub fn quote_item(_connection: &PgConnection, item: &OrderJson) -> Result<PriceBreakdown, Error> {
+    let _module = get_item_module(_connection, item)?;
+
+    if let Some(_event) = check_event_item(_connection, item, &_module)? {
+        return Ok(PriceBreakdown {
+            object_id:  _module.id,
+            title:      _event.title,
+            source:     String::from("event"),
+            unit_price: _event.price,
+            units:      1,
+            total:      _event.price,
+            discount:   0,
+            rules:      Vec::new(),
+        });
+    }
+
+    let units = billed_hours(&item.time_start, &item.time_end)?;
+    let start = parse_datetime(&item.time_start, "time_start")?;
+    let rules = PriceRule::get_for_module_type(_connection, &_module.type_id)?;
+    if let Some(rule) = PriceRule::find(&rules, PriceRuleKind::Flat, start, units) {
+        return Ok(PriceBreakdown {
+            object_id:  _module.id,
+            title:      _module.title,
+            source:     String::from("price_rule"),
+            unit_price: rule.price,
+            units:      1,
+            total:      rule.price,
+            discount:   0,
+            rules:      vec![rule.title.clone()],
+        });
+    }
+
+    let (base_price, base_source) = if _module.price > 0 {
+        (_module.price, "module")
+    }
+    else {
+        let type_price = schema::module_types::table
+            .filter(schema::module_types::id.eq(&_module.type_id))
+            .select(schema::module_types::price)
+            .first::<i32>(_connection)?;
+        (type_price, "module_type")
+    };
+    let mut total = 0;
+    let mut applied: Vec<String> = Vec::new();
+    for hour in 0..units {
+        match PriceRule::find(&rules, PriceRuleKind::PerHour, start + chrono::Duration::hours(hour as i64), units) {
+            Some(rule) => {
+                total = add_price(total, rule.price)?;
+                if !applied.contains(&rule.title) {
+                    applied.push(rule.title.clone());
+                }
+            },
+            None => total = add_price(total, base_price)?,
+        }
+    }
+    // если часы стоят по-разному, unit_price - средняя цена часа
+    let (unit_price, source) = if applied.is_empty() {
+        (base_price, base_source)
+    }
+    else {
+        (total / units, "price_rule")
+    };
+    Ok(PriceBreakdown {
+        object_id:  _module.id,
+        title:      _module.title,
+        source:     String::from(source),
+        unit_price: unit_price,
+        units:      units,
+        total:      total,
+        discount:   0,
+        rules:      applied,
+    })
+}
+
+/// предпросмотр цены модуля на время, без заказа
+pub fn quote_module(_connection: &PgConnection, module_id: &String, time_start: &String, time_end: &String) -> Result<PriceBreakdown, Error> {
+    let _module = schema::modules::table
+        .filter(schema::modules::id.eq(module_id))
+        .first::<Module>(_connection)
+        .map_err(|_| Error::NotFound(String::from("Модуль не найден")))?;
+    quote_item(_connection, &OrderJson {
+        title:      _module.title,
+        place_id:   _module.place_id,
+        object_id:  _module.id,
+        event_id:   _module.event_id,
+        price:      None,
+        time_start: time_start.clone(),
+        time_end:   time_end.clone(),
+    })
+}
+
+/// цена всего заказа; переданная клиентом цена позиции (без скидки) должна совпасть с посчитанной
+pub fn quote_order(_connection: &PgConnection, form: &Vec<OrderJson>, promo: Option<&PromoCode>) -> Result<OrderPrice, Error> {
+    let mut items = Vec::new();
+    for i in form.iter() {
+        let _item = quote_item(_connection, i)?;
+        if let Some(price) = i.price {
+            if price != _item.total {
+                return Err(Error::BadRequest(format!(
+                    "Цена {} изменилась: {} вместо {}",
+                    _item.title, _item.total, price,
+                )));
+            }
+        }
+        items.push(_item);
+    }
+    let mut price = OrderPrice::from_items(items)?;
+    if let Some(promo) = promo {
+        promo.apply(_connection, form, &mut price)?;
+    }
+    Ok(price)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hours(time_start: &str, time_end: &str) -> Result<i32, Error> {
+        billed_hours(&String::from(time_start), &String::from(time_end))
+    }
+
+    #[test]
+    fn started_hour_is_billed_in_full() {
+        assert_eq!(hours("2025-07-01 10:00:00", "2025-07-01 11:00:00"), Ok(1));
+        assert_eq!(hours("2025-07-01 10:00:00", "2025-07-01 11:01:00"), Ok(2));
+        assert!(hours("2025-07-01 10:00:00", "2025-07-01 10:00:00").is_err());
+    }
+
+    #[test]
+    fn booking_longer_than_max_days_is_rejected() {
+        assert_eq!(hours("2025-07-01 00:00:00", "2025-09-01 00:00:00"), Ok(62 * 24));
+        assert!(hours("2025-07-01 00:00:00", "2025-09-01 00:01:00").is_err());
+        assert!(hours("2025-07-01 00:00:00", "2125-07-01 00:00:00").is_err());
+    }
+
+    #[test]
+    fn price_overflow_is_an_error() {
+        assert_eq!(add_price(1000, 500), Ok(1500));
+        assert!(add_price(i32::MAX, 1).is_err());
+    }
+}
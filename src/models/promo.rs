@@ -0,0 +1,368 @@
+use crate::schema;
+use crate::schema::{promo_codes, promo_code_uses};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use crate::errors::Error;
+use crate::models::{OrderJson, OrderPrice, Permission, PlaceTz, User};
+
+
+/*
+На что действует промокод, хранится в promo_codes.scope:
+1 объект
+2 тип модуля
+3 все объекты партнёра
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PromoScope {
+    Place,
+    ModuleType,
+    Partner,
+}
+
+impl PromoScope {
+    pub fn from_code(code: i16) -> Option<PromoScope> {
+        match code {
+            1 => Some(PromoScope::Place),
+            2 => Some(PromoScope::ModuleType),
+            3 => Some(PromoScope::Partner),
+            _ => None,
+        }
+    }
+    pub fn code(&self) -> i16 {
+        match self {
+            PromoScope::Place      => 1,
+            PromoScope::ModuleType => 2,
+            PromoScope::Partner    => 3,
+        }
+    }
+}
+
+/*
+Вид скидки, хранится в promo_codes.kind:
+1 процент от суммы
+2 фиксированная сумма
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PromoKind {
+    Percent,
+    Fixed,
+}
+
+impl PromoKind {
+    pub fn from_code(code: i16) -> Option<PromoKind> {
+        match code {
+            1 => Some(PromoKind::Percent),
+            2 => Some(PromoKind::Fixed),
+            _ => None,
+        }
+    }
+    pub fn code(&self) -> i16 {
+        match self {
+            PromoKind::Percent => 1,
+            PromoKind::Fixed   => 2,
+        }
+    }
+}
+
+#[derive(Debug, Queryable, Serialize, Deserialize, Identifiable, Insertable)]
+#[table_name="promo_codes"]
+pub struct PromoCode {
+    pub id:            String,
+    pub code:          String,
+    pub scope:         i16,
+    pub scope_id:      String,
+    pub kind:          i16,
+    pub value:         i32,
+    pub min_order:     i32,
+    pub valid_from:    Option<chrono::NaiveDateTime>,
+    pub valid_to:      Option<chrono::NaiveDateTime>,
+    pub max_uses:      Option<i32>,
+    pub max_uses_user: Option<i32>,
+    pub is_active:     bool,
+    pub user_id:       String,
+    pub created:       chrono::NaiveDateTime,
+}
+
+/// valid_from / valid_to - местное время объекта (для кода на тип модуля - объекта этого типа),
+/// для кода на всех объектах партнёра - московское
+#[derive(Deserialize)]
+pub struct PromoCodeJson {
+    pub code:          String,
+    pub scope:         i16,
+    pub scope_id:      String,
+    pub kind:          i16,
+    pub value:         i32,
+    pub min_order:     Option<i32>,
+    pub valid_from:    Option<String>,
+    pub valid_to:      Option<String>,
+    pub max_uses:      Option<i32>,
+    pub max_uses_user: Option<i32>,
+}
+
+/// промокод из строки запроса: /create_order/?promo_code=SUMMER
+#[derive(Deserialize, Serialize)]
+pub struct PromoCodeParams {
+    pub promo_code: Option<String>,
+}
+
+#[derive(Debug, Queryable, Insertable)]
+#[table_name="promo_code_uses"]
+pub struct PromoCodeUse {
+    pub id:            String,
+    pub promo_code_id: String,
+    pub user_id:       String,
+    pub discount:      i32,
+    pub created:       chrono::NaiveDateTime,
+}
+
+impl PromoCode {
+    pub fn scope(&self) -> Option<PromoScope> {
+        return PromoScope::from_code(self.scope);
+    }
+    pub fn kind(&self) -> Option<PromoKind> {
+        return PromoKind::from_code(self.kind);
+    }
+
+    /// пояс, в котором задан срок действия кода
+    fn tz(_connection: &PgConnection, scope: PromoScope, scope_id: &String) -> Result<PlaceTz, Error> {
+        return match scope {
+            PromoScope::Place => PlaceTz::for_place(_connection, scope_id),
+            PromoScope::ModuleType => {
+                let place_id = schema::module_types::table
+                    .filter(schema::module_types::id.eq(scope_id))
+                    .select(schema::module_types::place_id)
+                    .first::<String>(_connection)?;
+                PlaceTz::for_place(_connection, &place_id)
+            },
+            PromoScope::Partner => Ok(PlaceTz::DEFAULT),
+        };
+    }
+    /// срок действия для ответа - в том же поясе, в котором его задавали
+    pub fn localize(mut self, _connection: &PgConnection) -> Result<PromoCode, Error> {
+        let tz = match self.scope() {
+            Some(scope) => PromoCode::tz(_connection, scope, &self.scope_id)?,
+            None => PlaceTz::DEFAULT,
+        };
+        self.valid_from = self.valid_from.map(|from| tz.to_local(from));
+        self.valid_to = self.valid_to.map(|to| tz.to_local(to));
+        Ok(self)
+    }
+
+    /// заводить коды может владелец объекта, к которому относится код
+    fn check_owner(_connection: &PgConnection, user: &User, scope: PromoScope, scope_id: &String) -> Result<(), Error> {
+        if user.role().can(Permission::ManageAnyPlace) {
+            return Ok(());
+        }
+        let allowed = match scope {
+            PromoScope::Place => user.is_partner_of_place_with_id(_connection, scope_id),
+            PromoScope::ModuleType => match schema::module_types::table
+                .filter(schema::module_types::id.eq(scope_id))
+                .select(schema::module_types::place_id)
+                .first::<String>(_connection) {
+                    Ok(place_id) => user.is_partner_of_place_with_id(_connection, &place_id),
+                    Err(_) => false,
+            },
+            PromoScope::Partner => user.is_partner() && &user.id == scope_id,
+        };
+        if !allowed {
+            return Err(Error::Forbidden);
+        }
+        Ok(())
+    }
+
+    pub fn create(_connection: &PgConnection, user: &User, form: &PromoCodeJson) -> Result<PromoCode, Error> {
+        let scope = PromoScope::from_code(form.scope)
+            .ok_or(Error::BadRequest(String::from("Неизвестная область действия промокода")))?;
+        let kind = PromoKind::from_code(form.kind)
+            .ok_or(Error::BadRequest(String::from("Неизвестный вид скидки")))?;
+        PromoCode::check_owner(_connection, user, scope, &form.scope_id)?;
+
+        let code = form.code.trim().to_uppercase();
+        if code.is_empty() || code.chars().count() > 50 {
+            return Err(Error::BadRequest(String::from("Промокод должен быть от 1 до 50 символов")));
+        }
+        if form.value <= 0 || (kind == PromoKind::Percent && form.value > 100) {
+            return Err(Error::BadRequest(String::from("Скидка должна быть больше 0, в процентах - не больше 100")));
+        }
+        if form.min_order.unwrap_or(0) < 0
+            || form.max_uses.map_or(false, |n| n <= 0)
+            || form.max_uses_user.map_or(false, |n| n <= 0) {
+            return Err(Error::BadRequest(String::from("Минимальная сумма и лимиты не могут быть отрицательными")));
+        }
+        let tz = PromoCode::tz(_connection, scope, &form.scope_id)?;
+        let valid_from = match &form.valid_from {
+            Some(value) => Some(tz.parse(value, "valid_from")?),
+            None => None,
+        };
+        let valid_to = match &form.valid_to {
+            Some(value) => Some(tz.parse(value, "valid_to")?),
+            None => None,
+        };
+        if let (Some(from), Some(to)) = (valid_from, valid_to) {
+            if to <= from {
+                return Err(Error::BadRequest(String::from("valid_to должно быть позже valid_from")));
+            }
+        }
+        if schema::promo_codes::table
+            .filter(schema::promo_codes::code.eq(&code))
+            .select(schema::promo_codes::id)
+            .first::<String>(_connection)
+            .is_ok() {
+                return Err(Error::BadRequest(String::from("Такой промокод уже существует")));
+        }
+
+        let new_code = PromoCode {
+            id:            uuid::Uuid::new_v4().to_string(),
+            code:          code,
+            scope:         scope.code(),
+            scope_id:      form.scope_id.clone(),
+            kind:          kind.code(),
+            value:         form.value,
+            min_order:     form.min_order.unwrap_or(0),
+            valid_from:    valid_from,
+            valid_to:      valid_to,
+            max_uses:      form.max_uses,
+            max_uses_user: form.max_uses_user,
+            is_active:     true,
+            user_id:       user.id.clone(),
+            created:       chrono::Utc::now().naive_utc(),
+        };
+        diesel::insert_into(schema::promo_codes::table)
+            .values(&new_code)
+            .execute(_connection)?;
+        Ok(new_code)
+    }
+    pub fn get_for_user(_connection: &PgConnection, user_id: &String) -> Result<Vec<PromoCode>, Error> {
+        return Ok(schema::promo_codes::table
+            .filter(schema::promo_codes::user_id.eq(user_id))
+            .order(schema::promo_codes::created.desc())
+            .load::<PromoCode>(_connection)?);
+    }
+    /// код не удаляется: по нему остаются применения и брони
+    pub fn disable(_connection: &PgConnection, id: &String, user: &User) -> Result<PromoCode, Error> {
+        let _code = schema::promo_codes::table
+            .filter(schema::promo_codes::id.eq(id))
+            .first::<PromoCode>(_connection)?;
+        if _code.user_id != user.id && !user.role().can(Permission::ManageAnyPlace) {
+            return Err(Error::Forbidden);
+        }
+        return Ok(diesel::update(&_code)
+            .set(schema::promo_codes::is_active.eq(false))
+            .get_result::<PromoCode>(_connection)?);
+    }
+
+    fn count_uses(&self, _connection: &PgConnection, user_id: Option<&String>) -> Result<i64, Error> {
+        let mut query = schema::promo_code_uses::table
+            .filter(schema::promo_code_uses::promo_code_id.eq(&self.id))
+            .into_boxed();
+        if let Some(user_id) = user_id {
+            query = query.filter(schema::promo_code_uses::user_id.eq(user_id));
+        }
+        return Ok(query.count().get_result::<i64>(_connection)?);
+    }
+
+    /// Действующий код: активен, в сроке и не исчерпан.
+    /// Строка кода блокируется до конца транзакции заказа, чтобы параллельные заказы не превысили лимит.
+    pub fn find_valid(_connection: &PgConnection, code: &String, user_id: Option<&String>) -> Result<PromoCode, Error> {
+        let invalid = || Error::BadRequest(String::from("Промокод не найден или больше не действует"));
+        let _code = schema::promo_codes::table
+            .filter(schema::promo_codes::code.eq(code.trim().to_uppercase()))
+            .filter(schema::promo_codes::is_active.eq(true))
+            .for_update()
+            .first::<PromoCode>(_connection)
+            .map_err(|_| invalid())?;
+
+        let now = chrono::Utc::now().naive_utc();
+        if _code.valid_from.map_or(false, |from| now < from) || _code.valid_to.map_or(false, |to| now >= to) {
+            return Err(invalid());
+        }
+        if let Some(max_uses) = _code.max_uses {
+            if _code.count_uses(_connection, None)? >= max_uses as i64 {
+                return Err(invalid());
+            }
+        }
+        if let (Some(max_uses_user), Some(user_id)) = (_code.max_uses_user, user_id) {
+            if _code.count_uses(_connection, Some(user_id))? >= max_uses_user as i64 {
+                return Err(Error::BadRequest(String::from("Вы уже использовали этот промокод")));
+            }
+        }
+        Ok(_code)
+    }
+
+    /// действует ли код на позицию заказа
+    fn covers(&self, _connection: &PgConnection, item: &OrderJson) -> Result<bool, Error> {
+        return Ok(match self.scope() {
+            Some(PromoScope::Place) => item.place_id == self.scope_id,
+            Some(PromoScope::ModuleType) => schema::modules::table
+                .filter(schema::modules::id.eq(&item.object_id))
+                .select(schema::modules::type_id)
+                .first::<String>(_connection)
+                .map_or(false, |type_id| type_id == self.scope_id),
+            Some(PromoScope::Partner) => schema::places::table
+                .filter(schema::places::id.eq(&item.place_id))
+                .select(schema::places::user_id)
+                .first::<String>(_connection)
+                .map_or(false, |user_id| user_id == self.scope_id),
+            None => false,
+        });
+    }
+
+    /// Скидка на подходящие позиции: минимальная сумма считается по ним же,
+    /// скидка делится между ними пропорционально цене.
+    pub fn apply(&self, _connection: &PgConnection, form: &Vec<OrderJson>, price: &mut OrderPrice) -> Result<(), Error> {
+        let mut covered = Vec::new();
+        for (n, i) in form.iter().enumerate() {
+            if self.covers(_connection, i)? {
+                covered.push(n);
+            }
+        }
+        let subtotal: i32 = covered.iter().map(|n| price.items[*n].total).sum();
+        if covered.is_empty() || subtotal == 0 {
+            return Err(Error::BadRequest(String::from("Промокод не действует на выбранные позиции")));
+        }
+        if subtotal < self.min_order {
+            return Err(Error::BadRequest(format!("Промокод действует на заказ от {}", self.min_order)));
+        }
+        let discount = match self.kind() {
+            Some(PromoKind::Percent) => (subtotal as i64 * self.value as i64 / 100) as i32,
+            Some(PromoKind::Fixed) => std::cmp::min(self.value, subtotal),
+            None => 0,
+        };
+
+        let mut rest = discount;
+        for (k, n) in covered.iter().enumerate() {
+            let item = &mut price.items[*n];
+            item.discount = if k + 1 == covered.len() {
+                rest
+            }
+            else {
+                (item.total as i64 * discount as i64 / subtotal as i64) as i32
+            };
+            rest -= item.discount;
+        }
+        price.promo_code = Some(self.code.clone());
+        price.discount = discount;
+        price.total -= discount;
+        Ok(())
+    }
+
+    pub fn register_use(&self, _connection: &PgConnection, user_id: &String, discount: i32) -> Result<(), Error> {
+        diesel::insert_into(schema::promo_code_uses::table)
+            .values(&PromoCodeUse {
+                id:            uuid::Uuid::new_v4().to_string(),
+                promo_code_id: self.id.clone(),
+                user_id:       user_id.clone(),
+                discount:      discount,
+                created:       chrono::Utc::now().naive_utc(),
+            })
+            .execute(_connection)?;
+        Ok(())
+    }
+}
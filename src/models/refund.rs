@@ -0,0 +1,330 @@
+use crate::schema;
+use crate::schema::{cancellation_policies, refunds};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    JoinOnDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    Connection,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use crate::errors::Error;
+use crate::models::{Order, OrderStatus, PaymentStatus, PlaceTz, User};
+use crate::utils::PaymentProvider;
+
+
+#[derive(Debug, Queryable, Serialize, Deserialize, Insertable, AsChangeset)]
+#[table_name="cancellation_policies"]
+pub struct CancellationPolicy {
+    pub place_id:    String,
+    pub free_hours:  i32,
+    pub fee_hours:   i32,
+    pub fee_percent: i16,
+    pub updated:     chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct CancellationPolicyJson {
+    pub free_hours:  i32,
+    pub fee_hours:   i32,
+    pub fee_percent: i16,
+}
+
+/// сколько вернуть и сколько удержать при отмене
+#[derive(Debug, Serialize)]
+pub struct RefundQuote {
+    pub amount: i32,
+    pub fee:    i32,
+}
+
+impl CancellationPolicy {
+    pub fn get(_connection: &PgConnection, place_id: &String) -> Result<Option<CancellationPolicy>, Error> {
+        return Ok(schema::cancellation_policies::table
+            .filter(schema::cancellation_policies::place_id.eq(place_id))
+            .first::<CancellationPolicy>(_connection)
+            .ok());
+    }
+    pub fn save(_connection: &PgConnection, place_id: &String, form: &CancellationPolicyJson) -> Result<CancellationPolicy, Error> {
+        if form.fee_hours < 0 || form.free_hours < form.fee_hours {
+            return Err(Error::BadRequest(String::from("free_hours должно быть не меньше fee_hours, fee_hours - не меньше 0")));
+        }
+        if form.fee_percent < 0 || form.fee_percent > 100 {
+            return Err(Error::BadRequest(String::from("fee_percent должен быть от 0 до 100")));
+        }
+        let policy = CancellationPolicy {
+            place_id:    place_id.clone(),
+            free_hours:  form.free_hours,
+            fee_hours:   form.fee_hours,
+            fee_percent: form.fee_percent,
+            updated:     chrono::Utc::now().naive_utc(),
+        };
+        return Ok(diesel::insert_into(schema::cancellation_policies::table)
+            .values(&policy)
+            .on_conflict(schema::cancellation_policies::place_id)
+            .do_update()
+            .set(&policy)
+            .get_result::<CancellationPolicy>(_connection)?);
+    }
+
+    /// возврат по правилам объекта; без правил - полный. time_start брони - в UTC
+    pub fn quote(policy: &Option<CancellationPolicy>, price: i32, time_start: chrono::NaiveDateTime) -> Result<RefundQuote, Error> {
+        let policy = match policy {
+            Some(policy) => policy,
+            None => return Ok(RefundQuote { amount: price, fee: 0 }),
+        };
+        let hours_left = (time_start - chrono::Utc::now().naive_utc()).num_minutes() as f64 / 60.0;
+        let fee = if hours_left >= policy.free_hours as f64 {
+            0
+        }
+        else if hours_left >= policy.fee_hours as f64 {
+            price * policy.fee_percent as i32 / 100
+        }
+        else {
+            price
+        };
+        Ok(RefundQuote {
+            amount: price - fee,
+            fee:    fee,
+        })
+    }
+}
+
+/*
+Статус возврата, хранится в refunds.status:
+1 ожидает   - запись создана вместе с отменой, шлюз ещё не вызван
+2 проведён
+3 ошибка    - шлюз отказал, возврат нужно провести вручную
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RefundStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+impl RefundStatus {
+    pub fn from_code(code: i16) -> RefundStatus {
+        match code {
+            2 => RefundStatus::Succeeded,
+            3 => RefundStatus::Failed,
+            _ => RefundStatus::Pending,
+        }
+    }
+    pub fn code(&self) -> i16 {
+        match self {
+            RefundStatus::Pending   => 1,
+            RefundStatus::Succeeded => 2,
+            RefundStatus::Failed    => 3,
+        }
+    }
+}
+
+#[derive(Debug, Queryable, Serialize, Deserialize, Insertable)]
+#[table_name="refunds"]
+pub struct Refund {
+    pub id:          String,
+    pub payment_id:  String,
+    pub order_id:    String,
+    pub amount:      i32,
+    pub fee:         i32,
+    pub status:      i16,
+    pub external_id: Option<String>,
+    pub created:     chrono::NaiveDateTime,
+}
+
+#[derive(Serialize)]
+pub struct CancelOrderJson {
+    pub order:  Order,
+    pub refund: Option<Refund>,
+}
+
+impl Refund {
+    pub fn status(&self) -> RefundStatus {
+        RefundStatus::from_code(self.status)
+    }
+    pub fn get_for_order(_connection: &PgConnection, order_id: &String) -> Result<Vec<Refund>, Error> {
+        return Ok(schema::refunds::table
+            .filter(schema::refunds::order_id.eq(order_id))
+            .order(schema::refunds::created.asc())
+            .load::<Refund>(_connection)?);
+    }
+
+    /// проведённый платёж, которым оплачена бронь
+    fn paid_with(_connection: &PgConnection, order_id: &String) -> Result<Option<(String, Option<String>)>, Error> {
+        return Ok(schema::payments::table
+            .inner_join(schema::payment_orders::table.on(schema::payment_orders::payment_id.eq(schema::payments::id)))
+            .filter(schema::payment_orders::order_id.eq(order_id))
+            .filter(schema::payments::status.eq(PaymentStatus::Succeeded.code()))
+            .select((schema::payments::id, schema::payments::external_id))
+            .first::<(String, Option<String>)>(_connection)
+            .ok());
+    }
+
+    /// Запись о возврате по отменённой брони. Создаётся в транзакции отмены;
+    /// если деньги нужно вернуть через шлюз, запись ждёт вызова Refund::send.
+    pub fn issue(
+        _connection: &PgConnection,
+        order:       &Order,
+        quote:       RefundQuote,
+    ) -> Result<Option<Refund>, Error> {
+        let (payment_id, external_id) = match Refund::paid_with(_connection, &order.id)? {
+            Some(paid) => paid,
+            None => return Ok(None),
+        };
+        let status = if external_id.is_some() && quote.amount > 0 {
+            RefundStatus::Pending
+        }
+        else {
+            RefundStatus::Succeeded
+        };
+        let new_refund = Refund {
+            id:          uuid::Uuid::new_v4().to_string(),
+            payment_id:  payment_id,
+            order_id:    order.id.clone(),
+            amount:      quote.amount,
+            fee:         quote.fee,
+            status:      status.code(),
+            external_id: None,
+            created:     chrono::Utc::now().naive_utc(),
+        };
+        diesel::insert_into(schema::refunds::table)
+            .values(&new_refund)
+            .execute(_connection)?;
+        Ok(Some(new_refund))
+    }
+
+    /// Проводит ожидающий возврат через шлюз - уже после коммита отмены, чтобы откат
+    /// транзакции не оставил у шлюза возврат без записи. Id возврата передаётся шлюзу
+    /// ключом идемпотентности, повторный вызов не вернёт деньги дважды.
+    pub fn send(_connection: &PgConnection, provider: &dyn PaymentProvider, mut refund: Refund) -> Result<Refund, Error> {
+        if refund.status() != RefundStatus::Pending {
+            return Ok(refund);
+        }
+        let external_id = schema::payments::table
+            .filter(schema::payments::id.eq(&refund.payment_id))
+            .select(schema::payments::external_id)
+            .first::<Option<String>>(_connection)?;
+        let result = match external_id {
+            Some(external_id) => provider.refund(&external_id, &refund.id, refund.amount),
+            None => Err(Error::InternalServerError(String::from("У платежа нет id шлюза"))),
+        };
+        // заглушка проводит возврат сразу; асинхронный шлюз обновит статус вебхуком
+        let (status, refund_external_id) = match result {
+            Ok(refund_external_id) => (RefundStatus::Succeeded, Some(refund_external_id)),
+            Err(err) => {
+                log::error!("refund {}: provider error: {}", refund.id, err);
+                (RefundStatus::Failed, None)
+            },
+        };
+        diesel::update(schema::refunds::table
+            .filter(schema::refunds::id.eq(&refund.id))
+            .filter(schema::refunds::status.eq(RefundStatus::Pending.code())))
+            .set((
+                schema::refunds::status.eq(status.code()),
+                schema::refunds::external_id.eq(&refund_external_id),
+            ))
+            .execute(_connection)?;
+        refund.status = status.code();
+        refund.external_id = refund_external_id;
+        Ok(refund)
+    }
+
+    /// Отмена брони с возвратом: клиент получает деньги по правилам объекта,
+    /// при отмене объектом возврат полный.
+    pub fn cancel_order(
+        _connection: &PgConnection,
+        provider:    &dyn PaymentProvider,
+        id:          &String,
+        user:        &User,
+        to:          OrderStatus,
+    ) -> Result<CancelOrderJson, Error> {
+        let (order, refund) = _connection.transaction::<_, Error, _>(|| {
+            let was_paid = schema::orders::table
+                .filter(schema::orders::id.eq(id))
+                .for_update()
+                .first::<Order>(_connection)?
+                .status() == OrderStatus::Paid;
+            let _order = Order::change_status(_connection, id, user, to)?;
+            let tz = PlaceTz::for_place(_connection, &_order.place_id)?;
+            if !was_paid {
+                return Ok((_order.localize(tz), None));
+            }
+            let quote = if to == OrderStatus::CancelledByUser {
+                let policy = CancellationPolicy::get(_connection, &_order.place_id)?;
+                CancellationPolicy::quote(&policy, _order.price, _order.time_start)?
+            }
+            else {
+                RefundQuote { amount: _order.price, fee: 0 }
+            };
+            let refund = Refund::issue(_connection, &_order, quote)?;
+            Ok((_order.localize(tz), refund))
+        })?;
+        let refund = match refund {
+            Some(refund) => Some(Refund::send(_connection, provider, refund)?),
+            None => None,
+        };
+        Ok(CancelOrderJson { order: order, refund: refund })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Option<CancellationPolicy> {
+        Some(CancellationPolicy {
+            place_id:    String::from("place"),
+            free_hours:  24,
+            fee_hours:   6,
+            fee_percent: 30,
+            updated:     chrono::Utc::now().naive_utc(),
+        })
+    }
+    /// начало брони через hours часов от текущего момента
+    fn starts_in(hours: i64) -> chrono::NaiveDateTime {
+        chrono::Utc::now().naive_utc() + chrono::Duration::hours(hours)
+    }
+
+    #[test]
+    fn without_policy_refund_is_full() {
+        let quote = CancellationPolicy::quote(&None, 1000, starts_in(1)).unwrap();
+        assert_eq!((quote.amount, quote.fee), (1000, 0));
+    }
+
+    #[test]
+    fn before_free_hours_refund_is_full() {
+        let quote = CancellationPolicy::quote(&policy(), 1000, starts_in(48)).unwrap();
+        assert_eq!((quote.amount, quote.fee), (1000, 0));
+    }
+
+    #[test]
+    fn between_fee_and_free_hours_fee_is_kept() {
+        let quote = CancellationPolicy::quote(&policy(), 1000, starts_in(12)).unwrap();
+        assert_eq!((quote.amount, quote.fee), (700, 300));
+    }
+
+    #[test]
+    fn after_fee_hours_nothing_is_refunded() {
+        let quote = CancellationPolicy::quote(&policy(), 1000, starts_in(2)).unwrap();
+        assert_eq!((quote.amount, quote.fee), (0, 1000));
+        let started = CancellationPolicy::quote(&policy(), 1000, starts_in(-1)).unwrap();
+        assert_eq!((started.amount, started.fee), (0, 1000));
+    }
+
+    #[test]
+    fn fee_is_rounded_down_in_favour_of_client() {
+        let quote = CancellationPolicy::quote(&policy(), 999, starts_in(12)).unwrap();
+        assert_eq!((quote.amount, quote.fee), (700, 299));
+        assert_eq!(quote.amount + quote.fee, 999);
+    }
+
+    #[test]
+    fn refund_status_codes_round_trip() {
+        for status in [RefundStatus::Pending, RefundStatus::Succeeded, RefundStatus::Failed].iter() {
+            assert_eq!(RefundStatus::from_code(status.code()), *status);
+        }
+    }
+}
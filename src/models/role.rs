@@ -0,0 +1,88 @@
+use serde::{Serialize, Deserialize};
+
+
+/*
+Роль пользователя, хранится в users.perm:
+1  обычный
+2  менеджер объекта
+3  подал заявку на создание объектов
+4  владелец объекта
+5  модератор сайта
+10 суперпользователь
+21 заблокированный
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Role {
+    User,
+    Manager,
+    AwaitingPartner,
+    Owner,
+    Moderator,
+    Superuser,
+    Banned,
+}
+
+/// Действия, доступ к которым зависит только от роли.
+/// Доступ к конкретному объекту (владелец / менеджер) проверяется отдельно.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Book,
+    SuggestPartner,
+    CreatePlace,
+    ViewUsers,
+    ViewStaff,
+    ManagePartners,
+    BlockUsers,
+    BlockStaff,
+    ReviewPlaces,
+    ManageGeo,
+    ManageAnyPlace,
+    ManageAuth,
+}
+
+impl Role {
+    pub fn from_perm(perm: i16) -> Role {
+        match perm {
+            2  => Role::Manager,
+            3  => Role::AwaitingPartner,
+            4  => Role::Owner,
+            5  => Role::Moderator,
+            10 => Role::Superuser,
+            21 => Role::Banned,
+            _  => Role::User,
+        }
+    }
+    pub fn perm(&self) -> i16 {
+        match self {
+            Role::User            => 1,
+            Role::Manager         => 2,
+            Role::AwaitingPartner => 3,
+            Role::Owner           => 4,
+            Role::Moderator       => 5,
+            Role::Superuser       => 10,
+            Role::Banned          => 21,
+        }
+    }
+    pub fn perms(roles: &[Role]) -> Vec<i16> {
+        roles.iter().map(|r| r.perm()).collect()
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        match self {
+            Role::Superuser => true,
+            Role::Banned    => false,
+            _ => match permission {
+                Permission::Book | Permission::SuggestPartner => true,
+                Permission::CreatePlace => *self == Role::Owner,
+                Permission::ViewUsers | Permission::BlockUsers => *self == Role::Moderator,
+                Permission::ViewStaff
+                | Permission::BlockStaff
+                | Permission::ManagePartners
+                | Permission::ReviewPlaces
+                | Permission::ManageGeo
+                | Permission::ManageAnyPlace
+                | Permission::ManageAuth => false,
+            },
+        }
+    }
+}
@@ -0,0 +1,487 @@
+use crate::schema;
+use crate::schema::{place_hours, place_seasons, place_closures};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    Connection,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use chrono::Datelike;
+use crate::errors::Error;
+use crate::models::{OrderStatus, PlaceTz, User};
+use crate::utils::get_mailer;
+
+
+#[derive(Debug, Queryable, Serialize, Deserialize, Insertable)]
+#[table_name="place_hours"]
+pub struct PlaceHours {
+    pub place_id:   String,
+    pub weekday:    i16,
+    pub time_open:  chrono::NaiveTime,
+    pub time_close: chrono::NaiveTime,
+}
+
+/// день недели 0 - понедельник; время "чч:мм", time_close "00:00" - до полуночи
+#[derive(Deserialize)]
+pub struct PlaceHoursJson {
+    pub weekday:    i16,
+    pub time_open:  String,
+    pub time_close: String,
+}
+
+#[derive(Debug, Queryable, Serialize, Deserialize, Insertable)]
+#[table_name="place_seasons"]
+pub struct PlaceSeason {
+    pub id:         String,
+    pub place_id:   String,
+    pub date_start: chrono::NaiveDate,
+    pub date_end:   chrono::NaiveDate,
+}
+
+/// даты "ГГГГ-ММ-ДД" включительно
+#[derive(Deserialize)]
+pub struct PlaceSeasonJson {
+    pub date_start: String,
+    pub date_end:   String,
+}
+
+#[derive(Debug, Queryable, Serialize, Deserialize, Identifiable, Insertable)]
+#[table_name="place_closures"]
+pub struct PlaceClosure {
+    pub id:           String,
+    pub place_id:     String,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_start:   chrono::NaiveDateTime,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_end:     chrono::NaiveDateTime,
+    pub reason:       String,
+    pub is_emergency: bool,
+    pub user_id:      String,
+    pub created:      chrono::NaiveDateTime,
+}
+
+/// время местное; is_emergency - оповестить клиентов броней, попавших в закрытие
+#[derive(Deserialize)]
+pub struct PlaceClosureJson {
+    pub time_start:   String,
+    pub time_end:     String,
+    pub reason:       String,
+    pub is_emergency: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedClosureJson {
+    pub closure:  PlaceClosure,
+    pub notified: Vec<String>,
+}
+
+/// промежуток, когда объект не работает
+#[derive(Debug, Clone, Serialize)]
+pub struct ClosedInterval {
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_start: chrono::NaiveDateTime,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_end:   chrono::NaiveDateTime,
+    pub reason:     String,
+}
+
+#[derive(Serialize)]
+pub struct PlaceScheduleJson {
+    pub hours:    Vec<PlaceHours>,
+    pub seasons:  Vec<PlaceSeason>,
+    pub closures: Vec<PlaceClosure>,
+}
+
+fn midnight() -> chrono::NaiveTime {
+    chrono::NaiveTime::from_hms_opt(0, 0, 0).unwrap()
+}
+fn parse_time(value: &str, field: &str) -> Result<chrono::NaiveTime, Error> {
+    chrono::NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|_| Error::BadRequest(format!("Поле {} должно быть в формате чч:мм", field)))
+}
+fn parse_date(value: &str, field: &str) -> Result<chrono::NaiveDate, Error> {
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| Error::BadRequest(format!("Поле {} должно быть в формате ГГГГ-ММ-ДД", field)))
+}
+
+/// Расписание объекта: часы, сезоны и закрытия, пересекающие нужный период.
+/// Всё сводится к списку промежутков закрытия в UTC.
+pub struct PlaceSchedule {
+    pub tz:       PlaceTz,
+    pub hours:    Vec<PlaceHours>,
+    pub seasons:  Vec<PlaceSeason>,
+    pub closures: Vec<PlaceClosure>,
+}
+
+impl PlaceSchedule {
+    /// сколько дней расписания можно развернуть за один запрос
+    pub const MAX_DAYS: i64 = 62;
+
+    pub fn load(_connection: &PgConnection, place_id: &String, from: chrono::NaiveDateTime, to: chrono::NaiveDateTime) -> Result<PlaceSchedule, Error> {
+        Ok(PlaceSchedule {
+            tz:       PlaceTz::for_place(_connection, place_id)?,
+            hours:    PlaceSchedule::get_hours(_connection, place_id)?,
+            seasons:  PlaceSchedule::get_seasons(_connection, place_id)?,
+            closures: schema::place_closures::table
+                .filter(schema::place_closures::place_id.eq(place_id))
+                .filter(schema::place_closures::time_start.lt(to))
+                .filter(schema::place_closures::time_end.gt(from))
+                .order(schema::place_closures::time_start.asc())
+                .load::<PlaceClosure>(_connection)?,
+        })
+    }
+    fn get_hours(_connection: &PgConnection, place_id: &String) -> Result<Vec<PlaceHours>, Error> {
+        return Ok(schema::place_hours::table
+            .filter(schema::place_hours::place_id.eq(place_id))
+            .order(schema::place_hours::weekday.asc())
+            .load::<PlaceHours>(_connection)?);
+    }
+    fn get_seasons(_connection: &PgConnection, place_id: &String) -> Result<Vec<PlaceSeason>, Error> {
+        return Ok(schema::place_seasons::table
+            .filter(schema::place_seasons::place_id.eq(place_id))
+            .order(schema::place_seasons::date_start.asc())
+            .load::<PlaceSeason>(_connection)?);
+    }
+
+    /// расписание для API: часы, сезоны и ещё не закончившиеся закрытия в местном времени
+    pub fn get_json(_connection: &PgConnection, place_id: &String) -> Result<PlaceScheduleJson, Error> {
+        let tz = PlaceTz::for_place(_connection, place_id)?;
+        let closures = schema::place_closures::table
+            .filter(schema::place_closures::place_id.eq(place_id))
+            .filter(schema::place_closures::time_end.gt(chrono::Utc::now().naive_utc()))
+            .order(schema::place_closures::time_start.asc())
+            .load::<PlaceClosure>(_connection)?
+            .into_iter()
+            .map(|c| c.localize(tz))
+            .collect();
+        Ok(PlaceScheduleJson {
+            hours:    PlaceSchedule::get_hours(_connection, place_id)?,
+            seasons:  PlaceSchedule::get_seasons(_connection, place_id)?,
+            closures: closures,
+        })
+    }
+
+    /// промежутки закрытия в [from, to), UTC, по возрастанию начала
+    pub fn closed_intervals(&self, from: chrono::NaiveDateTime, to: chrono::NaiveDateTime) -> Vec<ClosedInterval> {
+        let mut stack = Vec::new();
+        let mut push = |start: chrono::NaiveDateTime, end: chrono::NaiveDateTime, reason: &str| {
+            stack.push(ClosedInterval {
+                time_start: start,
+                time_end:   end,
+                reason:     String::from(reason),
+            });
+        };
+        let last_day = self.tz.to_local(to).date();
+        let mut day = self.tz.to_local(from).date();
+        while day <= last_day {
+            let day_start = self.tz.to_utc(day.and_time(midnight()));
+            let day_end = day_start + chrono::Duration::days(1);
+            if !self.seasons.is_empty() && !self.seasons.iter().any(|s| s.date_start <= day && day <= s.date_end) {
+                push(day_start, day_end, "Вне сезона");
+            }
+            else if !self.hours.is_empty() {
+                let weekday = day.weekday().num_days_from_monday() as i16;
+                match self.hours.iter().find(|h| h.weekday == weekday) {
+                    Some(h) => {
+                        if h.time_open > midnight() {
+                            push(day_start, self.tz.to_utc(day.and_time(h.time_open)), "Вне часов работы");
+                        }
+                        if h.time_close > midnight() {
+                            push(self.tz.to_utc(day.and_time(h.time_close)), day_end, "Вне часов работы");
+                        }
+                    },
+                    None => push(day_start, day_end, "Выходной день"),
+                }
+            }
+            day = match day.succ_opt() {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        for c in self.closures.iter() {
+            push(c.time_start, c.time_end, &c.reason);
+        }
+        stack.retain(|c| c.time_start < to && c.time_end > from);
+        stack.sort_by_key(|c| c.time_start);
+        stack
+    }
+
+    /// Бронь должна попасть в сезон и часы работы и не задеть закрытие.
+    /// Для брони на событие важны только закрытия: время события задаёт сам объект.
+    pub fn check_booking(
+        _connection: &PgConnection,
+        place_id:    &String,
+        time_start:  chrono::NaiveDateTime,
+        time_end:    chrono::NaiveDateTime,
+        is_event:    bool,
+    ) -> Result<(), Error> {
+        if (time_end - time_start).num_days() > PlaceSchedule::MAX_DAYS {
+            return Err(Error::BadRequest(format!("Бронь не может быть длиннее {} дней", PlaceSchedule::MAX_DAYS)));
+        }
+        let mut schedule = PlaceSchedule::load(_connection, place_id, time_start, time_end)?;
+        if is_event {
+            schedule.hours.clear();
+            schedule.seasons.clear();
+        }
+        if let Some(closed) = schedule.closed_intervals(time_start, time_end).first() {
+            return Err(Error::BadRequest(format!(
+                "Объект не работает с {} до {}: {}",
+                schedule.tz.format(closed.time_start),
+                schedule.tz.format(closed.time_end),
+                closed.reason,
+            )));
+        }
+        Ok(())
+    }
+
+    /// часы работы заменяются целиком
+    pub fn save_hours(_connection: &PgConnection, place_id: &String, form: &Vec<PlaceHoursJson>) -> Result<Vec<PlaceHours>, Error> {
+        let mut stack = Vec::new();
+        for i in form.iter() {
+            if i.weekday < 0 || i.weekday > 6 {
+                return Err(Error::BadRequest(String::from("weekday - от 0 (понедельник) до 6 (воскресенье)")));
+            }
+            if stack.iter().any(|h: &PlaceHours| h.weekday == i.weekday) {
+                return Err(Error::BadRequest(String::from("День недели указан дважды")));
+            }
+            let time_open = parse_time(&i.time_open, "time_open")?;
+            let time_close = parse_time(&i.time_close, "time_close")?;
+            if time_close <= time_open && time_close != midnight() {
+                return Err(Error::BadRequest(String::from("time_close должно быть позже time_open")));
+            }
+            stack.push(PlaceHours {
+                place_id:   place_id.clone(),
+                weekday:    i.weekday,
+                time_open:  time_open,
+                time_close: time_close,
+            });
+        }
+        _connection.transaction(|| {
+            diesel::delete(schema::place_hours::table.filter(schema::place_hours::place_id.eq(place_id)))
+                .execute(_connection)?;
+            diesel::insert_into(schema::place_hours::table)
+                .values(&stack)
+                .execute(_connection)?;
+            Ok::<(), Error>(())
+        })?;
+        Ok(stack)
+    }
+
+    /// сезоны заменяются целиком
+    pub fn save_seasons(_connection: &PgConnection, place_id: &String, form: &Vec<PlaceSeasonJson>) -> Result<Vec<PlaceSeason>, Error> {
+        let mut stack = Vec::new();
+        for i in form.iter() {
+            let date_start = parse_date(&i.date_start, "date_start")?;
+            let date_end = parse_date(&i.date_end, "date_end")?;
+            if date_end < date_start {
+                return Err(Error::BadRequest(String::from("date_end не может быть раньше date_start")));
+            }
+            stack.push(PlaceSeason {
+                id:         uuid::Uuid::new_v4().to_string(),
+                place_id:   place_id.clone(),
+                date_start: date_start,
+                date_end:   date_end,
+            });
+        }
+        _connection.transaction(|| {
+            diesel::delete(schema::place_seasons::table.filter(schema::place_seasons::place_id.eq(place_id)))
+                .execute(_connection)?;
+            diesel::insert_into(schema::place_seasons::table)
+                .values(&stack)
+                .execute(_connection)?;
+            Ok::<(), Error>(())
+        })?;
+        Ok(stack)
+    }
+}
+
+impl PlaceClosure {
+    pub fn get(_connection: &PgConnection, id: &String) -> Result<PlaceClosure, Error> {
+        return Ok(schema::place_closures::table
+            .filter(schema::place_closures::id.eq(id))
+            .first::<PlaceClosure>(_connection)?);
+    }
+    pub fn localize(mut self, tz: PlaceTz) -> PlaceClosure {
+        self.time_start = tz.to_local(self.time_start);
+        self.time_end = tz.to_local(self.time_end);
+        self
+    }
+
+    /// Закрытие объекта. При экстренном закрытии клиентам действующих броней
+    /// в этом промежутке уходит письмо; отменять брони - решение объекта.
+    pub fn create(_connection: &PgConnection, place_id: &String, user: &User, form: &PlaceClosureJson) -> Result<CreatedClosureJson, Error> {
+        let tz = PlaceTz::for_place(_connection, place_id)?;
+        let time_start = tz.parse(&form.time_start, "time_start")?;
+        let time_end = tz.parse(&form.time_end, "time_end")?;
+        if time_end <= time_start {
+            return Err(Error::BadRequest(String::from("Время окончания должно быть позже времени начала")));
+        }
+        if form.reason.trim().is_empty() {
+            return Err(Error::BadRequest(String::from("Укажите причину закрытия")));
+        }
+        let new_closure = PlaceClosure {
+            id:           uuid::Uuid::new_v4().to_string(),
+            place_id:     place_id.clone(),
+            time_start:   time_start,
+            time_end:     time_end,
+            reason:       form.reason.trim().to_string(),
+            is_emergency: form.is_emergency.unwrap_or(false),
+            user_id:      user.id.clone(),
+            created:      chrono::Utc::now().naive_utc(),
+        };
+        diesel::insert_into(schema::place_closures::table)
+            .values(&new_closure)
+            .execute(_connection)?;
+
+        let mut notified = Vec::new();
+        if new_closure.is_emergency {
+            let mut skip = OrderStatus::releasing_codes();
+            skip.push(OrderStatus::Completed.code());
+            let _orders = schema::orders::table
+                .filter(schema::orders::place_id.eq(place_id))
+                .filter(schema::orders::status.ne_all(skip))
+                .filter(schema::orders::time_start.lt(time_end))
+                .filter(schema::orders::time_end.gt(time_start))
+                .select((schema::orders::id, schema::orders::user_id, schema::orders::title, schema::orders::time_start))
+                .load::<(String, String, String, chrono::NaiveDateTime)>(_connection)?;
+            let mailer = get_mailer();
+            for (order_id, user_id, title, order_start) in _orders {
+                let email = schema::users::table
+                    .filter(schema::users::id.eq(&user_id))
+                    .select(schema::users::email)
+                    .first::<String>(_connection)?;
+                // письмо не дошло - закрытие всё равно действует, объект увидит бронь в списке
+                if let Err(err) = mailer.send(
+                    &email,
+                    "Объект закрыт",
+                    &format!(
+                        "Ваша бронь «{}» на {} попадает в закрытие объекта с {} до {}.\nПричина: {}",
+                        title, tz.format(order_start), tz.format(time_start), tz.format(time_end), new_closure.reason,
+                    ),
+                ) {
+                    log::error!("closure {}: order {} not notified: {}", new_closure.id, order_id, err);
+                    continue;
+                }
+                notified.push(order_id);
+            }
+        }
+        Ok(CreatedClosureJson {
+            closure:  new_closure.localize(tz),
+            notified: notified,
+        })
+    }
+    pub fn delete(_connection: &PgConnection, id: &String) -> Result<(), Error> {
+        diesel::delete(schema::place_closures::table.filter(schema::place_closures::id.eq(id)))
+            .execute(_connection)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// время в UTC
+    fn utc(day: u32, hour: u32) -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2025, 6, day).unwrap().and_hms_opt(hour, 0, 0).unwrap()
+    }
+    fn hours(weekday: i16, open: u32, close: u32) -> PlaceHours {
+        PlaceHours {
+            place_id:   String::from("place"),
+            weekday:    weekday,
+            time_open:  chrono::NaiveTime::from_hms_opt(open, 0, 0).unwrap(),
+            time_close: chrono::NaiveTime::from_hms_opt(close, 0, 0).unwrap(),
+        }
+    }
+    /// объект в Москве, UTC+3
+    fn schedule() -> PlaceSchedule {
+        PlaceSchedule {
+            tz:       PlaceTz { utc_offset: 180 },
+            hours:    Vec::new(),
+            seasons:  Vec::new(),
+            closures: Vec::new(),
+        }
+    }
+    fn spans(closed: &Vec<ClosedInterval>) -> Vec<(chrono::NaiveDateTime, chrono::NaiveDateTime, &str)> {
+        closed.iter().map(|c| (c.time_start, c.time_end, c.reason.as_str())).collect()
+    }
+
+    #[test]
+    fn without_schedule_place_is_always_open() {
+        assert!(schedule().closed_intervals(utc(1, 0), utc(30, 0)).is_empty());
+    }
+
+    #[test]
+    fn hours_are_local_to_place() {
+        // 30.06.2025 - понедельник, работа с 9 до 21 по Москве
+        let mut schedule = schedule();
+        schedule.hours.push(hours(0, 9, 21));
+        // местные сутки понедельника: 29.06 21:00 - 30.06 21:00 UTC
+        let closed = schedule.closed_intervals(utc(29, 21), utc(30, 21));
+        assert_eq!(spans(&closed), vec![
+            (utc(29, 21), utc(30, 6), "Вне часов работы"),
+            (utc(30, 18), utc(30, 21), "Вне часов работы"),
+        ]);
+    }
+
+    #[test]
+    fn close_at_midnight_means_open_till_end_of_day() {
+        let mut schedule = schedule();
+        schedule.hours.push(hours(0, 0, 0));
+        assert!(schedule.closed_intervals(utc(29, 21), utc(30, 21)).is_empty());
+    }
+
+    #[test]
+    fn day_without_hours_is_day_off_in_local_date() {
+        // 30.06 22:00 UTC - уже вторник 01:00 по Москве, а часы заданы только на понедельник
+        let mut schedule = schedule();
+        schedule.hours.push(hours(0, 0, 0));
+        let closed = schedule.closed_intervals(utc(30, 22), utc(30, 23));
+        assert_eq!(spans(&closed), vec![
+            (utc(30, 21), utc(30, 21) + chrono::Duration::days(1), "Выходной день"),
+        ]);
+    }
+
+    #[test]
+    fn days_outside_season_are_closed() {
+        let mut schedule = schedule();
+        schedule.seasons.push(PlaceSeason {
+            id:         String::from("season"),
+            place_id:   String::from("place"),
+            date_start: chrono::NaiveDate::from_ymd_opt(2025, 7, 1).unwrap(),
+            date_end:   chrono::NaiveDate::from_ymd_opt(2025, 8, 31).unwrap(),
+        });
+        let closed = schedule.closed_intervals(utc(30, 6), utc(30, 23));
+        assert_eq!(spans(&closed), vec![
+            (utc(29, 21), utc(30, 21), "Вне сезона"),
+        ]);
+    }
+
+    #[test]
+    fn closures_are_merged_sorted_and_limited_to_range() {
+        let mut schedule = schedule();
+        schedule.hours.push(hours(0, 9, 21));
+        for (start, end, reason) in vec![(utc(30, 10), utc(30, 12), "Шторм"), (utc(20, 10), utc(20, 12), "Ремонт")] {
+            schedule.closures.push(PlaceClosure {
+                id:           String::from(reason),
+                place_id:     String::from("place"),
+                time_start:   start,
+                time_end:     end,
+                reason:       String::from(reason),
+                is_emergency: false,
+                user_id:      String::from("user"),
+                created:      utc(1, 0),
+            });
+        }
+        let closed = schedule.closed_intervals(utc(29, 21), utc(30, 21));
+        assert_eq!(spans(&closed), vec![
+            (utc(29, 21), utc(30, 6), "Вне часов работы"),
+            (utc(30, 10), utc(30, 12), "Шторм"),
+            (utc(30, 18), utc(30, 21), "Вне часов работы"),
+        ]);
+    }
+}
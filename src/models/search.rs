@@ -0,0 +1,544 @@
+use crate::schema;
+use crate::schema::{amenities, place_amenities, place_reviews};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    PgTextExpressionMethods,
+    RunQueryDsl,
+    Connection,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use diesel::pg::Pg;
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Date, Double, Integer, BigInt, Numeric, Nullable};
+use std::collections::HashMap;
+use crate::errors::Error;
+use crate::models::{Place, Order, OrderStatus};
+use crate::utils::Paginated;
+
+
+#[derive(Debug, Queryable, Serialize, Deserialize, Identifiable)]
+#[table_name="amenities"]
+pub struct Amenity {
+    pub id:   i32,
+    pub name: String,
+}
+
+#[derive(Debug, Queryable, Insertable)]
+#[table_name="place_amenities"]
+pub struct PlaceAmenity {
+    pub place_id:   String,
+    pub amenity_id: i32,
+}
+
+impl Amenity {
+    pub fn get_all(_connection: &PgConnection) -> Result<Vec<Amenity>, Error> {
+        return Ok(schema::amenities::table
+            .order(schema::amenities::name.asc())
+            .load::<Amenity>(_connection)?);
+    }
+    pub fn get_for_place(_connection: &PgConnection, place_id: &String) -> Result<Vec<Amenity>, Error> {
+        let ids = schema::place_amenities::table
+            .filter(schema::place_amenities::place_id.eq(place_id))
+            .select(schema::place_amenities::amenity_id);
+        return Ok(schema::amenities::table
+            .filter(schema::amenities::id.eq_any(ids))
+            .order(schema::amenities::name.asc())
+            .load::<Amenity>(_connection)?);
+    }
+    pub fn create(_connection: &PgConnection, name: &String) -> Result<Amenity, Error> {
+        if name.trim().is_empty() {
+            return Err(Error::BadRequest(String::from("Укажите название удобства")));
+        }
+        return Ok(diesel::insert_into(schema::amenities::table)
+            .values(schema::amenities::name.eq(name.trim()))
+            .get_result::<Amenity>(_connection)?);
+    }
+    /// удобства объекта заменяются целиком
+    pub fn set_for_place(_connection: &PgConnection, place_id: &String, ids: &Vec<i32>) -> Result<Vec<Amenity>, Error> {
+        let mut ids = ids.clone();
+        ids.sort();
+        ids.dedup();
+        let known = schema::amenities::table
+            .filter(schema::amenities::id.eq_any(&ids))
+            .count()
+            .get_result::<i64>(_connection)?;
+        if known != ids.len() as i64 {
+            return Err(Error::BadRequest(String::from("Неизвестное удобство")));
+        }
+        let rows: Vec<PlaceAmenity> = ids.iter()
+            .map(|id| PlaceAmenity {
+                place_id:   place_id.clone(),
+                amenity_id: *id,
+            })
+            .collect();
+        _connection.transaction(|| {
+            diesel::delete(schema::place_amenities::table.filter(schema::place_amenities::place_id.eq(place_id)))
+                .execute(_connection)?;
+            diesel::insert_into(schema::place_amenities::table)
+                .values(&rows)
+                .execute(_connection)?;
+            Ok::<(), Error>(())
+        })?;
+        Amenity::get_for_place(_connection, place_id)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AmenityJson {
+    pub name: String,
+}
+
+
+/// Отзыв клиента о завершённой брони, из отзывов считается рейтинг объекта.
+#[derive(Debug, Queryable, Serialize, Deserialize, Identifiable, Insertable)]
+#[table_name="place_reviews"]
+pub struct PlaceReview {
+    pub id:       String,
+    pub place_id: String,
+    pub order_id: String,
+    pub user_id:  String,
+    pub rating:   i16,
+    pub text:     String,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub created:  chrono::NaiveDateTime,
+}
+
+/// rating - от 1 до 5
+#[derive(Deserialize)]
+pub struct ReviewJson {
+    pub rating: i16,
+    pub text:   Option<String>,
+}
+
+impl PlaceReview {
+    pub fn create(_connection: &PgConnection, order: &Order, form: &ReviewJson) -> Result<PlaceReview, Error> {
+        if OrderStatus::from_code(order.status) != OrderStatus::Completed {
+            return Err(Error::BadRequest(String::from("Отзыв можно оставить только по завершённой брони")));
+        }
+        if form.rating < 1 || form.rating > 5 {
+            return Err(Error::BadRequest(String::from("Оценка - от 1 до 5")));
+        }
+        let exists = schema::place_reviews::table
+            .filter(schema::place_reviews::order_id.eq(&order.id))
+            .count()
+            .get_result::<i64>(_connection)?;
+        if exists > 0 {
+            return Err(Error::BadRequest(String::from("Отзыв на эту бронь уже оставлен")));
+        }
+        let new_review = PlaceReview {
+            id:       uuid::Uuid::new_v4().to_string(),
+            place_id: order.place_id.clone(),
+            order_id: order.id.clone(),
+            user_id:  order.user_id.clone(),
+            rating:   form.rating,
+            text:     form.text.clone().unwrap_or_default().trim().to_string(),
+            created:  chrono::Utc::now().naive_utc(),
+        };
+        diesel::insert_into(schema::place_reviews::table)
+            .values(&new_review)
+            .execute(_connection)?;
+        Ok(new_review)
+    }
+    pub fn get_for_place(_connection: &PgConnection, place_id: &String, page: i32, limit: i64) -> Result<Paginated<PlaceReview>, Error> {
+        let page = page.max(1);
+        let limit = limit.max(1);
+        let total = schema::place_reviews::table
+            .filter(schema::place_reviews::place_id.eq(place_id))
+            .count()
+            .get_result::<i64>(_connection)?;
+        let items = schema::place_reviews::table
+            .filter(schema::place_reviews::place_id.eq(place_id))
+            .order(schema::place_reviews::created.desc())
+            .limit(limit)
+            .offset((page as i64 - 1) * limit)
+            .load::<PlaceReview>(_connection)?;
+        Ok(Paginated {
+            items:    items,
+            page:     page,
+            limit:    limit,
+            total:    total,
+            has_next: page as i64 * limit < total,
+        })
+    }
+}
+
+
+/// /places/search/: все фильтры необязательны.
+/// amenities - id удобств через запятую, нужны все; date - "ГГГГ-ММ-ДД", есть свободное время в этот день;
+/// price_min / price_max - по цене типов модулей; sort - rating, distance (нужны lat и lon), price
+#[derive(Deserialize)]
+pub struct PlaceSearchParams {
+    pub q:         Option<String>,
+    pub city_id:   Option<i32>,
+    pub region_id: Option<i32>,
+    pub type_id:   Option<i16>,
+    pub amenities: Option<String>,
+    pub date:      Option<String>,
+    pub price_min: Option<i32>,
+    pub price_max: Option<i32>,
+    pub sort:      Option<String>,
+    pub lat:       Option<f64>,
+    pub lon:       Option<f64>,
+    pub limit:     Option<i64>,
+}
+
+/// distance - км от lat / lon, если они переданы и у объекта есть координаты
+#[derive(Serialize)]
+pub struct PlaceSearchItem {
+    pub place:     Place,
+    pub rating:    Option<f64>,
+    pub reviews:   i64,
+    pub min_price: Option<i32>,
+    pub distance:  Option<f64>,
+}
+
+/// координаты на земле: широта от -90 до 90, долгота от -180 до 180; NaN сюда не проходит
+fn valid_cord(lat: f64, lon: f64) -> bool {
+    (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)
+}
+/// cord объекта - "широта,долгота"
+fn parse_cord(cord: &Option<String>) -> Option<(f64, f64)> {
+    let mut parts = cord.as_ref()?.split(',').map(|p| p.trim().parse::<f64>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(lat)), Some(Ok(lon))) if valid_cord(lat, lon) => Some((lat, lon)),
+        _ => None,
+    }
+}
+/// расстояние по поверхности Земли в км
+fn distance_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat2, lon2) = (to.0.to_radians(), to.1.to_radians());
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    6371.0 * 2.0 * a.sqrt().asin()
+}
+
+/// Открытые объекты по фильтрам, без сортировки. День проверяется в запросе
+/// функцией place_has_free_time - тем же правилом, что и Place::get_availability
+fn search_query(params: &PlaceSearchParams, day: Option<chrono::NaiveDate>) -> Result<schema::places::BoxedQuery<'static, Pg>, Error> {
+    let mut query = schema::places::table
+        .filter(schema::places::types.eq(1))
+        .into_boxed();
+    if let Some(q) = params.q.as_ref().map(|q| q.trim()).filter(|q| !q.is_empty()) {
+        let pattern = format!("%{}%", q.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        query = query.filter(schema::places::title.ilike(pattern));
+    }
+    if let Some(city_id) = params.city_id {
+        query = query.filter(schema::places::city_id.eq(city_id));
+    }
+    if let Some(region_id) = params.region_id {
+        query = query.filter(schema::places::city_id.eq_any(schema::cities::table
+            .filter(schema::cities::region_id.eq(region_id))
+            .select(schema::cities::id)));
+    }
+    if let Some(type_id) = params.type_id {
+        query = query.filter(schema::places::type_id.eq(type_id));
+    }
+    if let Some(amenities) = &params.amenities {
+        for id in amenities.split(',').filter(|i| !i.trim().is_empty()) {
+            let amenity_id = crate::utils::parse_field::<i32>(id.trim(), "amenities")?;
+            query = query.filter(schema::places::id.eq_any(schema::place_amenities::table
+                .filter(schema::place_amenities::amenity_id.eq(amenity_id))
+                .select(schema::place_amenities::place_id)));
+        }
+    }
+    if params.price_min.is_some() || params.price_max.is_some() {
+        let mut prices = schema::module_types::table
+            .select(schema::module_types::place_id)
+            .into_boxed();
+        if let Some(price_min) = params.price_min {
+            prices = prices.filter(schema::module_types::price.ge(price_min));
+        }
+        if let Some(price_max) = params.price_max {
+            prices = prices.filter(schema::module_types::price.le(price_max));
+        }
+        query = query.filter(schema::places::id.eq_any(prices));
+    }
+    if let Some(day) = day {
+        query = query.filter(sql::<Bool>("place_has_free_time(places.id, ")
+            .bind::<Date, _>(day)
+            .sql(")"));
+    }
+    Ok(query)
+}
+
+/// рейтинг, число отзывов, минимальная цена и расстояние - двумя запросами на все объекты страницы сразу
+fn search_items(_connection: &PgConnection, _places: Vec<Place>, origin: Option<(f64, f64)>) -> Result<Vec<PlaceSearchItem>, Error> {
+    let ids: Vec<String> = _places.iter().map(|p| p.id.clone()).collect();
+    let mut ratings: HashMap<String, (i64, i64)> = HashMap::new();
+    for (place_id, rating) in schema::place_reviews::table
+        .filter(schema::place_reviews::place_id.eq_any(&ids))
+        .select((schema::place_reviews::place_id, schema::place_reviews::rating))
+        .load::<(String, i16)>(_connection)? {
+        let entry = ratings.entry(place_id).or_insert((0, 0));
+        entry.0 += rating as i64;
+        entry.1 += 1;
+    }
+    let mut prices: HashMap<String, i32> = HashMap::new();
+    for (place_id, price) in schema::module_types::table
+        .filter(schema::module_types::place_id.eq_any(&ids))
+        .select((schema::module_types::place_id, schema::module_types::price))
+        .load::<(String, i32)>(_connection)? {
+        let entry = prices.entry(place_id).or_insert(price);
+        *entry = std::cmp::min(*entry, price);
+    }
+
+    Ok(_places.into_iter()
+        .map(|place| {
+            let (sum, count) = ratings.get(&place.id).cloned().unwrap_or((0, 0));
+            PlaceSearchItem {
+                rating:    if count > 0 { Some((sum as f64 / count as f64 * 10.0).round() / 10.0) } else { None },
+                reviews:   count,
+                min_price: prices.get(&place.id).cloned(),
+                distance:  match (origin, parse_cord(&place.cord)) {
+                    (Some(origin), Some(cord)) => Some((distance_km(origin, cord) * 10.0).round() / 10.0),
+                    _ => None,
+                },
+                place:     place,
+            }
+        })
+        .collect())
+}
+
+impl Place {
+    /// Поиск открытых объектов. Фильтры, сортировка, страница и total - в запросе;
+    /// рейтинг, цена и расстояние для ответа считаются только по объектам страницы
+    pub fn search(_connection: &PgConnection, params: &PlaceSearchParams, page: i32, limit: i64) -> Result<Paginated<PlaceSearchItem>, Error> {
+        let origin = match (params.lat, params.lon) {
+            (Some(lat), Some(lon)) if valid_cord(lat, lon) => Some((lat, lon)),
+            (Some(_), Some(_)) => return Err(Error::BadRequest(String::from("lat - от -90 до 90, lon - от -180 до 180"))),
+            (None, None) => None,
+            _ => return Err(Error::BadRequest(String::from("Укажите оба параметра lat и lon"))),
+        };
+        let day = match &params.date {
+            Some(date) => Some(chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| Error::BadRequest(String::from("Поле date должно быть в формате ГГГГ-ММ-ДД")))?),
+            None => None,
+        };
+        let query = search_query(params, day)?;
+        // объекты без рейтинга, цены или координат - в конце списка, при равенстве - по названию
+        let query = match (params.sort.as_deref().unwrap_or(""), origin) {
+            ("", _) => query.order(schema::places::title.asc()),
+            ("rating", _) => query.order((
+                sql::<Nullable<Numeric>>("(SELECT ROUND(AVG(r.rating), 1) FROM place_reviews r WHERE r.place_id = places.id) DESC NULLS LAST"),
+                sql::<BigInt>("(SELECT COUNT(*) FROM place_reviews r WHERE r.place_id = places.id) DESC"),
+                schema::places::title.asc(),
+            )),
+            ("price", _) => query.order((
+                sql::<Nullable<Integer>>("(SELECT MIN(mt.price) FROM module_types mt WHERE mt.place_id = places.id) ASC NULLS LAST"),
+                schema::places::title.asc(),
+            )),
+            ("distance", Some((lat, lon))) => query.order((
+                sql::<Nullable<Double>>("place_distance_km(places.cord, ")
+                    .bind::<Double, _>(lat)
+                    .sql(", ")
+                    .bind::<Double, _>(lon)
+                    .sql(") ASC NULLS LAST"),
+                schema::places::title.asc(),
+            )),
+            ("distance", None) => return Err(Error::BadRequest(String::from("Для сортировки по расстоянию нужны lat и lon"))),
+            _ => return Err(Error::BadRequest(String::from("sort - rating, distance или price"))),
+        };
+
+        let page = page.max(1);
+        let limit = limit.max(1);
+        let total = search_query(params, day)?
+            .count()
+            .get_result::<i64>(_connection)?;
+        let _places = query
+            .limit(limit)
+            .offset((page as i64 - 1) * limit)
+            .load::<Place>(_connection)?;
+        Ok(Paginated::new(search_items(_connection, _places, origin)?, page, limit, total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{AvailabilityParams, Module, ModuleType};
+    use crate::utils::DATETIME_FORMAT;
+
+    #[test]
+    fn coordinates_out_of_range_or_nan_are_rejected() {
+        assert!(valid_cord(44.6, 33.5));
+        assert!(valid_cord(-90.0, 180.0));
+        assert!(!valid_cord(90.1, 0.0));
+        assert!(!valid_cord(0.0, -180.5));
+        assert!(!valid_cord(f64::NAN, 0.0));
+        assert!(!valid_cord(0.0, f64::INFINITY));
+    }
+
+    #[test]
+    fn place_cord_is_parsed_only_when_valid() {
+        assert_eq!(parse_cord(&Some(String::from("44.6, 33.5"))), Some((44.6, 33.5)));
+        assert_eq!(parse_cord(&Some(String::from("NaN,33.5"))), None);
+        assert_eq!(parse_cord(&Some(String::from("144.6,33.5"))), None);
+        assert_eq!(parse_cord(&Some(String::from("44.6"))), None);
+        assert_eq!(parse_cord(&None), None);
+    }
+
+    /// база с применёнными миграциями из TEST_DATABASE_URL; без неё тест пропускается
+    fn test_connection() -> Option<PgConnection> {
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+        Some(PgConnection::establish(&url).expect("TEST_DATABASE_URL is not reachable"))
+    }
+    fn insert_place(_connection: &PgConnection, city_id: i32, title: &str, price: Option<i32>) -> Result<Place, Error> {
+        let _place = diesel::insert_into(schema::places::table)
+            .values(&Place {
+                id:         uuid::Uuid::new_v4().to_string(),
+                title:      String::from(title),
+                types:      1,
+                created:    chrono::Utc::now().naive_utc(),
+                user_id:    uuid::Uuid::new_v4().to_string(),
+                city_id:    city_id,
+                type_id:    1,
+                image:      None,
+                background: None,
+                cord:       None,
+            })
+            .get_result::<Place>(_connection)?;
+        if let Some(price) = price {
+            let type_id = uuid::Uuid::new_v4().to_string();
+            diesel::insert_into(schema::module_types::table)
+                .values(&ModuleType {
+                    id:          type_id.clone(),
+                    place_id:    _place.id.clone(),
+                    title:       String::from("Шезлонг"),
+                    description: String::new(),
+                    types:       String::new(),
+                    image:       None,
+                    price:       price,
+                })
+                .execute(_connection)?;
+            diesel::insert_into(schema::modules::table)
+                .values(&Module {
+                    id:         uuid::Uuid::new_v4().to_string(),
+                    title:      String::from("1"),
+                    label:      String::from("1"),
+                    types:      1,
+                    place_id:   _place.id.clone(),
+                    type_id:    type_id,
+                    price:      price,
+                    z_index:    0,
+                    _width:     10,
+                    _height:    10,
+                    _left:      0.0,
+                    _top:       0.0,
+                    _angle:     0.0,
+                    font_color: String::new(),
+                    font_size:  String::new(),
+                    back_color: String::new(),
+                    image:      None,
+                    event_id:   None,
+                })
+                .execute(_connection)?;
+        }
+        Ok(_place)
+    }
+    fn params(city_id: i32, sort: Option<&str>, date: Option<String>) -> PlaceSearchParams {
+        PlaceSearchParams {
+            q:         None,
+            city_id:   Some(city_id),
+            region_id: None,
+            type_id:   None,
+            amenities: None,
+            date:      date,
+            price_min: None,
+            price_max: None,
+            sort:      sort.map(String::from),
+            lat:       None,
+            lon:       None,
+            limit:     None,
+        }
+    }
+
+    #[test]
+    fn price_sort_pages_in_query() {
+        let _connection = match test_connection() {
+            Some(_connection) => _connection,
+            None => return,
+        };
+        _connection.test_transaction::<_, Error, _>(|| {
+            let city_id = -4242;
+            insert_place(&_connection, city_id, "А", Some(900))?;
+            insert_place(&_connection, city_id, "Б", None)?;
+            insert_place(&_connection, city_id, "В", Some(300))?;
+
+            let first = Place::search(&_connection, &params(city_id, Some("price"), None), 1, 2)?;
+            assert_eq!(first.total, 3);
+            assert!(first.has_next);
+            let titles: Vec<&str> = first.items.iter().map(|i| i.place.title.as_str()).collect();
+            assert_eq!(titles, vec!["В", "А"]);
+            // объект без цены - в конце, на второй странице
+            let second = Place::search(&_connection, &params(city_id, Some("price"), None), 2, 2)?;
+            assert!(!second.has_next);
+            assert_eq!(second.items[0].place.title, "Б");
+            // без отзывов и координат порядок - по названию
+            let mut by_distance = params(city_id, Some("distance"), None);
+            by_distance.lat = Some(44.6);
+            by_distance.lon = Some(33.5);
+            for sorted in vec![params(city_id, Some("rating"), None), by_distance] {
+                let found = Place::search(&_connection, &sorted, 1, 3)?;
+                let titles: Vec<&str> = found.items.iter().map(|i| i.place.title.as_str()).collect();
+                assert_eq!(titles, vec!["А", "Б", "В"]);
+            }
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn date_filter_matches_availability() {
+        let _connection = match test_connection() {
+            Some(_connection) => _connection,
+            None => return,
+        };
+        _connection.test_transaction::<_, Error, _>(|| {
+            let city_id = -4243;
+            let day = chrono::Utc::now().naive_utc().date() + chrono::Duration::days(10);
+            let free = insert_place(&_connection, city_id, "Свободный", Some(500))?;
+            let busy = insert_place(&_connection, city_id, "Занятый", Some(500))?;
+            insert_place(&_connection, city_id, "Без модулей", None)?;
+            let module_id = schema::modules::table
+                .filter(schema::modules::place_id.eq(&busy.id))
+                .select(schema::modules::id)
+                .first::<String>(&_connection)?;
+            let now = chrono::Utc::now().naive_utc();
+            diesel::insert_into(schema::orders::table)
+                .values(&Order {
+                    id:             uuid::Uuid::new_v4().to_string(),
+                    title:          String::from("test"),
+                    types:          1,
+                    place_id:       busy.id.clone(),
+                    object_id:      module_id,
+                    event_id:       None,
+                    created:        now,
+                    user_id:        uuid::Uuid::new_v4().to_string(),
+                    price:          500,
+                    time_start:     day.and_hms_opt(0, 0, 0).unwrap() - chrono::Duration::days(1),
+                    time_end:       day.and_hms_opt(0, 0, 0).unwrap() + chrono::Duration::days(2),
+                    status:         OrderStatus::Paid.code(),
+                    status_changed: now,
+                    promo_code_id:  None,
+                    discount:       0,
+                })
+                .execute(&_connection)?;
+
+            let found = Place::search(&_connection, &params(city_id, None, Some(day.format("%Y-%m-%d").to_string())), 1, 10)?;
+            assert_eq!(found.total, 1);
+            assert_eq!(found.items[0].place.id, free.id);
+            // то же решение, что и у Place::get_availability за этот день
+            let window = AvailabilityParams {
+                from: Some(day.and_hms_opt(0, 0, 0).unwrap().format(DATETIME_FORMAT).to_string()),
+                to:   Some((day + chrono::Duration::days(1)).and_hms_opt(0, 0, 0).unwrap().format(DATETIME_FORMAT).to_string()),
+            };
+            assert!(free.get_availability(&_connection, &window)?.iter().any(|m| m.next_free.is_some()));
+            assert!(!busy.get_availability(&_connection, &window)?.iter().any(|m| m.next_free.is_some()));
+            Ok(())
+        });
+    }
+}
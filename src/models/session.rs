@@ -0,0 +1,99 @@
+use crate::schema;
+use crate::schema::user_sessions;
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+};
+use serde::Serialize;
+use crate::utils::{
+    encode_session_token,
+    decode_session_token,
+};
+use diesel::PgConnection;
+use crate::errors::{Error, AuthError};
+use crate::models::User;
+use crate::vars;
+
+
+#[derive(Debug, Queryable, Serialize, Identifiable, Insertable)]
+#[table_name="user_sessions"]
+pub struct UserSession {
+    pub id:      String,
+    pub user_id: String,
+    pub created: chrono::NaiveDateTime,
+    pub expires: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize)]
+pub struct SessionTokenJson {
+    pub token:   String,
+    pub expires: chrono::NaiveDateTime,
+}
+
+impl UserSession {
+    /// открываем новую сессию и возвращаем подписанный токен для заголовка secret
+    pub fn create(_connection: &PgConnection, user_id: &String) -> Result<SessionTokenJson, Error> {
+        let created = chrono::Utc::now().naive_utc();
+        let expires = created + chrono::Duration::hours(vars::session_ttl_hours());
+
+        // заодно чистим протухшие сессии этого пользователя
+        diesel::delete (
+                schema::user_sessions::table
+                    .filter(schema::user_sessions::user_id.eq(user_id))
+                    .filter(schema::user_sessions::expires.le(created))
+            )
+            .execute(_connection)?;
+
+        let new_session = UserSession {
+            id:      uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.clone(),
+            created: created,
+            expires: expires,
+        };
+        diesel::insert_into(schema::user_sessions::table)
+            .values(&new_session)
+            .execute(_connection)?;
+
+        return Ok(SessionTokenJson {
+            token:   encode_session_token(&new_session.id, created.timestamp(), expires.timestamp()),
+            expires: expires,
+        });
+    }
+
+    /// проверяем подпись и срок токена, что сессия не отозвана, и отдаём её владельца
+    pub fn get_user(_connection: &PgConnection, token: &str) -> Result<(UserSession, User), AuthError> {
+        let claims = decode_session_token(token)?;
+        let now = chrono::Utc::now().naive_utc();
+
+        let session = schema::user_sessions::table
+            .filter(schema::user_sessions::id.eq(&claims.session_id))
+            .filter(schema::user_sessions::expires.gt(now))
+            .first::<UserSession>(_connection)
+            .map_err(|_| AuthError::AuthenticationError(String::from("Сессия не найдена")))?;
+        let user = schema::users::table
+            .filter(schema::users::id.eq(&session.user_id))
+            .first::<User>(_connection)
+            .map_err(|_| AuthError::AuthenticationError(String::from("Пользователь не найден")))?;
+        return Ok((session, user));
+    }
+
+    pub fn delete(_connection: &PgConnection, id: &String) -> Result<usize, Error> {
+        return Ok(diesel::delete (
+                schema::user_sessions::table
+                    .filter(schema::user_sessions::id.eq(id))
+            )
+            .execute(_connection)?);
+    }
+
+    /// выход со всех устройств
+    pub fn delete_all_for_user(_connection: &PgConnection, user_id: &String) -> Result<usize, Error> {
+        return Ok(diesel::delete (
+                schema::user_sessions::table
+                    .filter(schema::user_sessions::user_id.eq(user_id))
+            )
+            .execute(_connection)?);
+    }
+}
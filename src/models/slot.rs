@@ -0,0 +1,359 @@
+use crate::schema;
+use crate::schema::{slot_templates, slot_template_items};
+use crate::diesel::{
+    Queryable,
+    Insertable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+    Connection,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use crate::errors::Error;
+use crate::models::{ModuleType, OrderStatus, PlaceTz, PlaceSchedule};
+
+
+/// Шаблон слотов объекта или типа модуля, см. миграцию slot_templates.
+#[derive(Debug, Queryable, Serialize, Deserialize, Identifiable, Insertable)]
+#[table_name="slot_templates"]
+pub struct SlotTemplate {
+    pub id:             String,
+    pub place_id:       String,
+    pub module_type_id: Option<String>,
+    pub step_minutes:   Option<i32>,
+    pub created:        chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Queryable, Serialize, Deserialize, Identifiable, Insertable)]
+#[table_name="slot_template_items"]
+pub struct SlotTemplateItem {
+    pub id:          String,
+    pub template_id: String,
+    pub title:       String,
+    pub time_start:  chrono::NaiveTime,
+    pub time_end:    chrono::NaiveTime,
+    pub position:    i16,
+}
+
+/// module_type_id не указан - шаблон всего объекта; сохранение заменяет прежний шаблон
+#[derive(Deserialize)]
+pub struct SlotTemplateJson {
+    pub module_type_id: Option<String>,
+    pub step_minutes:   Option<i32>,
+    pub slots:          Option<Vec<SlotItemJson>>,
+}
+
+/// время "чч:мм", time_end "00:00" - до полуночи
+#[derive(Deserialize)]
+pub struct SlotItemJson {
+    pub title:      String,
+    pub time_start: String,
+    pub time_end:   String,
+}
+
+#[derive(Serialize)]
+pub struct SlotTemplateResp {
+    pub template: SlotTemplate,
+    pub slots:    Vec<SlotTemplateItem>,
+}
+
+/// /place/{id}/slots/?date=ГГГГ-ММ-ДД&module_type_id=...
+#[derive(Deserialize)]
+pub struct SlotParams {
+    pub date:           String,
+    pub module_type_id: Option<String>,
+}
+
+/// слот на конкретный день, время местное; remaining - сколько модулей ещё свободно
+#[derive(Serialize)]
+pub struct SlotJson {
+    pub title:      String,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_start: chrono::NaiveDateTime,
+    #[serde(with = "crate::utils::datetime_format")]
+    pub time_end:   chrono::NaiveDateTime,
+    pub capacity:   i32,
+    pub remaining:  i32,
+}
+
+fn midnight() -> chrono::NaiveTime {
+    chrono::NaiveTime::from_hms_opt(0, 0, 0).unwrap()
+}
+fn parse_time(value: &str, field: &str) -> Result<chrono::NaiveTime, Error> {
+    chrono::NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|_| Error::BadRequest(format!("Поле {} должно быть в формате чч:мм", field)))
+}
+
+impl SlotTemplateItem {
+    /// границы слота в местное время дня day
+    fn bounds(&self, day: chrono::NaiveDate) -> (chrono::NaiveDateTime, chrono::NaiveDateTime) {
+        let end = if self.time_end == midnight() {
+            day.and_time(midnight()) + chrono::Duration::days(1)
+        }
+        else {
+            day.and_time(self.time_end)
+        };
+        (day.and_time(self.time_start), end)
+    }
+}
+
+impl SlotTemplate {
+    pub fn get(_connection: &PgConnection, id: &String) -> Result<SlotTemplate, Error> {
+        return Ok(schema::slot_templates::table
+            .filter(schema::slot_templates::id.eq(id))
+            .first::<SlotTemplate>(_connection)?);
+    }
+    pub fn get_items(&self, _connection: &PgConnection) -> Result<Vec<SlotTemplateItem>, Error> {
+        return Ok(schema::slot_template_items::table
+            .filter(schema::slot_template_items::template_id.eq(&self.id))
+            .order((schema::slot_template_items::position.asc(), schema::slot_template_items::time_start.asc()))
+            .load::<SlotTemplateItem>(_connection)?);
+    }
+    pub fn get_for_place(_connection: &PgConnection, place_id: &String) -> Result<Vec<SlotTemplateResp>, Error> {
+        let _templates = schema::slot_templates::table
+            .filter(schema::slot_templates::place_id.eq(place_id))
+            .order(schema::slot_templates::created.asc())
+            .load::<SlotTemplate>(_connection)?;
+        let mut stack = Vec::new();
+        for template in _templates {
+            stack.push(SlotTemplateResp {
+                slots:    template.get_items(_connection)?,
+                template: template,
+            });
+        }
+        Ok(stack)
+    }
+
+    /// шаблон для типа модуля, а если у типа своего нет - шаблон объекта
+    pub fn find(_connection: &PgConnection, place_id: &String, module_type_id: Option<&String>) -> Result<Option<SlotTemplate>, Error> {
+        if let Some(module_type_id) = module_type_id {
+            let own = schema::slot_templates::table
+                .filter(schema::slot_templates::place_id.eq(place_id))
+                .filter(schema::slot_templates::module_type_id.eq(module_type_id))
+                .load::<SlotTemplate>(_connection)?;
+            if let Some(template) = own.into_iter().next() {
+                return Ok(Some(template));
+            }
+        }
+        return Ok(schema::slot_templates::table
+            .filter(schema::slot_templates::place_id.eq(place_id))
+            .filter(schema::slot_templates::module_type_id.is_null())
+            .load::<SlotTemplate>(_connection)?
+            .into_iter()
+            .next());
+    }
+
+    pub fn save(_connection: &PgConnection, place_id: &String, form: &SlotTemplateJson) -> Result<SlotTemplateResp, Error> {
+        if let Some(module_type_id) = &form.module_type_id {
+            if &ModuleType::get(_connection, module_type_id.clone())?.place_id != place_id {
+                return Err(Error::BadRequest(String::from("Тип модуля относится к другому объекту")));
+            }
+        }
+        let slots = form.slots.as_ref().map(|s| s.as_slice()).unwrap_or(&[]);
+        if form.step_minutes.is_none() && slots.is_empty() {
+            return Err(Error::BadRequest(String::from("Укажите шаг брони или список слотов")));
+        }
+        if let Some(step) = form.step_minutes {
+            if step < 5 || step > 1440 || 1440 % step != 0 {
+                return Err(Error::BadRequest(String::from("step_minutes - от 5 до 1440 минут, сутки должны делиться на шаг")));
+            }
+        }
+
+        let template = SlotTemplate {
+            id:             uuid::Uuid::new_v4().to_string(),
+            place_id:       place_id.clone(),
+            module_type_id: form.module_type_id.clone(),
+            step_minutes:   form.step_minutes,
+            created:        chrono::Utc::now().naive_utc(),
+        };
+        let mut items = Vec::new();
+        for (position, i) in slots.iter().enumerate() {
+            if i.title.trim().is_empty() {
+                return Err(Error::BadRequest(String::from("Укажите название слота")));
+            }
+            let time_start = parse_time(&i.time_start, "time_start")?;
+            let time_end = parse_time(&i.time_end, "time_end")?;
+            if time_end <= time_start && time_end != midnight() {
+                return Err(Error::BadRequest(String::from("time_end слота должно быть позже time_start")));
+            }
+            items.push(SlotTemplateItem {
+                id:          uuid::Uuid::new_v4().to_string(),
+                template_id: template.id.clone(),
+                title:       i.title.trim().to_string(),
+                time_start:  time_start,
+                time_end:    time_end,
+                position:    position as i16,
+            });
+        }
+
+        _connection.transaction(|| {
+            let old = SlotTemplate::find(_connection, place_id, form.module_type_id.as_ref())?;
+            if let Some(old) = old.filter(|t| t.module_type_id == form.module_type_id) {
+                SlotTemplate::delete(_connection, &old.id)?;
+            }
+            diesel::insert_into(schema::slot_templates::table)
+                .values(&template)
+                .execute(_connection)?;
+            diesel::insert_into(schema::slot_template_items::table)
+                .values(&items)
+                .execute(_connection)?;
+            Ok::<(), Error>(())
+        })?;
+        Ok(SlotTemplateResp {
+            template: template,
+            slots:    items,
+        })
+    }
+    pub fn delete(_connection: &PgConnection, id: &String) -> Result<(), Error> {
+        _connection.transaction(|| {
+            diesel::delete(schema::slot_template_items::table.filter(schema::slot_template_items::template_id.eq(id)))
+                .execute(_connection)?;
+            diesel::delete(schema::slot_templates::table.filter(schema::slot_templates::id.eq(id)))
+                .execute(_connection)?;
+            Ok(())
+        })
+    }
+
+    /// слоты на день day в местном времени: фиксированные или подряд по шагу
+    fn slots_for_day(&self, items: &[SlotTemplateItem], day: chrono::NaiveDate) -> Vec<(String, chrono::NaiveDateTime, chrono::NaiveDateTime)> {
+        if !items.is_empty() {
+            return items.iter()
+                .map(|i| {
+                    let (start, end) = i.bounds(day);
+                    (i.title.clone(), start, end)
+                })
+                .collect();
+        }
+        let mut stack = Vec::new();
+        if let Some(step) = self.step_minutes {
+            let step = chrono::Duration::minutes(step as i64);
+            let day_end = day.and_time(midnight()) + chrono::Duration::days(1);
+            let mut start = day.and_time(midnight());
+            while start < day_end {
+                stack.push((start.format("%H:%M").to_string(), start, start + step));
+                start = start + step;
+            }
+        }
+        stack
+    }
+
+    /// Бронь модуля должна совпасть с фиксированным слотом или лечь на шаг шаблона.
+    /// Время - UTC, сверяется в местном времени объекта
+    pub fn check_booking(
+        _connection: &PgConnection,
+        place_id:    &String,
+        object_id:   &String,
+        tz:          PlaceTz,
+        time_start:  chrono::NaiveDateTime,
+        time_end:    chrono::NaiveDateTime,
+    ) -> Result<(), Error> {
+        let module_type_id = schema::modules::table
+            .filter(schema::modules::id.eq(object_id))
+            .select(schema::modules::type_id)
+            .first::<String>(_connection)?;
+        let template = match SlotTemplate::find(_connection, place_id, Some(&module_type_id))? {
+            Some(template) => template,
+            None => return Ok(()),
+        };
+        let start = tz.to_local(time_start);
+        let end = tz.to_local(time_end);
+        let items = template.get_items(_connection)?;
+        if !items.is_empty() {
+            if items.iter().any(|i| i.bounds(start.date()) == (start, end)) {
+                return Ok(());
+            }
+            let allowed: Vec<String> = items.iter()
+                .map(|i| format!("{} {}-{}", i.title, i.time_start.format("%H:%M"), i.time_end.format("%H:%M")))
+                .collect();
+            return Err(Error::BadRequest(format!("Бронь должна совпадать с одним из слотов: {}", allowed.join(", "))));
+        }
+        if let Some(step) = template.step_minutes {
+            let from_midnight = (start - start.date().and_time(midnight())).num_minutes();
+            if from_midnight % step as i64 != 0 || (end - start).num_minutes() % step as i64 != 0 {
+                return Err(Error::BadRequest(format!("Начало и длительность брони должны быть кратны {} мин.", step)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Слоты дня с живой вместимостью: сколько модулей шаблона не занято бронями
+    /// и действующими холдами. Прошедшие слоты и слоты в закрытие объекта не показываются
+    pub fn get_slots(_connection: &PgConnection, place_id: &String, params: &SlotParams) -> Result<Vec<SlotJson>, Error> {
+        let day = chrono::NaiveDate::parse_from_str(&params.date, "%Y-%m-%d")
+            .map_err(|_| Error::BadRequest(String::from("Поле date должно быть в формате ГГГГ-ММ-ДД")))?;
+        let template = SlotTemplate::find(_connection, place_id, params.module_type_id.as_ref())?
+            .ok_or(Error::BadRequest(String::from("Для объекта не задан шаблон слотов")))?;
+        let tz = PlaceTz::for_place(_connection, place_id)?;
+        let slots: Vec<(String, chrono::NaiveDateTime, chrono::NaiveDateTime)> = template
+            .slots_for_day(&template.get_items(_connection)?, day)
+            .into_iter()
+            .map(|(title, start, end)| (title, tz.to_utc(start), tz.to_utc(end)))
+            .collect();
+        let from = match slots.iter().map(|s| s.1).min() {
+            Some(from) => from,
+            None => return Ok(Vec::new()),
+        };
+        let to = slots.iter().map(|s| s.2).max().unwrap();
+
+        // модули шаблона: нужного типа, а для шаблона объекта - типов без своего шаблона
+        let mut _modules = schema::modules::table
+            .filter(schema::modules::place_id.eq(place_id))
+            .filter(schema::modules::types.eq(1))
+            .filter(schema::modules::event_id.is_null())
+            .into_boxed();
+        match &template.module_type_id {
+            Some(module_type_id) => {
+                _modules = _modules.filter(schema::modules::type_id.eq(module_type_id.clone()));
+            },
+            None => {
+                let own: Vec<Option<String>> = schema::slot_templates::table
+                    .filter(schema::slot_templates::place_id.eq(place_id))
+                    .filter(schema::slot_templates::module_type_id.is_not_null())
+                    .select(schema::slot_templates::module_type_id)
+                    .load(_connection)?;
+                _modules = _modules.filter(schema::modules::type_id.ne_all(own.into_iter().flatten().collect::<Vec<String>>()));
+            },
+        }
+        let module_ids = _modules
+            .select(schema::modules::id)
+            .load::<String>(_connection)?;
+
+        let now = chrono::Utc::now().naive_utc();
+        let mut busy = schema::orders::table
+            .filter(schema::orders::place_id.eq(place_id))
+            .filter(schema::orders::object_id.eq_any(&module_ids))
+            .filter(schema::orders::status.ne_all(OrderStatus::releasing_codes()))
+            .filter(schema::orders::time_start.lt(to))
+            .filter(schema::orders::time_end.gt(from))
+            .select((schema::orders::object_id, schema::orders::time_start, schema::orders::time_end))
+            .load::<(String, chrono::NaiveDateTime, chrono::NaiveDateTime)>(_connection)?;
+        busy.extend(schema::order_holds::table
+            .filter(schema::order_holds::place_id.eq(place_id))
+            .filter(schema::order_holds::object_id.eq_any(&module_ids))
+            .filter(schema::order_holds::expires.gt(now))
+            .filter(schema::order_holds::time_start.lt(to))
+            .filter(schema::order_holds::time_end.gt(from))
+            .select((schema::order_holds::object_id, schema::order_holds::time_start, schema::order_holds::time_end))
+            .load::<(String, chrono::NaiveDateTime, chrono::NaiveDateTime)>(_connection)?);
+        let closed = PlaceSchedule::load(_connection, place_id, from, to)?.closed_intervals(from, to);
+
+        let mut stack = Vec::new();
+        for (title, start, end) in slots {
+            if start < now || closed.iter().any(|c| c.time_start < end && c.time_end > start) {
+                continue;
+            }
+            let taken = module_ids.iter()
+                .filter(|id| busy.iter().any(|(object_id, s, e)| &object_id == id && *s < end && *e > start))
+                .count();
+            stack.push(SlotJson {
+                title:      title,
+                time_start: tz.to_local(start),
+                time_end:   tz.to_local(end),
+                capacity:   module_ids.len() as i32,
+                remaining:  (module_ids.len() - taken) as i32,
+            });
+        }
+        Ok(stack)
+    }
+}
@@ -0,0 +1,76 @@
+use crate::schema;
+use crate::schema::timezones;
+use crate::diesel::{
+    Queryable,
+    QueryDsl,
+    ExpressionMethods,
+    RunQueryDsl,
+};
+use serde::{Serialize, Deserialize};
+use diesel::PgConnection;
+use crate::errors::Error;
+use crate::utils::{parse_datetime, DATETIME_FORMAT};
+
+
+#[derive(Debug, Queryable, Serialize, Deserialize, Identifiable)]
+#[table_name="timezones"]
+pub struct Timezone {
+    pub id:         i32,
+    pub name:       String,
+    pub utc_offset: i32,
+}
+
+impl Timezone {
+    pub fn get_all(_connection: &PgConnection) -> Result<Vec<Timezone>, Error> {
+        return Ok(schema::timezones::table
+            .order(schema::timezones::utc_offset.asc())
+            .load::<Timezone>(_connection)?);
+    }
+}
+
+#[derive(QueryableByName)]
+struct OffsetRow {
+    #[sql_type = "diesel::sql_types::Int4"]
+    utc_offset: i32,
+}
+
+/// Часовой пояс объекта. В базе время броней и событий хранится в UTC,
+/// в API - в местном времени объекта; перевод только через PlaceTz.
+#[derive(Debug, Clone, Copy)]
+pub struct PlaceTz {
+    pub utc_offset: i32,
+}
+
+impl PlaceTz {
+    /// московский пояс - тот же, что place_utc_offset даёт объекту без города
+    pub const DEFAULT: PlaceTz = PlaceTz { utc_offset: 180 };
+
+    /// пояс по городу и региону объекта, без него - московский (см. place_utc_offset)
+    pub fn for_place(_connection: &PgConnection, place_id: &String) -> Result<PlaceTz, Error> {
+        let row = diesel::sql_query("SELECT place_utc_offset($1) AS utc_offset")
+            .bind::<diesel::sql_types::Text, _>(place_id)
+            .get_result::<OffsetRow>(_connection)?;
+        Ok(PlaceTz { utc_offset: row.utc_offset })
+    }
+
+    fn offset(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.utc_offset as i64)
+    }
+    pub fn to_utc(&self, local: chrono::NaiveDateTime) -> chrono::NaiveDateTime {
+        local - self.offset()
+    }
+    pub fn to_local(&self, utc: chrono::NaiveDateTime) -> chrono::NaiveDateTime {
+        utc + self.offset()
+    }
+    pub fn now_local(&self) -> chrono::NaiveDateTime {
+        self.to_local(chrono::Utc::now().naive_utc())
+    }
+    /// местное время из запроса -> UTC
+    pub fn parse(&self, value: &str, field: &str) -> Result<chrono::NaiveDateTime, Error> {
+        Ok(self.to_utc(parse_datetime(value, field)?))
+    }
+    /// UTC из базы -> местное время для ответа
+    pub fn format(&self, utc: chrono::NaiveDateTime) -> String {
+        self.to_local(utc).format(DATETIME_FORMAT).to_string()
+    }
+}
@@ -2,6 +2,7 @@ use crate::schema;
 use crate::schema::{
     users,
     partners,
+    user_bans,
 };
 use rand::Rng;
 use crate::diesel::{
@@ -11,12 +12,15 @@ use crate::diesel::{
     ExpressionMethods,
     RunQueryDsl,
     Connection,
+    OptionalExtension,
 };
 use serde::{Serialize, Deserialize};
-use crate::utils::establish_connection;
-use crate::errors::Error;
+use diesel::PgConnection;
+use crate::errors::{Error, AuthError};
 use actix_web::web::Json;
-use crate::views::NewUserJson;
+use crate::views::{NewUserJson, is_valid_password, SHORT_PASSWORD};
+use crate::models::{Role, EmailVerificationToken, UserSession};
+use diesel::result::{DatabaseErrorKind, Error as DBError};
 
 
 #[derive(Debug, Queryable, Serialize, Identifiable, Insertable)]
@@ -31,6 +35,7 @@ pub struct User {
     pub level:      i16,
     pub image:      Option<String>,
     pub uuid:       Vec<u8>,
+    pub verified:   bool,
 }
 
 #[derive(Deserialize, Queryable, Serialize, Debug)]
@@ -56,10 +61,12 @@ pub struct EditOwnerPartnerJson {
 
 #[derive(Serialize)]
 pub struct OrderListJson {
+    pub id:         String,
     pub object_id:  String,
     pub price:      i32,
     pub time_start: chrono::NaiveDateTime,
     pub time_end:   chrono::NaiveDateTime,
+    pub status:     i16,
 }
 #[derive(Serialize, Queryable)]
 pub struct PlaceListJson {
@@ -75,39 +82,40 @@ pub struct RespOrderJson2 {
 }  
 
 impl User {
+    pub fn role(&self) -> Role {
+        return Role::from_perm(self.perm);
+    }
     pub fn is_superuser(&self) -> bool {
-        return self.perm == 10;
+        return self.role() == Role::Superuser;
     }
     pub fn is_await_partner(&self) -> bool {
-        return self.perm == 3;
+        return self.role() == Role::AwaitingPartner;
     }
     pub fn is_partner(&self) -> bool {
-        return self.perm == 4;
+        return self.role() == Role::Owner;
     }
-    pub fn is_partner_of_place_with_id(&self, place_id: &String) -> bool {
-        let _connection = establish_connection();
+    pub fn is_partner_of_place_with_id(&self, _connection: &PgConnection, place_id: &String) -> bool {
         return schema::places::table
             .filter(schema::places::id.eq(place_id))
+            .filter(schema::places::user_id.eq(&self.id))
             .select(schema::places::id)
-            .first::<String>(&_connection)
+            .first::<String>(_connection)
             .is_ok();
     }
-    pub fn is_manager_of_place_with_id(&self, place_id: &String) -> bool {
-        let _connection = establish_connection();
+    pub fn is_manager_of_place_with_id(&self, _connection: &PgConnection, place_id: &String) -> bool {
         return schema::place_managers::table
             .filter(schema::place_managers::place_id.eq(place_id))
             .filter(schema::place_managers::user_id.eq(self.id.clone()))
             .select(schema::place_managers::id)
-            .load::<String>(&_connection)
+            .first::<String>(_connection)
             .is_ok();
     }
     pub fn is_manager(&self) -> bool {
-        return self.perm == 2;
+        return self.role() == Role::Manager;
     }
-    pub fn get_objects(&self) -> Vec<PlaceListJson> {
-        let _connection = establish_connection();
+    pub fn get_objects(&self, _connection: &PgConnection) -> Result<Vec<PlaceListJson>, Error> {
         let uuid = hex::encode(self.uuid.clone());
-        return schema::places::table
+        return Ok(schema::places::table
             .filter(schema::places::user_id.eq(self.id.clone()))
             .or_filter(schema::places::user_id.eq(uuid))
             .order(schema::places::created.desc())
@@ -117,28 +125,24 @@ impl User {
                 schema::places::image,
                 schema::places::cord,
             )) 
-            .load::<PlaceListJson>(&_connection)
-            .expect("E");
+            .load::<PlaceListJson>(_connection)?);
     } 
-    pub fn get_orders(&self) -> Vec<RespOrderJson2> {
-        let _connection = establish_connection();
+    pub fn get_orders(&self, _connection: &PgConnection) -> Result<Vec<RespOrderJson2>, Error> {
         let uuid = hex::encode(self.uuid.clone());
 
         let list = schema::orders::table
             .filter(schema::orders::user_id.eq(self.id.clone()))
             .or_filter(schema::orders::user_id.eq(uuid))
             .order(schema::orders::created.desc())
-            .load::<crate::models::Order>(&_connection)
-            .expect("E");
+            .load::<crate::models::Order>(_connection)?;
         let mut stack = Vec::new();
         for i in list {
-            let _time_start = chrono::NaiveDateTime::parse_from_str(&i.time_start, "%Y-%m-%d %H:%M:%S").unwrap();
-            let _time_end = chrono::NaiveDateTime::parse_from_str(&i.time_end, "%Y-%m-%d %H:%M:%S").unwrap();
-            let new = chrono::Local::now().naive_utc() + chrono::Duration::hours(3);
-            //if _time_end > new {
+            let new = chrono::Utc::now().naive_utc();
+            //if i.time_end > new {
             //    continue; 
             //}
-            let _place = crate::models::Place::get_place(i.place_id.clone());
+            let _place = crate::models::Place::get_place(_connection, i.place_id.clone())?;
+            let tz = crate::models::PlaceTz::for_place(_connection, &i.place_id)?;
             let _place_item = PlaceListJson {
                 id:    _place.id.clone(),
                 title: _place.title.clone(),
@@ -146,10 +150,12 @@ impl User {
                 cord:  _place.cord.clone(),
             }; 
             let _order_item = OrderListJson {
+                id:         i.id.clone(),
                 object_id:  i.title.clone(),
                 price:      i.price,
-                time_start: _time_start,
-                time_end:   _time_end,
+                time_start: tz.to_local(i.time_start),
+                time_end:   tz.to_local(i.time_end),
+                status:     i.status,
             };
 
             stack.push(crate::models::RespOrderJson2 {
@@ -157,265 +163,419 @@ impl User {
                 place:  _place_item,
             });
         }
-        return stack;
+        return Ok(stack);
     }
-    pub fn create_manager(&self, form: Json<crate::models::PlaceManagerJson>) -> i16 {
-        let _connection = establish_connection();
+    pub fn create_manager(&self, _connection: &PgConnection, form: Json<crate::models::PlaceManagerJson>) -> Result<(), Error> {
         let place_id = form.place_id.clone();
         let user_id = form.user_id.clone();
-        let _place = crate::models::Place::get_place(place_id.clone());
-        if &self.id != &user_id {
-            return 0;
-        }
+        let _place = crate::models::Place::get_place(_connection, place_id.clone())?;
         
         let new_place_manager = crate::models::PlaceManager {
             id:       uuid::Uuid::new_v4().to_string(),
             user_id:  user_id,
             place_id: place_id,
         }; 
-        let _place_manager = diesel::insert_into(schema::place_managers::table)
+        diesel::insert_into(schema::place_managers::table)
             .values(&new_place_manager)
-            .execute(&_connection)
-            .expect("E.");
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
-    pub fn delete_manager(&self, form: Json<crate::models::PlaceManagerJson>) -> i16 {
-        let _connection = establish_connection();
+    pub fn delete_manager(&self, _connection: &PgConnection, form: Json<crate::models::PlaceManagerJson>) -> Result<(), Error> {
         let place_id = form.place_id.clone();
         let user_id = form.user_id.clone();
-        let _place = crate::models::Place::get_place(place_id.clone());
-        if self.id != user_id {
-            return 0;
-        }
+        let _place = crate::models::Place::get_place(_connection, place_id.clone())?;
         diesel::delete (
             schema::place_managers::table
                 .filter(schema::place_managers::user_id.eq(user_id))
                 .filter(schema::place_managers::place_id.eq(place_id.clone()))
             )
-            .execute(&_connection)
-            .expect("E");
-        
-        return 1;
-    }
-    pub fn create_superuser(user_id: String) -> Result<(), Error> {
-        let _connection = establish_connection();
-        _connection.transaction(|| Ok({
-            let _u = diesel::update(users::table.filter(users::id.eq(user_id)))
-                .set(schema::users::perm.eq(10))
-                .execute(&_connection); 
+            .execute(_connection)?;
+        Ok(())
+    }
+    /// Роль заблокированного не меняется: блокировка действует, пока её не снимут,
+    /// а при разблокировке восстанавливается роль, сохранённая в user_bans.
+    fn set_perm_unless_banned(_connection: &PgConnection, user_id: &String, perm: i16) -> Result<(), Error> {
+        let updated = diesel::update(users::table
+                .filter(users::id.eq(user_id))
+                .filter(users::perm.ne(Role::Banned.perm())))
+            .set(schema::users::perm.eq(perm))
+            .execute(_connection)?;
+        if updated == 0 {
+            // нет пользователя - NotFound, иначе он заблокирован
+            users::table
+                .filter(users::id.eq(user_id))
+                .select(users::id)
+                .first::<String>(_connection)?;
+            return Err(Error::Forbidden);
+        }
+        Ok(())
+    }
+    pub fn create_superuser(_connection: &PgConnection, user_id: String) -> Result<(), Error> {
+        User::set_perm_unless_banned(_connection, &user_id, Role::Superuser.perm())
+    }
+    fn new_superuser(_connection: &PgConnection, email: &String, password: &String) -> Result<User, Error> {
+        let _user = User::create(_connection, Json(NewUserJson {
+            first_name: "Admin".to_string(),
+            last_name:  "".to_string(),
+            email:      email.clone(),
+            password:   password.clone(),
         }))
+        .map_err(|err| Error::BadRequest(err.to_string()))?;
+        return Ok(diesel::update(&_user)
+            .set((
+                schema::users::perm.eq(Role::Superuser.perm()),
+                schema::users::verified.eq(true),
+            ))
+            .get_result::<User>(_connection)?);
+    }
+    /// SUPERUSER_EMAIL при старте: суперпользователь только создаётся. Зарегистрированный
+    /// на эту почту аккаунт не повышается - почту может занять кто угодно через регистрацию
+    /// или /edit_user/; повысить существующий аккаунт можно только командой create_superuser.
+    pub fn create_superuser_if_absent(_connection: &PgConnection, email: &String, password: Option<String>) -> Result<Option<User>, Error> {
+        _connection.transaction(|| {
+            match User::get_user_with_email(_connection, email) {
+                Ok(_) => return Ok(None),
+                Err(Error::NotFound(_)) => (),
+                Err(err) => return Err(err),
+            }
+            let password = password
+                .filter(|password| is_valid_password(password))
+                .ok_or(Error::BadRequest(format!("SUPERUSER_PASSWORD: {}", SHORT_PASSWORD)))?;
+            Ok(Some(User::new_superuser(_connection, email, &password)?))
+        })
+    }
+    /// Команда create_superuser: создаёт суперпользователя или повышает существующий аккаунт.
+    /// Пароль задаётся заново и все сессии аккаунта закрываются, так что войти
+    /// сможет только тот, кто запускал команду.
+    pub fn bootstrap_superuser(_connection: &PgConnection, email: &String, password: &String) -> Result<User, Error> {
+        if !is_valid_password(password) {
+            return Err(Error::BadRequest(String::from(SHORT_PASSWORD)));
+        }
+        _connection.transaction(|| {
+            let _user = match User::get_user_with_email(_connection, email) {
+                Ok(_user) => _user,
+                Err(Error::NotFound(_)) => return User::new_superuser(_connection, email, password),
+                Err(err) => return Err(err),
+            };
+            UserSession::delete_all_for_user(_connection, &_user.id)?;
+            return Ok(diesel::update(&_user)
+                .set((
+                    schema::users::perm.eq(Role::Superuser.perm()),
+                    schema::users::verified.eq(true),
+                    schema::users::password.eq(crate::utils::hash_password(password)),
+                ))
+                .get_result::<User>(_connection)?);
+        })
     }
-    pub fn edit_owner_partner(form: Json<crate::models::EditOwnerPartnerJson>) -> i16 {
-        let _connection = establish_connection();
+    pub fn edit_owner_partner(_connection: &PgConnection, form: Json<crate::models::EditOwnerPartnerJson>) -> Result<(), Error> {
         let _partner = schema::partners::table
             .filter(schema::partners::id.eq(form.id.clone()))
-            .first::<Partner>(&_connection)
-            .expect("E.");
+            .first::<Partner>(_connection)?;
         diesel::update(&_partner)
             .set(schema::partners::user_id.eq(&form.user_id.clone()))
-            .execute(&_connection)
-            .expect("E");
-        return 1;
-    }
-    pub fn delete_superuser(user_id: String) -> Result<(), Error> {
-        let _connection = establish_connection();
-        _connection.transaction(|| Ok({
-            let _u = diesel::update(users::table.filter(users::id.eq(user_id)))
-                .set(schema::users::perm.eq(1))
-                .execute(&_connection);
-        }))
+            .execute(_connection)?;
+        Ok(())
+    }
+    pub fn delete_superuser(_connection: &PgConnection, user_id: String) -> Result<(), Error> {
+        User::set_perm_unless_banned(_connection, &user_id, Role::User.perm())
     }
 
-    pub fn get_user_with_email(email: &String) -> Result<User, Error> {
-        let _connection = establish_connection();
+    pub fn get_user_with_email(_connection: &PgConnection, email: &String) -> Result<User, Error> {
         return Ok(schema::users::table
             .filter(schema::users::email.eq(email))
-            .first::<User>(&_connection)?);
+            .first::<User>(_connection)?);
     }
-    pub fn get_user_with_id(id: &String) -> Result<User, Error> {
-        let _connection = establish_connection();
+    pub fn get_user_with_id(_connection: &PgConnection, id: &String) -> Result<User, Error> {
         return Ok(schema::users::table
             .filter(schema::users::id.eq(id))
-            .first::<User>(&_connection)?);
+            .first::<User>(_connection)?);
     }
 
-    pub fn create_user_block(user_id: String) -> Result<(), Error> {
-        let _connection = establish_connection();
-        _connection.transaction(|| Ok({
-            let _u = diesel::update(users::table.filter(users::id.eq(user_id)))
-                .set(schema::users::perm.eq(21))
-                .execute(&_connection);
-        }))
+    /// блокировка с причиной и необязательным сроком; прежняя роль сохраняется в записи бана
+    pub fn create_user_block (
+        _connection: &PgConnection,
+        user_id:      String,
+        moderator_id: String,
+        reason:       String,
+        expires:      Option<chrono::NaiveDateTime>,
+    ) -> Result<(), Error> {
+        _connection.transaction(|| {
+            let _user = schema::users::table
+                .filter(schema::users::id.eq(&user_id))
+                .first::<User>(_connection)?;
+            if _user.role() == Role::Banned {
+                return Err(Error::BadRequest("Пользователь уже заблокирован".to_string()));
+            }
+            let new_ban = UserBan {
+                id:           uuid::Uuid::new_v4().to_string(),
+                user_id:      user_id.clone(),
+                moderator_id: moderator_id,
+                reason:       reason,
+                prev_perm:    _user.perm,
+                created:      chrono::Utc::now().naive_utc(),
+                expires:      expires,
+                lifted:       None,
+                lifted_by:    None,
+            };
+            diesel::insert_into(schema::user_bans::table)
+                .values(&new_ban)
+                .execute(_connection)?;
+            diesel::update(&_user)
+                .set(schema::users::perm.eq(Role::Banned.perm()))
+                .execute(_connection)?;
+            Ok(())
+        })
+    }
+    /// снимает действующий бан и возвращает роль, которая была до блокировки.
+    /// lifted_by = None - снят автоматически по истечении срока.
+    /// Без действующего бана роль не трогаем - 404
+    pub fn delete_user_block(_connection: &PgConnection, user_id: String, lifted_by: Option<String>) -> Result<(), Error> {
+        _connection.transaction(|| {
+            let _ban = UserBan::get_active(_connection, &user_id)?
+                .ok_or(Error::NotFound(String::from("У пользователя нет действующей блокировки")))?;
+            diesel::update(&_ban)
+                .set((
+                    schema::user_bans::lifted.eq(chrono::Utc::now().naive_utc()),
+                    schema::user_bans::lifted_by.eq(lifted_by),
+                ))
+                .execute(_connection)?;
+            diesel::update(users::table.filter(users::id.eq(&user_id)))
+                .set(schema::users::perm.eq(_ban.prev_perm))
+                .execute(_connection)?;
+            Ok(())
+        })
+    }
+    /// пропускает незаблокированных; истёкший бан снимает на лету
+    pub fn check_ban(self, _connection: &PgConnection) -> Result<User, AuthError> {
+        if self.role() != Role::Banned {
+            return Ok(self);
+        }
+        let _ban = UserBan::get_active(_connection, &self.id)
+            .map_err(|_| AuthError::ProcessError("Some database error occured".to_string()))?;
+        match _ban {
+            Some(_ban) if _ban.is_expired() => {
+                User::delete_user_block(_connection, self.id.clone(), None)
+                    .map_err(|_| AuthError::ProcessError("Some database error occured".to_string()))?;
+                User::get_user_with_id(_connection, &self.id)
+                    .map_err(|_| AuthError::ProcessError("Some database error occured".to_string()))
+            },
+            Some(_ban) => Err(AuthError::Banned(_ban.describe())),
+            None => Err(AuthError::Banned("Аккаунт заблокирован".to_string())),
+        }
     }
-    pub fn delete_user_block(user_id: String) -> Result<(), Error> {
-        let _connection = establish_connection();
-        _connection.transaction(|| Ok({
-            let _u = diesel::update(users::table.filter(users::id.eq(user_id)))
-                .set(schema::users::perm.eq(1))
-                .execute(&_connection);
-        }))
+    pub fn change_password(_connection: &PgConnection, user_id: &String, password: &String) -> Result<(), Error> {
+        diesel::update(users::table.filter(users::id.eq(user_id)))
+            .set(schema::users::password.eq(crate::utils::hash_password(password)))
+            .execute(_connection)?;
+        Ok(())
     }
-    pub fn change_avatar(user_id: String, image: Option<String>) -> Result<(), Error> {
-        let _connection = establish_connection();
-        _connection.transaction(|| Ok({
-            let _u = diesel::update(users::table.filter(users::id.eq(user_id)))
-                .set(schema::users::image.eq(image))
-                .execute(&_connection);
-        }))
+    pub fn verify_email(_connection: &PgConnection, email: &String) -> Result<(), Error> {
+        diesel::update(users::table.filter(users::email.eq(email)))
+            .set(schema::users::verified.eq(true))
+            .execute(_connection)?;
+        Ok(())
+    }
+    pub fn change_avatar(_connection: &PgConnection, user_id: String, image: Option<String>) -> Result<(), Error> {
+        diesel::update(users::table.filter(users::id.eq(user_id)))
+            .set(schema::users::image.eq(image))
+            .execute(_connection)?;
+        Ok(())
     }
     pub fn get_uuid(&self) -> String {
         hex::encode(self.uuid.clone())
     }
-    pub fn create(form: Json<NewUserJson>) -> User {
-        let _connection = establish_connection();
+    pub fn create(_connection: &PgConnection, form: Json<NewUserJson>) -> Result<User, AuthError> {
         let form_user = User { 
             id:         uuid::Uuid::new_v4().to_string(),
             first_name: form.first_name.clone(),
             last_name:  form.last_name.clone(),
             email:      form.email.clone(),
             password:   crate::utils::hash_password(&form.password),
-            perm:       1,
+            perm:       Role::User.perm(),
             level:      100,
             image:      None,
             uuid:       rand::thread_rng().gen::<[u8; 32]>().to_vec(),
+            verified:   false,
         };
 
-        let _new_user = diesel::insert_into(schema::users::table)
+        return Ok(diesel::insert_into(schema::users::table)
             .values(&form_user)
-            .get_result::<User>(&_connection)
-            .expect("Error saving user.");
-        
-        if _new_user.email == "ochkarik1983@mail.ru".to_string() {
-            diesel::update(&_new_user)
-                .set(schema::users::perm.eq(10))
-                .execute(&_connection)
-                .expect("Error.");
+            .get_result::<User>(_connection)?);
+    }
+    /// Смена почты снимает подтверждение: ссылка уходит на новый адрес,
+    /// неиспользованная ссылка на старый больше не действует.
+    pub fn edit(&self, _connection: &PgConnection, form: Json<EditUserJson>) -> Result<(), Error> {
+        let email_changed = form.email != self.email;
+        let duplicate = || Error::Conflict(String::from("Почта уже зарегистрирована"), Vec::new());
+        if email_changed && schema::users::table
+            .filter(schema::users::email.eq(&form.email))
+            .select(schema::users::id)
+            .first::<String>(_connection)
+            .optional()?
+            .is_some() {
+                return Err(duplicate());
         }
-        return _new_user;
-    }
-    pub fn edit(&self, form: Json<EditUserJson>) -> i16 {
-        let _connection = establish_connection();
-        diesel::update(self)
+        _connection.transaction(|| {
+            diesel::update(self)
                 .set((
                     schema::users::first_name.eq(&form.first_name.clone()),
                     schema::users::last_name.eq(form.last_name.clone()),
                     schema::users::email.eq(form.email.clone()),
+                    schema::users::verified.eq(self.verified && !email_changed),
                 ))
-                .execute(&_connection)
-                .expect("E");
-        return 1;
+                .execute(_connection)
+                // гонку двух одинаковых смен почты ловит UNIQUE(email)
+                .map_err(|err| match err {
+                    DBError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => duplicate(),
+                    err => Error::from(err),
+                })?;
+            if email_changed {
+                diesel::delete(schema::email_verification_token::table
+                    .filter(schema::email_verification_token::email.eq(&self.email)))
+                    .execute(_connection)?;
+            }
+            Ok::<(), Error>(())
+        })?;
+        if email_changed {
+            if let Err(err) = EmailVerificationToken::send(_connection, &form.email) {
+                log::error!("verification email error: {}", err);
+            }
+        }
+        Ok(())
     }
     
-    pub fn get_admins(&self) -> Json<Vec<UserJson>> {
-        let _connection = establish_connection(); 
-        if self.is_superuser() {
-            return Json(schema::users::table
-                .filter(schema::users::perm.eq(5))
-                .select((
-                    schema::users::id,
-                    schema::users::first_name,
-                    schema::users::last_name,
-                    schema::users::email,
-                    schema::users::level,
-                    schema::users::image,
-                ))
-                .load::<UserJson>(&_connection)
-                .expect("E"));
-        }
-        else {
-            return Json(Vec::new());
-        }
+    pub fn get_admins(&self, _connection: &PgConnection) -> Result<Json<Vec<UserJson>>, Error> {
+        return Ok(Json(schema::users::table
+            .filter(schema::users::perm.eq(Role::Moderator.perm()))
+            .select((
+                schema::users::id,
+                schema::users::first_name,
+                schema::users::last_name,
+                schema::users::email,
+                schema::users::perm,
+                schema::users::image,
+            ))
+            .load::<UserJson>(_connection)?));
     }
-    pub fn get_partners(&self) -> Json<Vec<UserJson>> {
-        let _connection = establish_connection();
-        if self.is_superuser() {
-            return Json(schema::users::table
-                .filter(schema::users::perm.eq(4))
-                .select((
-                    schema::users::id,
-                    schema::users::first_name,
-                    schema::users::last_name,
-                    schema::users::email,
-                    schema::users::level,
-                    schema::users::image,
-                ))
-                .load::<UserJson>(&_connection)
-                .expect("E"));
-        }
-        else {
-            return Json(Vec::new());
-        }
+    pub fn get_partners(&self, _connection: &PgConnection) -> Result<Json<Vec<UserJson>>, Error> {
+        return Ok(Json(schema::users::table
+            .filter(schema::users::perm.eq(Role::Owner.perm()))
+            .select((
+                schema::users::id,
+                schema::users::first_name,
+                schema::users::last_name,
+                schema::users::email,
+                schema::users::perm,
+                schema::users::image,
+            ))
+            .load::<UserJson>(_connection)?));
     }
 
-    pub fn is_can_work_in_object_with_id(&self, place_id: &String) -> bool {
-        let _connection = establish_connection();
-
+    pub fn is_can_work_in_object_with_id(&self, _connection: &PgConnection, place_id: &String) -> bool {
         schema::places::table
             .filter(schema::places::id.eq(place_id))
             .filter(schema::places::user_id.eq(&self.id))
             .select(schema::places::id)
-            .first::<String>(&_connection)
+            .first::<String>(_connection)
             .is_ok() || schema::place_managers::table
             .filter(schema::place_managers::place_id.eq(place_id))
             .filter(schema::place_managers::user_id.eq(&self.id))
             .select(schema::place_managers::id)
-            .first::<String>(&_connection)
+            .first::<String>(_connection)
             .is_ok() || self.is_superuser()
     }
 
-    pub fn get_partner_objects(&self) -> Json<Vec<crate::models::Place>> {
-        let _connection = establish_connection();
-        if self.perm == 10 {
-            return crate::models::Place::all();
+    pub fn get_partner_objects(&self, _connection: &PgConnection) -> Result<Json<Vec<crate::models::Place>>, Error> {
+        if self.is_superuser() {
+            return crate::models::Place::all(_connection);
         }
         else {
-            return Json(schema::places::table
+            return Ok(Json(schema::places::table
                 .filter(schema::places::user_id.eq(self.id.clone()))
                 .filter(schema::places::types.eq(1))
-                .load::<crate::models::Place>(&_connection)
-                .expect("E"));
+                .load::<crate::models::Place>(_connection)?));
         }
 
     }
-    pub fn get_users(&self) -> Json<Vec<UserJson>> {
-        let _connection = establish_connection();
-        if self.is_superuser() {
-            return Json(schema::users::table
-                .filter(schema::users::perm.lt(10))
-                .select((
-                    schema::users::id,
-                    schema::users::first_name,
-                    schema::users::last_name,
-                    schema::users::email,
-                    schema::users::perm,
-                    schema::users::image,
-                ))
-                .load::<UserJson>(&_connection)
-                .expect("E"));
-        }
-        else {
-            return Json(Vec::new());
-        }
+    pub fn get_users(&self, _connection: &PgConnection) -> Result<Json<Vec<UserJson>>, Error> {
+        return Ok(Json(schema::users::table
+            .filter(schema::users::perm.ne_all(Role::perms(&[Role::Superuser, Role::Banned])))
+            .select((
+                schema::users::id,
+                schema::users::first_name,
+                schema::users::last_name,
+                schema::users::email,
+                schema::users::perm,
+                schema::users::image,
+            ))
+            .load::<UserJson>(_connection)?));
     }
-    pub fn get_banned_users(&self) -> Json<Vec<UserJson>> {
-        let _connection = establish_connection();
-        if self.is_superuser() {
-            return Json(schema::users::table
-                .filter(schema::users::perm.gt(10))
-                .select((
-                    schema::users::id,
-                    schema::users::first_name,
-                    schema::users::last_name,
-                    schema::users::email,
-                    schema::users::level,
-                    schema::users::image,
-                ))
-                .load::<UserJson>(&_connection)
-                .expect("E"));
+    pub fn get_banned_users(&self, _connection: &PgConnection) -> Result<Json<Vec<UserJson>>, Error> {
+        return Ok(Json(schema::users::table
+            .filter(schema::users::perm.eq(Role::Banned.perm()))
+            .select((
+                schema::users::id,
+                schema::users::first_name,
+                schema::users::last_name,
+                schema::users::email,
+                schema::users::perm,
+                schema::users::image,
+            ))
+            .load::<UserJson>(_connection)?));
+    }
+}
+
+#[derive(Debug, Queryable, Serialize, Identifiable, Insertable)]
+#[table_name="user_bans"]
+pub struct UserBan {
+    pub id:           String,
+    pub user_id:      String,
+    pub moderator_id: String,
+    pub reason:       String,
+    pub prev_perm:    i16,
+    pub created:      chrono::NaiveDateTime,
+    pub expires:      Option<chrono::NaiveDateTime>,
+    pub lifted:       Option<chrono::NaiveDateTime>,
+    pub lifted_by:    Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct BlockUserJson {
+    pub id:      String,
+    pub reason:  String,
+    pub expires: Option<chrono::NaiveDateTime>,
+}
+
+impl UserBan {
+    pub fn get_active(_connection: &PgConnection, user_id: &String) -> Result<Option<UserBan>, Error> {
+        return Ok(schema::user_bans::table
+            .filter(schema::user_bans::user_id.eq(user_id))
+            .filter(schema::user_bans::lifted.is_null())
+            .order(schema::user_bans::created.desc())
+            .first::<UserBan>(_connection)
+            .optional()?);
+    }
+    pub fn get_for_user(_connection: &PgConnection, user_id: &String) -> Result<Vec<UserBan>, Error> {
+        return Ok(schema::user_bans::table
+            .filter(schema::user_bans::user_id.eq(user_id))
+            .order(schema::user_bans::created.desc())
+            .load::<UserBan>(_connection)?);
+    }
+    pub fn is_expired(&self) -> bool {
+        match self.expires {
+            Some(expires) => expires <= chrono::Utc::now().naive_utc(),
+            None => false,
         }
-        else {
-            return Json(Vec::new());
+    }
+    pub fn describe(&self) -> String {
+        let mut text = match self.expires {
+            Some(expires) => format!("Аккаунт заблокирован до {}", expires.format("%Y-%m-%d %H:%M:%S")),
+            None => "Аккаунт заблокирован".to_string(),
+        };
+        if !self.reason.is_empty() {
+            text = format!("{}: {}", text, self.reason);
         }
+        return text;
     }
 }
 
@@ -478,44 +638,38 @@ pub struct EditPartnerJson {
 }
 
 impl Partner {
-    pub fn get_owner(&self) -> UserJson {
-        let _connection = establish_connection();
-        return schema::users::table
+    pub fn get_owner(&self, _connection: &PgConnection) -> Result<UserJson, Error> {
+        return Ok(schema::users::table
             .filter(schema::users::id.eq(self.user_id.clone()))
             .select((
                 schema::users::id,
                 schema::users::first_name,
                 schema::users::last_name,
                 schema::users::email,
-                schema::users::level,
+                schema::users::perm,
                 schema::users::image,
             )) 
-            .first::<UserJson>(&_connection)
-            .expect("E");
+            .first::<UserJson>(_connection)?);
     }
-    pub fn get(id: String) -> Json<RespPartnerJson> {
-        let _connection = establish_connection();
+    pub fn get(_connection: &PgConnection, id: String) -> Result<Json<RespPartnerJson>, Error> {
         let item = schema::partners::table
             .filter(schema::partners::id.eq(id))
-            .first::<Partner>(&_connection)
-            .expect("E"); 
+            .first::<Partner>(_connection)?;
         
-        return Json(RespPartnerJson {
+        return Ok(Json(RespPartnerJson {
             id:      item.id.clone(),
             title:   item.title.clone(),
             inn:     item.inn.clone(),
             types:   item.types,
             created: item.created,
-            user:    item.get_owner(),
-        });
+            user:    item.get_owner(_connection)?,
+        }));
     }
-    pub fn get_partners() -> Json<Vec<RespPartnerJson>> {
-        let _connection = establish_connection();
+    pub fn get_partners(_connection: &PgConnection) -> Result<Json<Vec<RespPartnerJson>>, Error> {
         let mut stack = Vec::new();
         let items = schema::partners::table
             .filter(schema::partners::types.eq(1))
-            .load::<Partner>(&_connection)
-            .expect("E"); 
+            .load::<Partner>(_connection)?;
         for i in items {
             stack.push (RespPartnerJson {
                 id:      i.id.clone(),
@@ -523,18 +677,16 @@ impl Partner {
                 inn:     i.inn.clone(),
                 types:   i.types,
                 created: i.created,
-                user:    i.get_owner(),
+                user:    i.get_owner(_connection)?,
             }); 
         }
-        return Json(stack);
+        return Ok(Json(stack));
     }
-    pub fn get_suggest() -> Json<Vec<RespPartnerJson>> {
-        let _connection = establish_connection();
+    pub fn get_suggest(_connection: &PgConnection) -> Result<Json<Vec<RespPartnerJson>>, Error> {
         let mut stack = Vec::new();
         let items = schema::partners::table
             .filter(schema::partners::types.eq(0))
-            .load::<Partner>(&_connection)
-            .expect("E"); 
+            .load::<Partner>(_connection)?;
         for i in items {
             stack.push (RespPartnerJson {
                 id:      i.id.clone(),
@@ -542,14 +694,15 @@ impl Partner {
                 inn:     i.inn.clone(),
                 types:   i.types,
                 created: i.created,
-                user:    i.get_owner(),
+                user:    i.get_owner(_connection)?,
             }); 
         }
-        return Json(stack);
+        return Ok(Json(stack));
     }
-    pub fn suggest_partner(form: Json<PartnerJson>) -> i16 {
-        let _connection = establish_connection();
-        
+    pub fn suggest_partner(_connection: &PgConnection, form: Json<PartnerJson>) -> Result<(), Error> {
+        let _user = schema::users::table
+            .filter(schema::users::id.eq(form.user_id.clone()))
+            .first::<User>(_connection)?;
         let new = Partner {
             id:      uuid::Uuid::new_v4().to_string(),
             title:   form.title.clone(),
@@ -558,67 +711,180 @@ impl Partner {
             created: chrono::Local::now().naive_utc(),
             user_id: form.user_id.clone(),
         };
-        let _partner = diesel::insert_into(schema::partners::table)
-            .values(&new)
-            .execute(&_connection)
-            .expect("E.");
-        let _user = schema::users::table
-            .filter(schema::users::id.eq(form.user_id.clone()))
-            .first::<User>(&_connection)
-            .expect("E.");
-        diesel::update(&_user)
-            .set(schema::users::perm.eq(3))
-            .execute(&_connection)
-            .expect("E");
-        return 1;
-    }
-    pub fn create_partner(user_id: String) -> i16 {
-        let _connection = establish_connection();
-        
+        _connection.transaction(|| {
+            diesel::insert_into(schema::partners::table)
+                .values(&new)
+                .execute(_connection)?;
+            User::set_perm_unless_banned(_connection, &_user.id, Role::AwaitingPartner.perm())
+        })
+    }
+    pub fn create_partner(_connection: &PgConnection, user_id: String) -> Result<(), Error> {
         let _partner = schema::partners::table
             .filter(schema::partners::user_id.eq(&user_id))
             .filter(schema::partners::types.eq(0))
-            .first::<Partner>(&_connection)
-            .expect("E.");
+            .first::<Partner>(_connection)?;
         let _user = schema::users::table
             .filter(schema::users::id.eq(user_id))
-            .first::<User>(&_connection)
-            .expect("E.");
+            .first::<User>(_connection)?;
 
-        diesel::update(&_partner)
-            .set(schema::partners::types.eq(1))
-            .execute(&_connection)
-            .expect("E");
-        diesel::update(&_user)
-            .set(schema::users::perm.eq(4))
-            .execute(&_connection)
-            .expect("E");
-        return 1;
-    }
-
-    pub fn edit(id: String, form: EditPartnerJson) -> i16 {
-        let _connection = establish_connection();
+        _connection.transaction(|| {
+            diesel::update(&_partner)
+                .set(schema::partners::types.eq(1))
+                .execute(_connection)?;
+            User::set_perm_unless_banned(_connection, &_user.id, Role::Owner.perm())
+        })
+    }
+
+    pub fn edit(_connection: &PgConnection, id: String, form: EditPartnerJson) -> Result<(), Error> {
         let _partner = schema::partners::table
             .filter(schema::partners::id.eq(id))
-            .first::<Partner>(&_connection)
-            .expect("E.");
+            .first::<Partner>(_connection)?;
         diesel::update(&_partner)
             .set((
                 schema::partners::title.eq(&form.title.clone()),
                 schema::partners::inn.eq(&form.inn),
             ))
-            .execute(&_connection)
-            .expect("E");
-        return 1;
+            .execute(_connection)?;
+        Ok(())
     }
-    pub fn delete(user_id: String) -> i16 {
-        let _connection = establish_connection();
+    pub fn delete(_connection: &PgConnection, user_id: String) -> Result<(), Error> {
         diesel::delete (
             schema::partners::table
                 .filter(schema::partners::user_id.eq(&user_id))
         )
-        .execute(&_connection)
-        .expect("E");
-        return 1;
+        .execute(_connection)?;
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// база с применёнными миграциями из TEST_DATABASE_URL; без неё тест пропускается
+    fn test_connection() -> Option<PgConnection> {
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+        Some(PgConnection::establish(&url).expect("TEST_DATABASE_URL is not reachable"))
+    }
+    fn insert_user(_connection: &PgConnection, email: &str, perm: i16) -> Result<User, Error> {
+        return Ok(diesel::insert_into(schema::users::table)
+            .values(&User {
+                id:         uuid::Uuid::new_v4().to_string(),
+                first_name: String::from("Test"),
+                last_name:  String::from("User"),
+                email:      String::from(email),
+                password:   String::from("hash"),
+                perm:       perm,
+                level:      100,
+                image:      None,
+                uuid:       vec![0; 32],
+                verified:   true,
+            })
+            .get_result::<User>(_connection)?);
+    }
+    fn edit_json(email: &str) -> Json<EditUserJson> {
+        Json(EditUserJson {
+            first_name: String::from("Test"),
+            last_name:  String::from("User"),
+            email:      String::from(email),
+        })
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn changing_email_drops_verification_and_refuses_taken_address() {
+        let _connection = match test_connection() {
+            Some(_connection) => _connection,
+            None => return,
+        };
+        _connection.test_transaction::<_, Error, _>(|| {
+            let _user = insert_user(&_connection, "edit-owner@test.local", Role::User.perm())?;
+            insert_user(&_connection, "edit-taken@test.local", Role::User.perm())?;
+
+            let taken = _user.edit(&_connection, edit_json("edit-taken@test.local"));
+            assert!(matches!(taken, Err(Error::Conflict(_, _))));
+
+            _user.edit(&_connection, edit_json("edit-owner@test.local"))?;
+            assert!(User::get_user_with_email(&_connection, &String::from("edit-owner@test.local"))?.verified);
+
+            _user.edit(&_connection, edit_json("edit-new@test.local"))?;
+            let _user = User::get_user_with_email(&_connection, &String::from("edit-new@test.local"))?;
+            assert!(!_user.verified);
+            let tokens = schema::email_verification_token::table
+                .filter(schema::email_verification_token::email.eq("edit-new@test.local"))
+                .count()
+                .get_result::<i64>(&_connection)?;
+            assert_eq!(tokens, 1);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn env_bootstrap_never_promotes_existing_account() {
+        let _connection = match test_connection() {
+            Some(_connection) => _connection,
+            None => return,
+        };
+        _connection.test_transaction::<_, Error, _>(|| {
+            let email = String::from("boot-taken@test.local");
+            let _user = insert_user(&_connection, &email, Role::User.perm())?;
+            let created = User::create_superuser_if_absent(&_connection, &email, Some(String::from("secret-pass")))?;
+            assert!(created.is_none());
+            assert_eq!(User::get_user_with_email(&_connection, &email)?.perm, Role::User.perm());
+
+            let email = String::from("boot-new@test.local");
+            let short = User::create_superuser_if_absent(&_connection, &email, Some(String::from("123")));
+            assert!(matches!(short, Err(Error::BadRequest(_))));
+            let created = User::create_superuser_if_absent(&_connection, &email, Some(String::from("secret-pass")))?
+                .expect("free email creates a superuser");
+            assert_eq!(created.perm, Role::Superuser.perm());
+            assert!(created.verified);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn command_bootstrap_resets_password_and_sessions() {
+        let _connection = match test_connection() {
+            Some(_connection) => _connection,
+            None => return,
+        };
+        _connection.test_transaction::<_, Error, _>(|| {
+            let email = String::from("boot-cli@test.local");
+            let _user = insert_user(&_connection, &email, Role::User.perm())?;
+            UserSession::create(&_connection, &_user.id)?;
+            let promoted = User::bootstrap_superuser(&_connection, &email, &String::from("new-secret"))?;
+            assert_eq!(promoted.perm, Role::Superuser.perm());
+            assert_ne!(promoted.password, _user.password);
+            let sessions = schema::user_sessions::table
+                .filter(schema::user_sessions::user_id.eq(&_user.id))
+                .count()
+                .get_result::<i64>(&_connection)?;
+            assert_eq!(sessions, 0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn banned_user_role_is_not_overwritten() {
+        let _connection = match test_connection() {
+            Some(_connection) => _connection,
+            None => return,
+        };
+        _connection.test_transaction::<_, Error, _>(|| {
+            let _user = insert_user(&_connection, "banned-partner@test.local", Role::Banned.perm())?;
+            let suggested = Partner::suggest_partner(&_connection, Json(PartnerJson {
+                title:   String::from("Test"),
+                inn:     String::from("0000000000"),
+                user_id: _user.id.clone(),
+            }));
+            assert_eq!(suggested, Err(Error::Forbidden));
+            let partners = schema::partners::table
+                .filter(schema::partners::user_id.eq(&_user.id))
+                .count()
+                .get_result::<i64>(&_connection)?;
+            assert_eq!(partners, 0);
+            assert_eq!(User::create_superuser(&_connection, _user.id.clone()), Err(Error::Forbidden));
+            assert_eq!(User::get_user_with_email(&_connection, &_user.email)?.perm, Role::Banned.perm());
+            Ok(())
+        });
+    }
+}
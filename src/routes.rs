@@ -4,6 +4,7 @@ use crate::views::{
     other_views,
     users_views,
     places_views,
+    payments_views,
 };
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
@@ -12,5 +13,6 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
     .configure(other_views::other_routes)
     .configure(users_views::user_routes)
     .configure(places_views::places_routes)
+    .configure(payments_views::payments_routes)
     ;
 }
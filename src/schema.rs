@@ -1,5 +1,43 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    amenities (id) {
+        id -> Int4,
+        name -> Varchar,
+    }
+}
+
+diesel::table! {
+    auth_attempts (id) {
+        id -> Text,
+        email -> Varchar,
+        user_id -> Nullable<Varchar>,
+        ip -> Varchar,
+        user_agent -> Varchar,
+        success -> Bool,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    auth_lockouts (key) {
+        key -> Varchar,
+        failures -> Int4,
+        locked_until -> Nullable<Timestamp>,
+        updated -> Timestamp,
+    }
+}
+
+diesel::table! {
+    cancellation_policies (place_id) {
+        place_id -> Varchar,
+        free_hours -> Int4,
+        fee_hours -> Int4,
+        fee_percent -> Int2,
+        updated -> Timestamp,
+    }
+}
+
 diesel::table! {
     cities (id) {
         id -> Int4,
@@ -30,8 +68,8 @@ diesel::table! {
         types -> Int2,
         created -> Timestamp,
         price -> Int4,
-        time_start -> Varchar,
-        time_end -> Varchar,
+        time_start -> Timestamp,
+        time_end -> Timestamp,
         image -> Nullable<Varchar>,
     }
 }
@@ -45,6 +83,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    idempotency_keys (user_id, key) {
+        key -> Varchar,
+        user_id -> Varchar,
+        request_hash -> Varchar,
+        response -> Jsonb,
+        created -> Timestamp,
+    }
+}
+
 diesel::table! {
     logs (id) {
         id -> Text,
@@ -91,6 +139,31 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    order_holds (id) {
+        id -> Text,
+        user_id -> Varchar,
+        place_id -> Varchar,
+        object_id -> Varchar,
+        event_id -> Nullable<Varchar>,
+        time_start -> Timestamp,
+        time_end -> Timestamp,
+        expires -> Timestamp,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    order_status_logs (id) {
+        id -> Text,
+        order_id -> Varchar,
+        user_id -> Varchar,
+        status_from -> Int2,
+        status_to -> Int2,
+        created -> Timestamp,
+    }
+}
+
 diesel::table! {
     orders (id) {
         id -> Text,
@@ -102,8 +175,12 @@ diesel::table! {
         created -> Timestamp,
         user_id -> Varchar,
         price -> Int4,
-        time_start -> Varchar,
-        time_end -> Varchar,
+        time_start -> Timestamp,
+        time_end -> Timestamp,
+        status -> Int2,
+        status_changed -> Timestamp,
+        promo_code_id -> Nullable<Varchar>,
+        discount -> Int4,
     }
 }
 
@@ -118,6 +195,64 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    password_reset_tokens (id) {
+        id -> Bytea,
+        user_id -> Varchar,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    payment_orders (payment_id, order_id) {
+        payment_id -> Varchar,
+        order_id -> Varchar,
+    }
+}
+
+diesel::table! {
+    payments (id) {
+        id -> Text,
+        user_id -> Varchar,
+        provider -> Varchar,
+        external_id -> Nullable<Varchar>,
+        amount -> Int4,
+        status -> Int2,
+        created -> Timestamp,
+        updated -> Timestamp,
+    }
+}
+
+diesel::table! {
+    place_amenities (place_id, amenity_id) {
+        place_id -> Varchar,
+        amenity_id -> Int4,
+    }
+}
+
+diesel::table! {
+    place_closures (id) {
+        id -> Text,
+        place_id -> Varchar,
+        time_start -> Timestamp,
+        time_end -> Timestamp,
+        reason -> Varchar,
+        is_emergency -> Bool,
+        user_id -> Varchar,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    place_hours (place_id, weekday) {
+        place_id -> Varchar,
+        weekday -> Int2,
+        time_open -> Time,
+        time_close -> Time,
+    }
+}
+
 diesel::table! {
     place_managers (id) {
         id -> Text,
@@ -126,6 +261,27 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    place_reviews (id) {
+        id -> Text,
+        place_id -> Varchar,
+        order_id -> Varchar,
+        user_id -> Varchar,
+        rating -> Int2,
+        text -> Varchar,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    place_seasons (id) {
+        id -> Text,
+        place_id -> Varchar,
+        date_start -> Date,
+        date_end -> Date,
+    }
+}
+
 diesel::table! {
     place_types (id) {
         id -> Text,
@@ -148,6 +304,67 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    price_rules (id) {
+        id -> Text,
+        module_type_id -> Varchar,
+        title -> Varchar,
+        priority -> Int4,
+        kind -> Int2,
+        price -> Int4,
+        weekdays -> Int2,
+        time_from -> Nullable<Time>,
+        time_to -> Nullable<Time>,
+        date_from -> Nullable<Date>,
+        date_to -> Nullable<Date>,
+        min_hours -> Nullable<Int4>,
+        max_hours -> Nullable<Int4>,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    promo_code_uses (id) {
+        id -> Text,
+        promo_code_id -> Varchar,
+        user_id -> Varchar,
+        discount -> Int4,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    promo_codes (id) {
+        id -> Text,
+        code -> Varchar,
+        scope -> Int2,
+        scope_id -> Varchar,
+        kind -> Int2,
+        value -> Int4,
+        min_order -> Int4,
+        valid_from -> Nullable<Timestamp>,
+        valid_to -> Nullable<Timestamp>,
+        max_uses -> Nullable<Int4>,
+        max_uses_user -> Nullable<Int4>,
+        is_active -> Bool,
+        user_id -> Varchar,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    refunds (id) {
+        id -> Text,
+        payment_id -> Varchar,
+        order_id -> Varchar,
+        amount -> Int4,
+        fee -> Int4,
+        status -> Int2,
+        external_id -> Nullable<Varchar>,
+        created -> Timestamp,
+    }
+}
+
 diesel::table! {
     regions (id) {
         id -> Int4,
@@ -159,6 +376,27 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    slot_template_items (id) {
+        id -> Text,
+        template_id -> Varchar,
+        title -> Varchar,
+        time_start -> Time,
+        time_end -> Time,
+        position -> Int2,
+    }
+}
+
+diesel::table! {
+    slot_templates (id) {
+        id -> Text,
+        place_id -> Varchar,
+        module_type_id -> Nullable<Varchar>,
+        step_minutes -> Nullable<Int4>,
+        created -> Timestamp,
+    }
+}
+
 diesel::table! {
     times (id) {
         id -> Text,
@@ -166,6 +404,37 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    timezones (id) {
+        id -> Int4,
+        name -> Varchar,
+        utc_offset -> Int4,
+    }
+}
+
+diesel::table! {
+    user_bans (id) {
+        id -> Text,
+        user_id -> Varchar,
+        moderator_id -> Varchar,
+        reason -> Varchar,
+        prev_perm -> Int2,
+        created -> Timestamp,
+        expires -> Nullable<Timestamp>,
+        lifted -> Nullable<Timestamp>,
+        lifted_by -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    user_sessions (id) {
+        id -> Text,
+        user_id -> Varchar,
+        created -> Timestamp,
+        expires -> Timestamp,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Text,
@@ -177,23 +446,48 @@ diesel::table! {
         level -> Int2,
         image -> Nullable<Varchar>,
         uuid -> Bytea,
+        verified -> Bool,
     }
 }
 
 diesel::allow_tables_to_appear_in_same_query!(
+    amenities,
+    auth_attempts,
+    auth_lockouts,
+    cancellation_policies,
     cities,
     email_verification_token,
     events,
     feedbacks,
+    idempotency_keys,
     logs,
     module_types,
     modules,
+    order_holds,
+    order_status_logs,
     orders,
     partners,
+    password_reset_tokens,
+    payment_orders,
+    payments,
+    place_amenities,
+    place_closures,
+    place_hours,
     place_managers,
+    place_reviews,
+    place_seasons,
     place_types,
     places,
+    price_rules,
+    promo_code_uses,
+    promo_codes,
+    refunds,
     regions,
+    slot_template_items,
+    slot_templates,
     times,
+    timezones,
+    user_bans,
+    user_sessions,
     users,
 );
@@ -3,11 +3,15 @@ use actix_web::{
   http::header::CONTENT_TYPE,
   HttpRequest,
 };
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 use crate::{errors::AuthError, vars};
 
+type HmacSha256 = Hmac<Sha256>;
+
 
 pub fn hash_password(password: &str) -> String {
-  Hasher::default() 
+  Hasher::default()
       .with_password(password)
       .with_secret_key(vars::secret_key().as_str())
       .hash()
@@ -32,3 +36,47 @@ pub fn is_json_request(req: &HttpRequest) -> bool {
         |header| header.to_str().map_or(false, |content_type| "application/json" == content_type)
       )
 }
+
+/// Данные, зашитые в токен сессии: `<session_id>.<issued>.<expires>.<signature>`.
+/// issued / expires - unix timestamp (UTC), signature - HMAC-SHA256 от первых трёх частей.
+#[derive(Debug)]
+pub struct SessionClaims {
+  pub session_id: String,
+  pub issued:     i64,
+  pub expires:    i64,
+}
+
+fn session_mac(payload: &str) -> HmacSha256 {
+  let mut mac = HmacSha256::new_from_slice(vars::secret_key().as_bytes())
+      .expect("HMAC accepts keys of any size");
+  mac.update(payload.as_bytes());
+  mac
+}
+
+pub fn encode_session_token(session_id: &str, issued: i64, expires: i64) -> String {
+  let payload = format!("{}.{}.{}", session_id, issued, expires);
+  let signature = hex::encode(session_mac(&payload).finalize().into_bytes());
+  format!("{}.{}", payload, signature)
+}
+
+pub fn decode_session_token(token: &str) -> Result<SessionClaims, AuthError> {
+  let invalid = || AuthError::AuthenticationError(String::from("Недействительный токен"));
+
+  let (payload, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+  let signature = hex::decode(signature).map_err(|_| invalid())?;
+  session_mac(payload)
+      .verify_slice(&signature)
+      .map_err(|_| invalid())?;
+
+  let mut parts = payload.split('.');
+  let session_id = parts.next().ok_or_else(invalid)?.to_string();
+  let issued: i64 = parts.next().and_then(|i| i.parse().ok()).ok_or_else(invalid)?;
+  let expires: i64 = parts.next().and_then(|i| i.parse().ok()).ok_or_else(invalid)?;
+  if parts.next().is_some() {
+    return Err(invalid());
+  }
+  if expires <= chrono::Utc::now().timestamp() {
+    return Err(AuthError::AuthenticationError(String::from("Срок действия токена истёк")));
+  }
+  Ok(SessionClaims { session_id, issued, expires })
+}
@@ -0,0 +1,49 @@
+use std::io;
+use crate::models::User;
+use crate::vars;
+use crate::utils::{establish_connection, Pool};
+
+
+/// `beaches_back create_superuser <email> [password]`
+/// Создаёт суперпользователя или повышает существующий аккаунт, задавая ему новый пароль.
+/// Пароль обязателен; вместо аргумента можно задать SUPERUSER_PASSWORD,
+/// чтобы он не попадал в историю shell. Результат - в лог (RUST_LOG=info).
+pub fn create_superuser_command(args: &[String]) -> io::Result<()> {
+    let email = match args.get(0) {
+        Some(email) => email,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "usage: beaches_back create_superuser <email> [password]",
+            ));
+        },
+    };
+    let password = args.get(1).cloned().or_else(vars::superuser_password).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "password is required: pass it as an argument or set SUPERUSER_PASSWORD",
+    ))?;
+
+    match User::bootstrap_superuser(&establish_connection(), email, &password) {
+        Ok(_user) => {
+            log::info!("superuser: {} ({})", _user.email, _user.id);
+            Ok(())
+        },
+        Err(err) => {
+            log::error!("create_superuser error: {}", err);
+            Err(io::Error::new(io::ErrorKind::Other, err.to_string()))
+        },
+    }
+}
+
+/// При старте сервера создаём суперпользователя из SUPERUSER_EMAIL, если такой почты ещё нет.
+/// Существующий аккаунт не трогаем: повышение - только командой create_superuser.
+pub fn bootstrap_superuser_from_env(pool: &Pool) {
+    if let Some(email) = vars::superuser_email() {
+        let _connection = pool.get().expect("Error getting connection from pool");
+        match User::create_superuser_if_absent(&_connection, &email, vars::superuser_password()) {
+            Ok(Some(_user)) => log::info!("superuser created: {} ({})", _user.email, _user.id),
+            Ok(None) => log::info!("SUPERUSER_EMAIL {} is already registered, not promoted; use create_superuser", email),
+            Err(err) => log::error!("superuser bootstrap error: {}", err),
+        }
+    }
+}
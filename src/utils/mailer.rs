@@ -0,0 +1,49 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use crate::errors::Error;
+use crate::vars;
+
+
+/// Отправка писем. Реальный SMTP-адаптер подключается реализацией этого же трейта.
+pub trait Mailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), Error>;
+}
+
+/// Пишет письма в stdout - для локальной разработки.
+pub struct StdoutMailer;
+
+impl Mailer for StdoutMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), Error> {
+        println!("=============");
+        println!("To: {}", to);
+        println!("Subject: {}", subject);
+        println!("{}", body);
+        println!("=============");
+        Ok(())
+    }
+}
+
+/// Дописывает письма в файл - для разработки и тестов.
+pub struct FileMailer {
+    pub path: String,
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), Error> {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| Error::InternalServerError(e.to_string()))?;
+        writeln!(f, "To: {}\nSubject: {}\n\n{}\n", to, subject, body)
+            .map_err(|e| Error::InternalServerError(e.to_string()))
+    }
+}
+
+pub fn get_mailer() -> Box<dyn Mailer> {
+    let sink = vars::mail_sink();
+    match sink.strip_prefix("file:") {
+        Some(path) => Box::new(FileMailer { path: path.to_string() }),
+        None => Box::new(StdoutMailer),
+    }
+}
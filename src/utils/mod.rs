@@ -1,15 +1,26 @@
 mod auth;
 mod reqwest;
+mod mailer;
+mod bootstrap;
+mod sweeper;
+mod payments;
 
 pub use self::{
     auth::*,
+    mailer::*,
+    bootstrap::*,
+    sweeper::*,
+    payments::*,
 };
 use actix_multipart::{Field, Multipart};
 use futures::StreamExt;
 use actix_web::{
+    dev::Payload,
+    FromRequest,
     HttpRequest,
     web,
 };
+use futures::future::LocalBoxFuture;
 use crate::schema;
 use serde::{Deserialize, Serialize};
 use crate::diesel::{
@@ -18,8 +29,12 @@ use crate::diesel::{
     RunQueryDsl,
     Connection,
     PgConnection,
+    r2d2::{ConnectionManager, Pool as DieselPool},
 };
-use crate::models::User;
+use crate::models::{User, UserSession, Role, Permission};
+use crate::errors::{AuthError, Error};
+use crate::vars;
+use std::ops::Deref;
 use std::fs::File;
 use std::io::{Write,BufWriter};
 use std::fs::create_dir_all;
@@ -163,32 +178,172 @@ pub struct NewUserForm {
     pub password:   String,
 }
 
+pub type Pool = DieselPool<ConnectionManager<PgConnection>>;
+
+/// общий пул соединений; создаётся один раз в main и попадает в обработчики через web::Data
+pub fn create_pool() -> Pool {
+    let manager = ConnectionManager::<PgConnection>::new(vars::database_url());
+    DieselPool::builder()
+        .max_size(vars::db_pool_size())
+        .build(manager)
+        .expect("Error creating database pool")
+}
+
+/// Одиночное соединение для консольных команд, где пула нет.
 pub fn establish_connection() -> PgConnection {
-    dotenv::dotenv().ok();
-    let database_url = std::env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set");
+    let database_url = vars::database_url();
     PgConnection::establish(&database_url)
         .expect(&format!("Error connecting to {}", database_url))
 }
 
+/// Берёт соединение из пула и выполняет работу с базой в пуле потоков actix,
+/// чтобы синхронный diesel не занимал воркеры.
+pub async fn db_block<F, R, E>(pool: &web::Data<Pool>, f: F) -> Result<R, E>
+where
+    F: FnOnce(&PgConnection) -> Result<R, E> + Send + 'static,
+    R: Send + 'static,
+    E: From<diesel::r2d2::PoolError> + From<actix_web::error::BlockingError> + Send + 'static,
+{
+    let pool = pool.clone();
+    web::block(move || {
+        let _connection = pool.get()?;
+        f(&_connection)
+    })
+    .await?
+}
+
 fn get_secret<'a>(req: &'a HttpRequest) -> Option<&'a str> {
     return req.headers().get("secret")?.to_str().ok();
 }
 
-pub fn is_signed_in(req: &HttpRequest) -> bool {
-  get_secret(&req).is_some()
+/// Адрес клиента для троттлинга входа. Заголовок X-Forwarded-For подделывается клиентом,
+/// поэтому берём адрес соединения, а последний адрес из заголовка - только если
+/// соединение пришло от прокси из TRUSTED_PROXIES
+pub fn get_client_ip(req: &HttpRequest) -> String {
+    let peer = match req.peer_addr() {
+        Some(addr) => addr.ip().to_string(),
+        None => return String::new(),
+    };
+    if !vars::trusted_proxies().contains(&peer) {
+        return peer;
+    }
+    req.headers()
+        .get("x-forwarded-for")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.rsplit(',').next())
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty())
+        .unwrap_or(peer)
+}
+
+pub fn get_user_agent(req: &HttpRequest) -> String {
+    req.headers()
+        .get("user-agent")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// пользователь и сессия по токену из заголовка secret
+pub fn get_request_user(_connection: &PgConnection, secret: &str) -> Result<RequestUser, AuthError> {
+    let (session, user) = UserSession::get_user(_connection, secret)?;
+    Ok(RequestUser {
+        user:       user.check_ban(_connection)?,
+        session_id: session.id,
+    })
+}
+
+/// Экстрактор текущего пользователя: при отсутствии или негодном токене
+/// запрос завершается 401, до обработчика дело не доходит.
+pub struct RequestUser {
+    pub user:       User,
+    pub session_id: String,
+}
+
+impl Deref for RequestUser {
+    type Target = User;
+
+    fn deref(&self) -> &User {
+        &self.user
+    }
+}
+
+/// Единая проверка прав: обработчик вызывает одну из require* и получает 403 при отказе.
+impl RequestUser {
+    pub fn require(&self, permission: Permission) -> Result<(), Error> {
+        if self.role().can(permission) {
+            return Ok(());
+        }
+        Err(Error::Forbidden)
+    }
+    /// бронировать (заказы и холды) можно только с подтверждённой почтой
+    pub fn require_verified(&self) -> Result<(), Error> {
+        if self.verified {
+            return Ok(());
+        }
+        Err(Error::Unverified)
+    }
+    /// владелец, менеджер объекта или суперпользователь
+    pub fn require_place(&self, _connection: &PgConnection, place_id: &String) -> Result<(), Error> {
+        if self.role() != Role::Banned && self.is_can_work_in_object_with_id(_connection, place_id) {
+            return Ok(());
+        }
+        Err(Error::Forbidden)
+    }
+    /// только владелец объекта или суперпользователь
+    pub fn require_place_owner(&self, _connection: &PgConnection, place_id: &String) -> Result<(), Error> {
+        if self.role().can(Permission::ManageAnyPlace) || self.is_partner_of_place_with_id(_connection, place_id) {
+            return Ok(());
+        }
+        Err(Error::Forbidden)
+    }
+}
+
+impl FromRequest for RequestUser {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let pool = req.app_data::<web::Data<Pool>>().cloned();
+        let secret = get_secret(req).map(|s| s.to_string());
+        Box::pin(async move {
+            let pool = pool
+                .ok_or(AuthError::ProcessError(String::from("Пул соединений не настроен")))?;
+            let secret = secret
+                .ok_or(AuthError::AuthenticationError(String::from("Требуется авторизация")))?;
+            db_block(&pool, move |_connection| get_request_user(_connection, &secret)).await
+        })
+    }
+}
+
+/// разбор поля формы, пришедшего строкой; вместо паники - 400 с названием поля
+pub fn parse_field<T: std::str::FromStr>(value: &str, field: &str) -> Result<T, Error> {
+    value.parse::<T>()
+        .map_err(|_| Error::BadRequest(format!("Некорректное значение поля {}", field)))
+}
+
+/// формат времени броней и событий в запросах и ответах
+pub const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+pub fn parse_datetime(value: &str, field: &str) -> Result<chrono::NaiveDateTime, Error> {
+    chrono::NaiveDateTime::parse_from_str(value, DATETIME_FORMAT)
+        .map_err(|_| Error::BadRequest(format!("Поле {} должно быть в формате ГГГГ-ММ-ДД чч:мм:сс", field)))
 }
 
-pub fn get_current_user(req: &HttpRequest) -> User {
+/// #[serde(with = "crate::utils::datetime_format")]: TIMESTAMP-поля отдаются
+/// в том же виде, в каком их принимает parse_datetime
+pub mod datetime_format {
+    use serde::{Deserialize, Deserializer, Serializer};
 
-    let secret = get_secret(&req).unwrap();
-    let uuid = hex::decode(secret).expect("failed decode");
-    let _connection = establish_connection();
-    return schema::users::table
-        .filter(schema::users::uuid.eq(uuid))
-        .first::<User>(&_connection)
-        .expect("Error.");
-} 
+    pub fn serialize<S: Serializer>(value: &chrono::NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.format(super::DATETIME_FORMAT).to_string())
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<chrono::NaiveDateTime, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        chrono::NaiveDateTime::parse_from_str(&value, super::DATETIME_FORMAT)
+            .map_err(serde::de::Error::custom)
+    }
+}
 
 pub fn get_limit (
     limit: Option<i64>,
@@ -232,6 +387,29 @@ pub fn get_page(req: &HttpRequest) -> i32 {
     page
 }
 
+/// Общая обёртка постраничных ответов: items - текущая страница, total - всего записей.
+#[derive(Serialize, Debug)]
+pub struct Paginated<T: Serialize> {
+    pub items:    Vec<T>,
+    pub page:     i32,
+    pub limit:    i64,
+    pub total:    i64,
+    pub has_next: bool,
+}
+
+impl<T: Serialize> Paginated<T> {
+    /// страница page (с 1) по limit записей, уже выбранная запросом из total записей
+    pub fn new(items: Vec<T>, page: i32, limit: i64, total: i64) -> Paginated<T> {
+        Paginated {
+            items:    items,
+            page:     page,
+            limit:    limit,
+            total:    total,
+            has_next: page as i64 * limit < total,
+        }
+    }
+}
+
 pub fn get_id(req: &HttpRequest) -> i32 {
     #[derive(Debug, Deserialize)]
     struct Params {
@@ -255,10 +433,8 @@ pub fn get_id(req: &HttpRequest) -> i32 {
 }
 
 
-pub fn get_user(id: String) -> User { 
-    let _connection = establish_connection();
-    return schema::users::table
+pub fn get_user(_connection: &PgConnection, id: String) -> Result<User, Error> { 
+    return Ok(schema::users::table
         .filter(schema::users::id.eq(id))
-        .first::<User>(&_connection)
-        .expect("Error.");
+        .first::<User>(_connection)?);
 }
\ No newline at end of file
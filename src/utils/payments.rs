@@ -0,0 +1,155 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use serde::{Serialize, Deserialize};
+use crate::errors::Error;
+use crate::vars;
+
+type HmacSha256 = Hmac<Sha256>;
+
+
+/// Платёж, созданный у шлюза: его id у шлюза и куда отправить клиента для оплаты.
+#[derive(Debug, Serialize)]
+pub struct CreatedPayment {
+    pub external_id:      String,
+    pub confirmation_url: String,
+}
+
+/// Проверенное уведомление шлюза о смене статуса платежа.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    pub external_id: String,
+    pub status:      String,
+    pub amount:      i32,
+}
+
+/// Платёжный шлюз. Адаптер настоящего эквайринга подключается реализацией этого же трейта.
+pub trait PaymentProvider {
+    fn name(&self) -> &str;
+    fn create_payment(&self, payment_id: &str, amount: i32, description: &str) -> Result<CreatedPayment, Error>;
+    /// проверяет подпись и разбирает тело вебхука
+    fn verify_webhook(&self, signature: &str, body: &[u8]) -> Result<WebhookEvent, Error>;
+    /// возврат части или всей суммы платежа; возвращает id возврата у шлюза
+    fn refund(&self, external_id: &str, refund_id: &str, amount: i32) -> Result<String, Error>;
+}
+
+/// Локальная заглушка: платежи никуда не уходят, вебхук подписывается HMAC-SHA256
+/// от тела запроса ключом PAYMENT_WEBHOOK_SECRET (заголовок X-Signature, hex).
+pub struct MockPaymentProvider {
+    pub secret: String,
+}
+
+impl MockPaymentProvider {
+    fn mac(&self, body: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(body);
+        mac
+    }
+    /// подписанное тело вебхука - для тестов и страницы оплаты заглушки
+    pub fn signed_webhook(&self, event: &WebhookEvent) -> (Vec<u8>, String) {
+        let body = serde_json::to_vec(event).expect("webhook event serializes");
+        let signature = hex::encode(self.mac(&body).finalize().into_bytes());
+        (body, signature)
+    }
+}
+
+impl PaymentProvider for MockPaymentProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+    fn create_payment(&self, payment_id: &str, _amount: i32, _description: &str) -> Result<CreatedPayment, Error> {
+        let external_id = format!("mock_{}", payment_id);
+        Ok(CreatedPayment {
+            confirmation_url: format!("{}/payments/mock/{}/", vars::site_url(), external_id),
+            external_id:      external_id,
+        })
+    }
+    fn verify_webhook(&self, signature: &str, body: &[u8]) -> Result<WebhookEvent, Error> {
+        let invalid = || Error::BadRequest(String::from("Неверная подпись"));
+        let signature = hex::decode(signature).map_err(|_| invalid())?;
+        self.mac(body)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+        serde_json::from_slice(body)
+            .map_err(|_| Error::BadRequest(String::from("Некорректное тело вебхука")))
+    }
+    fn refund(&self, _external_id: &str, refund_id: &str, _amount: i32) -> Result<String, Error> {
+        Ok(format!("mock_refund_{}", refund_id))
+    }
+}
+
+/// Настройки оплаты проверяются при старте: без шлюза и ключа подписи сервер не запускается,
+/// заглушка разрешена только в dev/test
+pub fn check_payment_config() {
+    let provider = vars::payment_provider().expect("PAYMENT_PROVIDER must be set");
+    vars::payment_webhook_secret().expect("PAYMENT_WEBHOOK_SECRET must be set");
+    match provider.as_str() {
+        "mock" => if !vars::is_dev() {
+            panic!("PAYMENT_PROVIDER=mock is allowed only with APP_ENV=dev or APP_ENV=test");
+        },
+        other => panic!("Unknown PAYMENT_PROVIDER: {}", other),
+    }
+}
+
+pub fn get_payment_provider() -> Result<Box<dyn PaymentProvider>, Error> {
+    let secret = vars::payment_webhook_secret()
+        .ok_or(Error::InternalServerError(String::from("PAYMENT_WEBHOOK_SECRET не задан")))?;
+    match vars::payment_provider().as_deref() {
+        Some("mock") if vars::is_dev() => Ok(Box::new(MockPaymentProvider { secret: secret })),
+        other => Err(Error::InternalServerError(format!("Недопустимый PAYMENT_PROVIDER: {:?}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider() -> MockPaymentProvider {
+        MockPaymentProvider { secret: String::from("test-secret") }
+    }
+    fn event() -> WebhookEvent {
+        WebhookEvent {
+            external_id: String::from("mock_1"),
+            status:      String::from("succeeded"),
+            amount:      1500,
+        }
+    }
+
+    #[test]
+    fn webhook_with_valid_signature_is_accepted() {
+        let provider = provider();
+        let (body, signature) = provider.signed_webhook(&event());
+        let verified = provider.verify_webhook(&signature, &body).unwrap();
+        assert_eq!(verified.external_id, "mock_1");
+        assert_eq!(verified.status, "succeeded");
+        assert_eq!(verified.amount, 1500);
+    }
+
+    #[test]
+    fn webhook_with_changed_body_is_rejected() {
+        let provider = provider();
+        let (_, signature) = provider.signed_webhook(&event());
+        let forged = serde_json::to_vec(&WebhookEvent { amount: 1, ..event() }).unwrap();
+        assert!(provider.verify_webhook(&signature, &forged).is_err());
+    }
+
+    #[test]
+    fn webhook_signed_with_other_secret_is_rejected() {
+        let (body, signature) = MockPaymentProvider { secret: String::from("other") }.signed_webhook(&event());
+        assert!(provider().verify_webhook(&signature, &body).is_err());
+    }
+
+    #[test]
+    fn webhook_without_signature_is_rejected() {
+        let (body, _) = provider().signed_webhook(&event());
+        assert!(provider().verify_webhook("", &body).is_err());
+        assert!(provider().verify_webhook("not hex", &body).is_err());
+    }
+
+    #[test]
+    fn mock_payment_url_points_to_mock_page() {
+        let created = provider().create_payment("42", 1500, "test").unwrap();
+        assert_eq!(created.external_id, "mock_42");
+        assert!(created.confirmation_url.ends_with("/payments/mock/mock_42/"));
+    }
+}
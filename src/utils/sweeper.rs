@@ -0,0 +1,20 @@
+use actix_web::web;
+use crate::models::OrderHold;
+use crate::utils::{db_block, Pool};
+use crate::errors::Error;
+
+
+/// раз в минуту удаляет истёкшие холды; запускается из main внутри рантайма actix
+pub fn spawn_hold_sweeper(pool: &Pool) {
+    let pool = web::Data::new(pool.clone());
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            let swept: Result<usize, Error> = db_block(&pool, |_connection| OrderHold::delete_expired(_connection)).await;
+            if let Err(err) = swept {
+                log::error!("hold sweeper error: {}", err);
+            }
+        }
+    });
+}
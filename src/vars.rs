@@ -1,7 +1,107 @@
 use dotenv::dotenv;
 use std::env::var;
 
+pub fn database_url() -> String {
+  dotenv().ok();
+  var("DATABASE_URL").expect("DATABASE_URL must be set")
+}
+
+/// сколько соединений с базой держит пул
+pub fn db_pool_size() -> u32 {
+  dotenv().ok();
+  var("DB_POOL_SIZE")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(10)
+}
+
 pub fn secret_key() -> String {
   dotenv().ok();
   var("SECRET_KEY").unwrap_or_else(|_| "0123".repeat(8))
 }
+
+pub fn session_ttl_hours() -> i64 {
+  dotenv().ok();
+  var("SESSION_TTL_HOURS")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(24 * 30)
+}
+
+pub fn site_url() -> String {
+  dotenv().ok();
+  var("SITE_URL").unwrap_or_else(|_| "http://localhost:8120".to_string())
+}
+
+/// адреса своих прокси через запятую; только от них верим X-Forwarded-For
+pub fn trusted_proxies() -> Vec<String> {
+  dotenv().ok();
+  var("TRUSTED_PROXIES")
+    .unwrap_or_default()
+    .split(',')
+    .map(|p| p.trim().to_string())
+    .filter(|p| !p.is_empty())
+    .collect()
+}
+
+/// куда отправлять письма: "stdout" (по умолчанию) или "file:/path/to/mail.log"
+pub fn mail_sink() -> String {
+  dotenv().ok();
+  var("MAIL_SINK").unwrap_or_else(|_| "stdout".to_string())
+}
+
+/// суперпользователь, которого создаём при старте сервера, если почта ещё свободна
+pub fn superuser_email() -> Option<String> {
+  dotenv().ok();
+  var("SUPERUSER_EMAIL").ok().filter(|v| !v.is_empty())
+}
+
+pub fn superuser_password() -> Option<String> {
+  dotenv().ok();
+  var("SUPERUSER_PASSWORD").ok().filter(|v| !v.is_empty())
+}
+
+/// на сколько минут холд держит модуль и насколько его продлевает extend
+pub fn hold_minutes() -> i64 {
+  dotenv().ok();
+  var("HOLD_MINUTES")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(10)
+}
+
+/// дольше этого от создания холд не продлевается
+pub fn hold_max_minutes() -> i64 {
+  dotenv().ok();
+  var("HOLD_MAX_MINUTES")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(30)
+}
+
+/// окружение: "dev" и "test" разрешают заглушки, всё остальное - боевой режим
+pub fn app_env() -> String {
+  dotenv().ok();
+  var("APP_ENV").unwrap_or_else(|_| "production".to_string())
+}
+
+pub fn is_dev() -> bool {
+  matches!(app_env().as_str(), "dev" | "test")
+}
+
+/// платёжный шлюз; значения по умолчанию нет. "mock" - заглушка, только при APP_ENV=dev/test
+pub fn payment_provider() -> Option<String> {
+  dotenv().ok();
+  var("PAYMENT_PROVIDER").ok().filter(|v| !v.is_empty())
+}
+
+/// ключ подписи вебхуков платёжного шлюза, обязателен
+pub fn payment_webhook_secret() -> Option<String> {
+  dotenv().ok();
+  var("PAYMENT_WEBHOOK_SECRET").ok().filter(|v| !v.is_empty())
+}
+
+/// страница оплаты заглушки подключается только явно выбранной заглушке в dev/test
+pub fn mock_payments_enabled() -> bool {
+  payment_provider().as_deref() == Some("mock") && is_dev()
+}
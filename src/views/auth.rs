@@ -4,22 +4,44 @@ use actix_web::{
     web,
     web::Json,
 };
+use diesel::{PgConnection, Connection};
 use crate::api_error::ApiError;
 use serde::{Deserialize, Serialize};
 use crate::utils::{
+    db_block,
     verify,
+    Pool,
+    get_client_ip,
+    get_user_agent,
+    RequestUser,
 };
 use crate::models::{
     User, 
-    SessionUser, 
+    UserSession,
+    EmailVerificationToken,
+    PasswordResetToken,
+    AuthAttempt,
+    AuthAttemptsParams,
+    AuthLockout,
+    ClearLockoutJson,
+    Permission,
 };
-use crate::errors::AuthError;
+use crate::errors::{AuthError, Error};
 
 
 pub fn auth_routes(config: &mut web::ServiceConfig) {
     config.route("/signup/", web::post().to(process_signup));
     config.route("/login/", web::post().to(login));
     config.route("/get_user_data/", web::post().to(get_user_data));
+    config.route("/logout/", web::post().to(logout));
+    config.route("/logout_all/", web::post().to(logout_all));
+    config.route("/verify_email/{token}/", web::get().to(verify_email));
+    config.route("/resend_verification/", web::post().to(resend_verification));
+    config.route("/change_password/", web::post().to(change_password));
+    config.route("/forgot_password/", web::post().to(forgot_password));
+    config.route("/reset_password/", web::post().to(reset_password));
+    config.route("/auth_attempts/", web::get().to(get_auth_attempts));
+    config.route("/clear_lockout/", web::post().to(clear_lockout));
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -42,6 +64,23 @@ pub struct NewUserJson {
     //pub token:      String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ChangePasswordJson {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForgotPasswordJson {
+    pub email: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResetPasswordJson {
+    pub token:    String,
+    pub password: String,
+}
+
 #[derive(Deserialize, Serialize, Debug, Queryable)]
 pub struct AuthResp {
     pub id:         String,
@@ -60,102 +99,236 @@ pub struct AuthResp2 {
     pub email:      String,
     pub perm:       i16,
     pub image:      Option<String>,
-    pub uuid:       String,
+    pub verified:   bool,
+    pub token:      String,
+    pub expires:    Option<chrono::NaiveDateTime>,
+}
+
+fn find_user(_connection: &PgConnection, email: String, password: String) -> Result<User, AuthError> {
+    let _user = match User::get_user_with_email(_connection, &email) {
+        Ok(_user) => _user,
+        Err(Error::NotFound(_)) => return Err(AuthError::NotFound(String::from("Пользователь не найден"))),
+        Err(_) => return Err(AuthError::ProcessError(String::from("Some database error occured"))),
+    };
+    if !verify(&_user.password, &password)? {
+        return Err(AuthError::AuthenticationError(String::from("Неверный пароль")));
+    }
+    _user.check_ban(_connection)
+}
+fn find_user_with_id(_connection: &PgConnection, id: String) -> Result<User, AuthError> {
+    match User::get_user_with_id(_connection, &id) {
+        Ok(_user) => Ok(_user),
+        Err(Error::NotFound(_)) => Err(AuthError::NotFound(String::from("Пользователь не найден"))),
+        Err(_) => Err(AuthError::ProcessError(String::from("Some database error occured"))),
+    }
+}
+
+fn journal_attempt(_connection: &PgConnection, ip: &String, user_agent: &String, email: &String, user_id: Option<String>, success: bool) {
+    if let Err(err) = AuthAttempt::create(_connection, email, user_id, ip, user_agent, success) {
+        log::error!("auth journal error: {}", err);
+    }
+}
+
+/// открывает сессию и собирает ответ для login / signup
+fn auth_resp_with_session(_connection: &PgConnection, _user: User) -> Result<Json<AuthResp2>, AuthError> {
+    let session = UserSession::create(_connection, &_user.id)
+        .map_err(|_| AuthError::ProcessError(String::from("Не удалось создать сессию")))?;
+    Ok(Json(AuthResp2 {
+        id:         _user.id,
+        first_name: _user.first_name,
+        last_name:  _user.last_name,
+        email:      _user.email,
+        perm:       _user.perm,
+        image:      _user.image,
+        verified:   _user.verified,
+        token:      session.token,
+        expires:    Some(session.expires),
+    }))
 }
 
-fn find_user(email: String, password: String) -> Result<User, AuthError> {
-    let user_some = User::get_user_with_email(&email); 
-    if user_some.is_ok() { 
-        let _user = user_some.expect("Error.");
-        if let Ok(matching) = verify(&_user.password, &password) {
-            if matching {
-                return Ok(_user);
-            }
+pub async fn login(pool: web::Data<Pool>, req: HttpRequest, data: Json<LoginUser2>) -> Result<Json<AuthResp2>, AuthError> {
+    let ip = get_client_ip(&req);
+    let user_agent = get_user_agent(&req);
+    db_block(&pool, move |_connection| {
+        // попытка считается неудачей до проверки пароля; без ответа базы о блокировке вход не пускаем
+        let locked_until = AuthLockout::reserve_attempt(_connection, &data.email, &ip).map_err(|err| {
+            log::error!("auth lockout error: {}", err);
+            AuthError::ProcessError(String::from("Some database error occured"))
+        })?;
+        if let Some(until) = locked_until {
+            journal_attempt(_connection, &ip, &user_agent, &data.email, None, false);
+            return Err(AuthError::TooManyAttempts(format!(
+                "Слишком много попыток входа, повторите после {} UTC",
+                until.format("%Y-%m-%d %H:%M:%S"),
+            )));
         }
+
+        match find_user(_connection, data.email.clone(), data.password.clone()) {
+            Ok(_user) => {
+                if let Err(err) = AuthLockout::register_success(_connection, &data.email, &ip) {
+                    log::error!("auth lockout error: {}", err);
+                }
+                journal_attempt(_connection, &ip, &user_agent, &data.email, Some(_user.id.clone()), true);
+                auth_resp_with_session(_connection, _user)
+            },
+            Err(err) => {
+                // считаем только перебор: неизвестная почта или неверный пароль
+                if !matches!(err, AuthError::NotFound(_) | AuthError::AuthenticationError(_)) {
+                    if let Err(err) = AuthLockout::release_attempt(_connection, &data.email, &ip) {
+                        log::error!("auth lockout error: {}", err);
+                    }
+                }
+                journal_attempt(_connection, &ip, &user_agent, &data.email, None, false);
+                Err(err)
+            },
+        }
+    }).await
+}
+
+pub async fn get_user_data(pool: web::Data<Pool>, data: Json<IdUser>) -> Result<Json<AuthResp>, AuthError> {
+    let _user = db_block(&pool, move |_connection| find_user_with_id(_connection, data.id.clone())).await?;
+    Ok(Json(AuthResp {
+        id:         _user.id,
+        first_name: _user.first_name,
+        last_name:  _user.last_name,
+        email:      _user.email,
+        perm:       _user.perm,
+        image:      _user.image,
+    }))
+}
+
+pub async fn process_signup(pool: web::Data<Pool>, data: Json<NewUserJson>) -> Result<Json<AuthResp2>, AuthError> {
+    if !is_valid_password(&data.password) {
+        return Err(AuthError::GenericError(String::from(SHORT_PASSWORD)));
     }
-    Err(AuthError::NotFound(String::from("User not found")))
+    db_block(&pool, move |_connection| {
+        match User::get_user_with_email(_connection, &data.email) {
+            Ok(_) => return Err(AuthError::DuplicateValue(String::from("Почта уже зарегистрирована"))),
+            Err(Error::NotFound(_)) => (),
+            Err(_) => return Err(AuthError::ProcessError(String::from("Some database error occured"))),
+        }
+        // гонку двух одинаковых регистраций ловит UNIQUE(email) -> DuplicateValue
+        let _new_user = User::create(_connection, data)?;
+
+        if let Err(err) = EmailVerificationToken::send(_connection, &_new_user.email) {
+            log::error!("verification email error: {}", err);
+        }
+        auth_resp_with_session(_connection, _new_user)
+    }).await
 }
-fn find_user_with_id(id: String) -> Result<User, AuthError> {
-    let user_some = User::get_user_with_id(&id); 
-    if user_some.is_ok() { 
-        let _user = user_some.expect("Error.");
-        return Ok(_user);
+
+pub async fn logout(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<HttpResponse, AuthError> {
+    db_block(&pool, move |_connection| UserSession::delete(_connection, &_request_user.session_id)
+        .map_err(|_| AuthError::ProcessError(String::from("Не удалось завершить сессию")))).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// завершает все сессии пользователя, включая текущую
+pub async fn logout_all(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<HttpResponse, AuthError> {
+    db_block(&pool, move |_connection| UserSession::delete_all_for_user(_connection, &_request_user.id)
+        .map_err(|_| AuthError::ProcessError(String::from("Не удалось завершить сессии")))).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn verify_email(pool: web::Data<Pool>, token: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = hex::decode(token.as_str())
+        .map_err(|_| ApiError::new(404, "Ссылка недействительна"))?;
+    db_block(&pool, move |conn| {
+        let _token = EmailVerificationToken::find(conn, &id)?;
+        if _token.expires_at < chrono::Utc::now().naive_utc() {
+            EmailVerificationToken::delete(conn, &id)?;
+            return Err(ApiError::new(410, "Срок действия ссылки истёк"));
+        }
+        User::verify_email(conn, &_token.email)
+            .map_err(|e| ApiError::new(500, e.to_string()))?;
+        EmailVerificationToken::delete(conn, &id)?;
+        Ok(())
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn resend_verification(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<HttpResponse, ApiError> {
+    if !_request_user.verified {
+        db_block(&pool, move |conn| EmailVerificationToken::send(conn, &_request_user.email)).await?;
     }
-    Err(AuthError::NotFound(String::from("User not found")))
-}
-
-pub async fn login(req: HttpRequest, data: Json<LoginUser2>) -> Json<AuthResp2> {
-    let result = find_user(data.email.clone(), data.password.clone());
-    match result {
-        Ok(_new_user) => {
-            return Json(AuthResp2 { 
-                id:         _new_user.id.clone(),
-                first_name: _new_user.first_name.clone(),
-                last_name:  _new_user.last_name.clone(),
-                email:      _new_user.email.clone(),
-                perm:       _new_user.perm,
-                image:      _new_user.image.clone(),
-                uuid:       _new_user.get_uuid(),
-            });   
-        },
-        Err(err) => {
-            return Json(AuthResp2 {
-                id:         "".to_string(),
-                first_name: "".to_string(),
-                last_name:  "".to_string(),
-                email:      "".to_string(),
-                perm:       0,
-                image:      None,
-                uuid:       "".to_string(),
-            });      
-        },
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub const SHORT_PASSWORD: &str = "Пароль должен быть не короче 6 символов";
+
+pub fn is_valid_password(password: &String) -> bool {
+    password.chars().count() >= 6
+}
+
+pub async fn change_password(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<ChangePasswordJson>) -> Result<HttpResponse, AuthError> {
+    if !is_valid_password(&data.new_password) {
+        return Err(AuthError::GenericError(String::from(SHORT_PASSWORD)));
     }
+    db_block(&pool, move |_connection| {
+        if !verify(&_request_user.password, &data.old_password)? {
+            return Err(AuthError::AuthenticationError(String::from("Неверный текущий пароль")));
+        }
+        User::change_password(_connection, &_request_user.id, &data.new_password)
+            .map_err(|_| AuthError::ProcessError(String::from("Не удалось сменить пароль")))
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// всегда отвечает 200, чтобы по ответу нельзя было проверить, зарегистрирована ли почта
+pub async fn forgot_password(pool: web::Data<Pool>, data: Json<ForgotPasswordJson>) -> HttpResponse {
+    let sent = db_block(&pool, move |conn| {
+        if let Ok(_user) = User::get_user_with_email(conn, &data.email) {
+            PasswordResetToken::send(conn, &_user.id, &_user.email)?;
+        }
+        Ok::<(), ApiError>(())
+    }).await;
+    if let Err(err) = sent {
+        log::error!("password reset email error: {}", err);
+    }
+    HttpResponse::Ok().finish()
 }
 
-pub async fn get_user_data(req: HttpRequest, data: Json<IdUser>) -> Json<AuthResp2> {
-    let result = find_user_with_id(data.id.clone());
-    match result {
-        Ok(_new_user) => {
-            return Json(AuthResp2 { 
-                id:         _new_user.id.clone(),
-                first_name: _new_user.first_name.clone(),
-                last_name:  _new_user.last_name.clone(),
-                email:      _new_user.email.clone(),
-                perm:       _new_user.perm,
-                image:      _new_user.image.clone(),
-                uuid:       _new_user.get_uuid(),
-            });   
-        },
-        Err(err) => {
-            return Json(AuthResp2 {
-                id:         "".to_string(),
-                first_name: "".to_string(),
-                last_name:  "".to_string(),
-                email:      "".to_string(),
-                perm:       0,
-                image:      None,
-                uuid:       "".to_string(),
-            });      
-        },
+/// смена пароля по токену из письма; токен одноразовый, все сессии пользователя завершаются
+pub async fn reset_password(pool: web::Data<Pool>, data: Json<ResetPasswordJson>) -> Result<HttpResponse, ApiError> {
+    let id = hex::decode(data.token.as_str())
+        .map_err(|_| ApiError::new(404, "Ссылка недействительна"))?;
+    if !is_valid_password(&data.password) {
+        return Err(ApiError::new(400, SHORT_PASSWORD));
+    }
+    // токен удаляется первым шагом транзакции: при сбое дальше он вернётся вместе с паролем,
+    // а параллельный запрос с тем же токеном его уже не найдёт
+    let done = db_block(&pool, move |conn| conn.transaction(|| {
+        let _token = PasswordResetToken::take(conn, &id)?;
+        if _token.expires_at < chrono::Utc::now().naive_utc() {
+            return Ok(false);
+        }
+        User::change_password(conn, &_token.user_id, &data.password)
+            .map_err(|e| ApiError::new(500, e.to_string()))?;
+        UserSession::delete_all_for_user(conn, &_token.user_id)
+            .map_err(|e| ApiError::new(500, e.to_string()))?;
+        Ok::<bool, ApiError>(true)
+    })).await?;
+    if !done {
+        return Err(ApiError::new(410, "Срок действия ссылки истёк"));
     }
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn get_auth_attempts(pool: web::Data<Pool>, _request_user: RequestUser, params: web::Query<AuthAttemptsParams>) -> Result<Json<Vec<AuthAttempt>>, Error> {
+    _request_user.require(Permission::ManageAuth)?;
+    Ok(Json(db_block(&pool, move |_connection| AuthAttempt::get_list(_connection, &params)).await?))
 }
 
-pub async fn process_signup(data: Json<NewUserJson>) -> Json<AuthResp2> {
-        let _new_user = User::create(data);
-
-        let _session_user = SessionUser {
-            id:    _new_user.id.clone(),
-            email: _new_user.email.clone(),
-        };
-
-        println!("Yes!");
-        return Json(AuthResp2 {
-            id:         _new_user.id.clone(),
-            first_name: _new_user.first_name.clone(),
-            last_name:  _new_user.last_name.clone(),
-            email:      _new_user.email.clone(),
-            perm:       _new_user.perm,
-            image:      _new_user.image.clone(),
-            uuid:       _new_user.get_uuid(),
-        })
-}
\ No newline at end of file
+pub async fn clear_lockout(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<ClearLockoutJson>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::ManageAuth)?;
+    db_block(&pool, move |_connection| {
+        if let Some(email) = &data.email {
+            AuthLockout::clear(_connection, &AuthLockout::email_key(email))?;
+        }
+        if let Some(ip) = &data.ip {
+            AuthLockout::clear(_connection, &AuthLockout::ip_key(ip))?;
+        }
+        Ok::<(), Error>(())
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
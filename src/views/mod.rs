@@ -2,10 +2,12 @@ pub mod auth;
 pub mod other_views;
 pub mod users_views;
 pub mod places_views;
+pub mod payments_views;
 
 pub use self::{
     auth::*,
     other_views::*,
     users_views::*,
     places_views::*,
+    payments_views::*,
 };
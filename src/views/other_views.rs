@@ -6,42 +6,229 @@ use actix_web::{
     web::Json,
 };
 use serde::{Deserialize, Serialize};
-use crate::models::{Order, OrderJson};
+use crate::models::{
+    Order, OrderJson, OrderStatus, OrderStatusLog, Permission,
+    CreatedOrderJson, OrderPrice, IdempotencyKey, OrderHold, quote_order,
+    Refund, RefundQuote, CancelOrderJson, CancellationPolicy,
+    PromoCode, PromoCodeJson, PromoCodeParams, PlaceTz,
+    PlaceReview, ReviewJson,
+};
 
 use crate::utils::{
-    is_signed_in,
-    get_current_user,
-    establish_connection,
+    db_block,
+    get_payment_provider,
+    Pool,
+    RequestUser,
 };
+use crate::errors::Error;
 
 
 pub fn other_routes(config: &mut web::ServiceConfig) {
     config.route("/create_order/", web::post().to(create_order));
+    config.route("/order_price/", web::post().to(get_order_price));
+    config.route("/promo_codes/", web::get().to(get_promo_codes));
+    config.route("/promo_codes/", web::post().to(create_promo_code));
+    config.route("/promo_code/{id}/disable/", web::post().to(disable_promo_code));
+    config.route("/holds/", web::get().to(get_holds));
+    config.route("/holds/", web::post().to(create_holds));
+    config.route("/holds/order/", web::post().to(order_holds));
+    config.route("/hold/{id}/extend/", web::post().to(extend_hold));
+    config.route("/hold/{id}/release/", web::post().to(release_hold));
     config.route("/delete_order/", web::post().to(delete_order));
+    config.route("/order/{id}/history/", web::get().to(get_order_history));
+    config.route("/order/{id}/refunds/", web::get().to(get_order_refunds));
+    config.route("/order/{id}/refund_quote/", web::get().to(get_refund_quote));
+    config.route("/order/{id}/confirm/", web::post().to(confirm_order));
+    config.route("/order/{id}/pay/", web::post().to(pay_order));
+    config.route("/order/{id}/check_in/", web::post().to(check_in_order));
+    config.route("/order/{id}/complete/", web::post().to(complete_order));
+    config.route("/order/{id}/cancel/", web::post().to(cancel_order));
+    config.route("/order/{id}/cancel_by_place/", web::post().to(cancel_order_by_place));
+    config.route("/order/{id}/no_show/", web::post().to(no_show_order));
+    config.route("/order/{id}/review/", web::post().to(review_order));
 } 
 
-pub async fn create_order(req: HttpRequest, data: Json<Vec<OrderJson>>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        Order::create(
-            _request_user.id.clone(),
-            data
-        ); 
-    }
-    HttpResponse::Ok()
+/// заказ создаётся целиком; с заголовком Idempotency-Key повтор запроса
+/// возвращает уже созданные заказы. Промокод передаётся в ?promo_code=
+pub async fn create_order(req: HttpRequest, pool: web::Data<Pool>, _request_user: RequestUser, params: web::Query<PromoCodeParams>, data: Json<Vec<OrderJson>>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::Book)?;
+    _request_user.require_verified()?;
+    let idempotency_key = req.headers()
+        .get("idempotency-key")
+        .map(|h| h.to_str().map(|s| s.to_string()))
+        .transpose()
+        .map_err(|_| Error::BadRequest(String::from("Некорректный Idempotency-Key")))?;
+    let _created: CreatedOrderJson = db_block(&pool, move |_connection| {
+        let user_id = _request_user.id.clone();
+        let promo_code = params.into_inner().promo_code;
+        match idempotency_key {
+            Some(key) => {
+                let request_hash = IdempotencyKey::request_hash(&(&promo_code, &*data))?;
+                IdempotencyKey::run(_connection, &user_id, &key, request_hash, || {
+                    Order::create(_connection, user_id.clone(), data, promo_code)
+                })
+            },
+            None => Order::create(_connection, user_id, data, promo_code),
+        }
+    }).await?;
+    Ok(HttpResponse::Ok().json(_created))
+}
+
+/// предварительный расчёт цены заказа без бронирования
+/// лимит использований промокода на пользователя здесь не проверяется - только при оформлении
+pub async fn get_order_price(pool: web::Data<Pool>, params: web::Query<PromoCodeParams>, data: Json<Vec<OrderJson>>) -> Result<Json<OrderPrice>, Error> {
+    db_block(&pool, move |_connection| {
+        let promo = match &params.promo_code {
+            Some(code) => Some(PromoCode::find_valid(_connection, code, None)?),
+            None => None,
+        };
+        Ok(Json(quote_order(_connection, &data, promo.as_ref())?))
+    }).await
+}
+
+/// промокоды, заведённые пользователем
+pub async fn get_promo_codes(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<Vec<PromoCode>>, Error> {
+    db_block(&pool, move |_connection| {
+        let codes = PromoCode::get_for_user(_connection, &_request_user.id)?
+            .into_iter()
+            .map(|c| c.localize(_connection))
+            .collect::<Result<Vec<PromoCode>, Error>>()?;
+        Ok(Json(codes))
+    }).await
+}
+pub async fn create_promo_code(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<PromoCodeJson>) -> Result<Json<PromoCode>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(PromoCode::create(_connection, &_request_user, &data)?.localize(_connection)?))).await
+}
+pub async fn disable_promo_code(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<PromoCode>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(PromoCode::disable(_connection, &id, &_request_user)?.localize(_connection)?))).await
 }
 
 #[derive(Deserialize)]
 pub struct OrderIdsJson {  
     pub ids: Vec<String>,
 }
-pub async fn delete_order(req: HttpRequest, data: Json<OrderIdsJson>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        Order::delete(
-            _request_user.id.clone(),
-            data
-        ); 
-    }
-    HttpResponse::Ok()
+/// брони не удаляются: клиент отменяет их по правилам отмены объекта, история остаётся у объекта
+pub async fn delete_order(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<OrderIdsJson>) -> Result<Json<Vec<CancelOrderJson>>, Error> {
+    db_block(&pool, move |_connection| {
+        let provider = get_payment_provider()?;
+        let mut stack = Vec::new();
+        for id in data.ids.iter() {
+            stack.push(Refund::cancel_order(_connection, provider.as_ref(), id, &_request_user, OrderStatus::CancelledByUser)?);
+        }
+        Ok(Json(stack))
+    }).await
+}
+
+pub async fn get_order_history(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<Vec<OrderStatusLog>>, Error> {
+    db_block(&pool, move |_connection| {
+        let _order = Order::get(_connection, &id)?;
+        if !_order.is_visible_to(_connection, &_request_user) {
+            return Err(Error::Forbidden);
+        }
+        Ok(Json(OrderStatusLog::get_for_order(_connection, &id)?))
+    }).await
+}
+pub async fn get_order_refunds(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<Vec<Refund>>, Error> {
+    db_block(&pool, move |_connection| {
+        let _order = Order::get(_connection, &id)?;
+        if !_order.is_visible_to(_connection, &_request_user) {
+            return Err(Error::Forbidden);
+        }
+        Ok(Json(Refund::get_for_order(_connection, &id)?))
+    }).await
+}
+/// отзыв клиента о завершённой брони
+pub async fn review_order(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>, data: Json<ReviewJson>) -> Result<Json<PlaceReview>, Error> {
+    db_block(&pool, move |_connection| {
+        let _order = Order::get(_connection, &id)?;
+        if _order.user_id != _request_user.id {
+            return Err(Error::Forbidden);
+        }
+        Ok(Json(PlaceReview::create(_connection, &_order, &data)?))
+    }).await
+}
+/// сколько клиент получит, если отменит бронь сейчас
+pub async fn get_refund_quote(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<RefundQuote>, Error> {
+    db_block(&pool, move |_connection| {
+        let _order = Order::get(_connection, &id)?;
+        if _order.user_id != _request_user.id {
+            return Err(Error::Forbidden);
+        }
+        let policy = CancellationPolicy::get(_connection, &_order.place_id)?;
+        Ok(Json(CancellationPolicy::quote(&policy, _order.price, _order.time_start)?))
+    }).await
+}
+
+async fn change_order_status(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>, to: OrderStatus) -> Result<Json<Order>, Error> {
+    db_block(&pool, move |_connection| {
+        let _order = Order::change_status(_connection, &id, &_request_user, to)?;
+        let tz = PlaceTz::for_place(_connection, &_order.place_id)?;
+        Ok(Json(_order.localize(tz)))
+    }).await
+}
+pub async fn confirm_order(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<Order>, Error> {
+    change_order_status(pool, _request_user, id, OrderStatus::Confirmed).await
+}
+pub async fn pay_order(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<Order>, Error> {
+    change_order_status(pool, _request_user, id, OrderStatus::Paid).await
+}
+pub async fn check_in_order(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<Order>, Error> {
+    change_order_status(pool, _request_user, id, OrderStatus::CheckedIn).await
+}
+pub async fn complete_order(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<Order>, Error> {
+    change_order_status(pool, _request_user, id, OrderStatus::Completed).await
+}
+async fn cancel_with_refund(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>, to: OrderStatus) -> Result<Json<CancelOrderJson>, Error> {
+    db_block(&pool, move |_connection| {
+        let provider = get_payment_provider()?;
+        Ok(Json(Refund::cancel_order(_connection, provider.as_ref(), &id, &_request_user, to)?))
+    }).await
+}
+pub async fn cancel_order(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<CancelOrderJson>, Error> {
+    cancel_with_refund(pool, _request_user, id, OrderStatus::CancelledByUser).await
+}
+pub async fn cancel_order_by_place(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<CancelOrderJson>, Error> {
+    cancel_with_refund(pool, _request_user, id, OrderStatus::CancelledByPlace).await
+}
+pub async fn no_show_order(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<Order>, Error> {
+    change_order_status(pool, _request_user, id, OrderStatus::NoShow).await
+}
+
+pub async fn get_holds(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<Vec<OrderHold>>, Error> {
+    db_block(&pool, move |_connection| {
+        let holds = OrderHold::get_for_user(_connection, &_request_user.id)?
+            .into_iter()
+            .map(|h| h.localize(_connection))
+            .collect::<Result<Vec<OrderHold>, Error>>()?;
+        Ok(Json(holds))
+    }).await
+}
+/// выбранные на схеме модули держатся за пользователем, пока он оформляет заказ
+pub async fn create_holds(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<Vec<OrderJson>>) -> Result<Json<Vec<OrderHold>>, Error> {
+    _request_user.require(Permission::Book)?;
+    _request_user.require_verified()?;
+    db_block(&pool, move |_connection| {
+        let holds = OrderHold::create(_connection, &_request_user.id, &data)?
+            .into_iter()
+            .map(|h| h.localize(_connection))
+            .collect::<Result<Vec<OrderHold>, Error>>()?;
+        Ok(Json(holds))
+    }).await
+}
+pub async fn extend_hold(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<OrderHold>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(OrderHold::extend(_connection, &id, &_request_user.id)?.localize(_connection)?))).await
+}
+pub async fn release_hold(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<HttpResponse, Error> {
+    db_block(&pool, move |_connection| OrderHold::release(_connection, &id, &_request_user.id)).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+/// превращает действующие холды пользователя в заказ
+pub async fn order_holds(pool: web::Data<Pool>, _request_user: RequestUser, params: web::Query<PromoCodeParams>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::Book)?;
+    _request_user.require_verified()?;
+    let _created = db_block(&pool, move |_connection| {
+        let form = OrderHold::to_order_json(_connection, &_request_user.id)?;
+        Order::create(_connection, _request_user.id.clone(), Json(form), params.into_inner().promo_code)
+    }).await?;
+    Ok(HttpResponse::Ok().json(_created))
 }
\ No newline at end of file
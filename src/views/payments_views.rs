@@ -0,0 +1,82 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    web,
+    web::Json,
+};
+use crate::models::{
+    Payment,
+    PaymentRespJson,
+    CreatePaymentJson,
+};
+use crate::utils::{
+    db_block,
+    get_payment_provider,
+    MockPaymentProvider,
+    PaymentProvider,
+    Pool,
+    RequestUser,
+    WebhookEvent,
+};
+use crate::errors::Error;
+use crate::vars;
+
+
+pub fn payments_routes(config: &mut web::ServiceConfig) {
+    config.route("/payments/", web::post().to(create_payment));
+    config.route("/payment/{id}/", web::get().to(get_payment));
+    config.route("/payments/webhook/", web::post().to(payment_webhook));
+    // страница оплаты заглушки проводит платёж без оплаты - только в dev/test
+    if vars::mock_payments_enabled() {
+        config.route("/payments/mock/{external_id}/", web::get().to(mock_pay));
+    }
+}
+
+pub async fn create_payment(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<CreatePaymentJson>) -> Result<Json<PaymentRespJson>, Error> {
+    db_block(&pool, move |_connection| {
+        let provider = get_payment_provider()?;
+        Ok(Json(Payment::create(_connection, provider.as_ref(), &_request_user.id, &data.order_ids)?))
+    }).await
+}
+
+pub async fn get_payment(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<PaymentRespJson>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(Payment::get_own(_connection, &id, &_request_user.id)?))).await
+}
+
+/// уведомление шлюза; подпись в заголовке X-Signature проверяет провайдер
+pub async fn payment_webhook(pool: web::Data<Pool>, req: HttpRequest, body: web::Bytes) -> Result<HttpResponse, Error> {
+    let signature = req.headers()
+        .get("x-signature")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    db_block(&pool, move |_connection| {
+        let provider = get_payment_provider()?;
+        let event = provider.verify_webhook(&signature, &body)?;
+        Payment::apply_webhook(_connection, provider.as_ref(), &event)
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// «страница оплаты» заглушки: сразу проводит платёж через тот же путь, что и вебхук.
+/// Подключается только при PAYMENT_PROVIDER=mock и APP_ENV=dev/test.
+pub async fn mock_pay(pool: web::Data<Pool>, external_id: web::Path<String>) -> Result<HttpResponse, Error> {
+    if !vars::mock_payments_enabled() {
+        return Err(Error::NotFound(String::from("Not found")));
+    }
+    db_block(&pool, move |_connection| {
+        let provider = MockPaymentProvider {
+            secret: vars::payment_webhook_secret()
+                .ok_or(Error::InternalServerError(String::from("PAYMENT_WEBHOOK_SECRET не задан")))?,
+        };
+        let amount = Payment::get_amount_by_external_id(_connection, "mock", &external_id)?;
+        let (body, signature) = provider.signed_webhook(&WebhookEvent {
+            external_id: external_id.to_string(),
+            status:      String::from("succeeded"),
+            amount:      amount,
+        });
+        let event = provider.verify_webhook(&signature, &body)?;
+        Payment::apply_webhook(_connection, &provider, &event)
+    }).await?;
+    Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("Оплачено"))
+}
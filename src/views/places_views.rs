@@ -9,31 +9,62 @@ use crate::models::{
     User, Place, PlaceJson, UserJson, ModuleJson, 
     RespOrderJson, CreateModuleJson, Module,
     Region, NewRegion, Citie, NewCitie,
-    ModuleType, Event,
+    ModuleType, Event, Permission,
+    AvailabilityParams, ModuleAvailability,
+    CancellationPolicy, CancellationPolicyJson,
+    PriceRule, PriceRuleJson, PricePreviewParams, PriceBreakdown, quote_module,
+    PlaceTz, Timezone,
+    PlaceSchedule, PlaceScheduleJson, PlaceHours, PlaceHoursJson, PlaceSeason, PlaceSeasonJson,
+    PlaceClosure, PlaceClosureJson, CreatedClosureJson,
+    SlotTemplate, SlotTemplateJson, SlotTemplateResp, SlotParams, SlotJson,
+    Amenity, AmenityJson, PlaceReview, PlaceSearchParams, PlaceSearchItem,
 
 };
 use serde::{Deserialize, Serialize};
 use actix_multipart::{Field, Multipart};
 
 use crate::utils::{
-    is_signed_in,
-    get_current_user,
-    establish_connection,
+    db_block,
     files_form,
+    get_limit,
+    get_page,
     Info,
+    Paginated,
+    Pool,
+    RequestUser,
 }; 
+use crate::errors::Error;
 use crate::views::{AuthResp2, AuthResp, ItemId};
 use crate::schema;
 use std::borrow::BorrowMut; 
 
 
 pub fn places_routes(config: &mut web::ServiceConfig) {
+    // до /places/{type_id}/, иначе search попадёт в type_id
+    config.route("/places/search/", web::get().to(search_places));
     config.route("/places/{type_id}/", web::get().to(get_places));
     config.route("/place/{id}/", web::get().to(get_place));
     config.route("/place/{id}/managers/", web::get().to(get_place_managers));
     config.route("/place/{id}/orders/", web::get().to(get_place_orders));
     config.route("/place/{id}/events/", web::get().to(get_place_events));
     config.route("/place/{id}/module_types/", web::get().to(get_place_module_types));
+    config.route("/place/{id}/availability/", web::get().to(get_place_availability));
+    config.route("/place/{id}/cancellation_policy/", web::get().to(get_cancellation_policy));
+    config.route("/place/{id}/cancellation_policy/", web::post().to(save_cancellation_policy));
+    config.route("/place/{id}/schedule/", web::get().to(get_place_schedule));
+    config.route("/place/{id}/hours/", web::post().to(save_place_hours));
+    config.route("/place/{id}/seasons/", web::post().to(save_place_seasons));
+    config.route("/place/{id}/closures/", web::post().to(create_place_closure));
+    config.route("/place_closure/{id}/delete/", web::post().to(delete_place_closure));
+    config.route("/place/{id}/slot_templates/", web::get().to(get_slot_templates));
+    config.route("/place/{id}/slot_templates/", web::post().to(save_slot_template));
+    config.route("/slot_template/{id}/delete/", web::post().to(delete_slot_template));
+    config.route("/place/{id}/slots/", web::get().to(get_place_slots));
+    config.route("/place/{id}/amenities/", web::get().to(get_place_amenities));
+    config.route("/place/{id}/amenities/", web::post().to(set_place_amenities));
+    config.route("/place/{id}/reviews/", web::get().to(get_place_reviews));
+    config.route("/amenities/", web::get().to(amenities));
+    config.route("/create_amenity/", web::post().to(create_amenity));
     config.route("/suggest_places/", web::get().to(get_suggest_places));
     config.route("/closed_places/", web::get().to(get_closed_places));
 
@@ -46,10 +77,15 @@ pub fn places_routes(config: &mut web::ServiceConfig) {
 
     config.route("/create_module_type/", web::post().to(create_module_type));
     config.route("/edit_module_type/{id}/", web::post().to(edit_module_type));
+    config.route("/module_type/{id}/price_rules/", web::get().to(get_price_rules));
+    config.route("/module_type/{id}/price_rules/", web::post().to(create_price_rule));
+    config.route("/price_rule/{id}/delete/", web::post().to(delete_price_rule));
+    config.route("/module/{id}/price/", web::get().to(get_module_price));
     config.route("/create_event/", web::post().to(create_event));
     config.route("/edit_event/{id}/", web::post().to(edit_event));
 
     config.route("/regions/", web::get().to(regions));
+    config.route("/timezones/", web::get().to(timezones));
     config.route("/cities/", web::get().to(cities)); 
     config.route("/region/{id}/", web::get().to(get_region));
     config.route("/create_region/", web::post().to(create_region));
@@ -67,15 +103,51 @@ pub fn places_routes(config: &mut web::ServiceConfig) {
     config.route("/delete_event/{id}/", web::post().to(delete_event));
 }
  
-pub async fn get_places(type_id: web::Path<i16>) -> Json<Vec<Place>> {
-    return Place::get_all(*type_id);
+pub async fn get_places(pool: web::Data<Pool>, type_id: web::Path<i16>) -> Result<Json<Vec<Place>>, Error> {
+    db_block(&pool, move |_connection| Place::get_all(_connection, *type_id)).await
 }
 
-pub async fn regions() -> Json<Vec<Region>> {
-    return Json(Region::get_all());
+/// поиск объектов, ?page= и ?limit= (до 100) - страница ответа
+pub async fn search_places(req: HttpRequest, pool: web::Data<Pool>, params: web::Query<PlaceSearchParams>) -> Result<Json<Paginated<PlaceSearchItem>>, Error> {
+    let page = get_page(&req);
+    let limit = get_limit(params.limit, 20);
+    db_block(&pool, move |_connection| Ok(Json(Place::search(_connection, &params, page, limit)?))).await
+}
+pub async fn amenities(pool: web::Data<Pool>) -> Result<Json<Vec<Amenity>>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(Amenity::get_all(_connection)?))).await
+}
+pub async fn create_amenity(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<AmenityJson>) -> Result<Json<Amenity>, Error> {
+    _request_user.require(Permission::ManageAnyPlace)?;
+    db_block(&pool, move |_connection| Ok(Json(Amenity::create(_connection, &data.name)?))).await
+}
+pub async fn get_place_amenities(pool: web::Data<Pool>, id: web::Path<String>) -> Result<Json<Vec<Amenity>>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(Amenity::get_for_place(_connection, &id)?))).await
+}
+/// заменяет удобства объекта списком id
+pub async fn set_place_amenities(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>, data: Json<Vec<i32>>) -> Result<Json<Vec<Amenity>>, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &id)?;
+        Ok(Json(Amenity::set_for_place(_connection, &id, &data)?))
+    }).await
+}
+#[derive(Deserialize)]
+pub struct LimitParams {
+    pub limit: Option<i64>,
+}
+pub async fn get_place_reviews(req: HttpRequest, pool: web::Data<Pool>, id: web::Path<String>, params: web::Query<LimitParams>) -> Result<Json<Paginated<PlaceReview>>, Error> {
+    let page = get_page(&req);
+    let limit = get_limit(params.limit, 20);
+    db_block(&pool, move |_connection| Ok(Json(PlaceReview::get_for_place(_connection, &id, page, limit)?))).await
 }
-pub async fn cities() -> Json<Vec<Citie>> {
-    return Json(Citie::get_all());
+
+pub async fn timezones(pool: web::Data<Pool>) -> Result<Json<Vec<Timezone>>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(Timezone::get_all(_connection)?))).await
+}
+pub async fn regions(pool: web::Data<Pool>) -> Result<Json<Vec<Region>>, Error> {
+    return Ok(Json(db_block(&pool, |_connection| Region::get_all(_connection)).await?));
+}
+pub async fn cities(pool: web::Data<Pool>) -> Result<Json<Vec<Citie>>, Error> {
+    return Ok(Json(db_block(&pool, |_connection| Citie::get_all(_connection)).await?));
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -85,132 +157,166 @@ pub struct PlaceDataJson {
     pub place:   Place,
 }
 
-pub async fn get_place(req: HttpRequest, id: web::Path<String>) -> Json<PlaceDataJson> {
-    let place = Place::get_place(id.clone());
-    return Json(PlaceDataJson {
-        modules: place.get_modules(),
-        orders:  place.get_orders(),
-        place:   place, 
-    });
-}
-pub async fn get_region(req: HttpRequest, id: web::Path<i32>) -> Json<Region> {
-    let _region = Region::get(*id);
-    return Json(_region);
-}
-pub async fn get_city(req: HttpRequest, id: web::Path<i32>) -> Json<Citie> {
-    let _city = Citie::get(*id);
-    return Json(_city);
-}
-pub async fn get_module_type(req: HttpRequest, id: web::Path<String>) -> Json<ModuleType> {
-    let _module_type = ModuleType::get(id.clone());
-    return Json(_module_type);
-}
-pub async fn get_event(req: HttpRequest, id: web::Path<String>) -> Json<Event> {
-    let _event = Event::get(id.clone());
-    return Json(_event);
+pub async fn get_place(pool: web::Data<Pool>, id: web::Path<String>) -> Result<Json<PlaceDataJson>, Error> {
+    db_block(&pool, move |_connection| {
+        let place = Place::get_place(_connection, id.clone())?;
+        return Ok(Json(PlaceDataJson {
+            modules: place.get_modules(_connection)?,
+            orders:  place.get_orders(_connection)?,
+            place:   place, 
+        }));
+    }).await
+}
+/// без параметров окно - сутки с текущего момента
+pub async fn get_place_availability(pool: web::Data<Pool>, id: web::Path<String>, params: web::Query<AvailabilityParams>) -> Result<Json<Vec<ModuleAvailability>>, Error> {
+    db_block(&pool, move |_connection| {
+        let place = Place::get_place(_connection, id.clone())?;
+        Ok(Json(place.get_availability(_connection, &params)?))
+    }).await
+}
+/// null - правил нет, возврат полный
+pub async fn get_cancellation_policy(pool: web::Data<Pool>, id: web::Path<String>) -> Result<Json<Option<CancellationPolicy>>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(CancellationPolicy::get(_connection, &id)?))).await
+}
+pub async fn save_cancellation_policy(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>, data: Json<CancellationPolicyJson>) -> Result<Json<CancellationPolicy>, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place_owner(_connection, &id)?;
+        Ok(Json(CancellationPolicy::save(_connection, &id, &data)?))
+    }).await
+}
+/// часы работы, сезоны и предстоящие закрытия в местном времени объекта
+pub async fn get_place_schedule(pool: web::Data<Pool>, id: web::Path<String>) -> Result<Json<PlaceScheduleJson>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(PlaceSchedule::get_json(_connection, &id)?))).await
+}
+/// заменяет часы работы на всю неделю; пустой список - круглосуточно
+pub async fn save_place_hours(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>, data: Json<Vec<PlaceHoursJson>>) -> Result<Json<Vec<PlaceHours>>, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place_owner(_connection, &id)?;
+        Ok(Json(PlaceSchedule::save_hours(_connection, &id, &data)?))
+    }).await
+}
+/// заменяет все сезоны; пустой список - круглый год
+pub async fn save_place_seasons(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>, data: Json<Vec<PlaceSeasonJson>>) -> Result<Json<Vec<PlaceSeason>>, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place_owner(_connection, &id)?;
+        Ok(Json(PlaceSchedule::save_seasons(_connection, &id, &data)?))
+    }).await
+}
+/// закрыть объект может и менеджер; notified - брони, клиентам которых ушло письмо
+pub async fn create_place_closure(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>, data: Json<PlaceClosureJson>) -> Result<Json<CreatedClosureJson>, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &id)?;
+        Ok(Json(PlaceClosure::create(_connection, &id, &_request_user, &data)?))
+    }).await
+}
+pub async fn delete_place_closure(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<HttpResponse, Error> {
+    db_block(&pool, move |_connection| {
+        let closure = PlaceClosure::get(_connection, &id)?;
+        _request_user.require_place(_connection, &closure.place_id)?;
+        PlaceClosure::delete(_connection, &id)
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+pub async fn get_slot_templates(pool: web::Data<Pool>, id: web::Path<String>) -> Result<Json<Vec<SlotTemplateResp>>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(SlotTemplate::get_for_place(_connection, &id)?))).await
+}
+/// заменяет шаблон объекта или типа модуля (module_type_id)
+pub async fn save_slot_template(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>, data: Json<SlotTemplateJson>) -> Result<Json<SlotTemplateResp>, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place_owner(_connection, &id)?;
+        Ok(Json(SlotTemplate::save(_connection, &id, &data)?))
+    }).await
+}
+pub async fn delete_slot_template(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<HttpResponse, Error> {
+    db_block(&pool, move |_connection| {
+        let template = SlotTemplate::get(_connection, &id)?;
+        _request_user.require_place_owner(_connection, &template.place_id)?;
+        SlotTemplate::delete(_connection, &id)
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+/// слоты дня с оставшейся вместимостью: /place/{id}/slots/?date=ГГГГ-ММ-ДД&module_type_id=...
+pub async fn get_place_slots(pool: web::Data<Pool>, id: web::Path<String>, params: web::Query<SlotParams>) -> Result<Json<Vec<SlotJson>>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(SlotTemplate::get_slots(_connection, &id, &params)?))).await
+}
+pub async fn get_region(pool: web::Data<Pool>, id: web::Path<i32>) -> Result<Json<Region>, Error> {
+    let _region = db_block(&pool, move |_connection| Region::get(_connection, *id)).await?;
+    return Ok(Json(_region));
+}
+pub async fn get_city(pool: web::Data<Pool>, id: web::Path<i32>) -> Result<Json<Citie>, Error> {
+    let _city = db_block(&pool, move |_connection| Citie::get(_connection, *id)).await?;
+    return Ok(Json(_city));
+}
+pub async fn get_module_type(pool: web::Data<Pool>, id: web::Path<String>) -> Result<Json<ModuleType>, Error> {
+    let _module_type = db_block(&pool, move |_connection| ModuleType::get(_connection, id.clone())).await?;
+    return Ok(Json(_module_type));
+}
+pub async fn get_event(pool: web::Data<Pool>, id: web::Path<String>) -> Result<Json<Event>, Error> {
+    let _event = db_block(&pool, move |_connection| {
+        let _event = Event::get(_connection, id.clone())?;
+        let tz = PlaceTz::for_place(_connection, &_event.place_id)?;
+        Ok::<Event, Error>(_event.localize(tz))
+    }).await?;
+    return Ok(Json(_event));
 }
 
-pub async fn get_place_managers(req: HttpRequest, id: web::Path<String>) -> Json<Vec<UserJson>> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        let _place = Place::get(id.clone());
-        if _request_user.is_can_work_in_object_with_id(&id) {
-            return _place.get_managers();
-        } 
-        else {
-            return Json(Vec::new());
-        }
-    }
-    else {
-        Json(Vec::new())
-    }
-}
-pub async fn get_place_orders(req: HttpRequest, id: web::Path<String>) -> Json<Vec<crate::models::RespOrderJson>> {
-    if is_signed_in(&req) { 
-        let _request_user = get_current_user(&req);
-        let _place = Place::get_place(id.clone());
-        if _request_user.is_can_work_in_object_with_id(&id) {
-            return Json(_place.get_orders());
-        }
-        else {
-            return Json(Vec::new());
-        }
-    }
-    else {
-        Json(Vec::new())
-    }
-} 
-pub async fn get_place_module_types(req: HttpRequest, id: web::Path<String>) -> Json<Vec<crate::models::ModuleType>> {
-    //if is_signed_in(&req) { 
-    //    let _request_user = get_current_user(&req);
-    //    if _request_user.is_can_work_in_object_with_id(&id) {
-            return crate::models::ModuleType::get_all_for_place(id.to_string());
-    //    }
-    //    else {
-    //        return Json(Vec::new());
-    //    }
-    //}
-    //else {
-    //    Json(Vec::new())
-    //}
-}
-pub async fn get_place_events(req: HttpRequest, id: web::Path<String>) -> Json<Vec<crate::models::Event>> {
-    return crate::models::Event::get_all_for_place(id.to_string());
+pub async fn get_place_managers(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<Vec<UserJson>>, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &id)?;
+        let _place = Place::get(_connection, id.clone())?;
+        _place.get_managers(_connection)
+    }).await
+}
+pub async fn get_place_orders(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<Json<Vec<crate::models::RespOrderJson>>, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &id)?;
+        let _place = Place::get_place(_connection, id.clone())?;
+        Ok(Json(_place.get_orders(_connection)?))
+    }).await
+}
+pub async fn get_place_module_types(pool: web::Data<Pool>, id: web::Path<String>) -> Result<Json<Vec<crate::models::ModuleType>>, Error> {
+    db_block(&pool, move |_connection| crate::models::ModuleType::get_all_for_place(_connection, id.to_string())).await
+}
+pub async fn get_place_events(pool: web::Data<Pool>, id: web::Path<String>) -> Result<Json<Vec<crate::models::Event>>, Error> {
+    db_block(&pool, move |_connection| crate::models::Event::get_all_for_place(_connection, id.to_string())).await
 }
 
-pub async fn get_suggest_places(req: HttpRequest) -> Json<Vec<Place>> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            return Place::get_suggest();
-        }
-        else {
-            return Json(Vec::new());
-        }
-    }
-    else {
-        Json(Vec::new())
-    }
+pub async fn get_suggest_places(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<Vec<Place>>, Error> {
+    _request_user.require(Permission::ReviewPlaces)?;
+    db_block(&pool, |_connection| Place::get_suggest(_connection)).await
 }
-pub async fn get_closed_places(req: HttpRequest) -> Json<Vec<Place>> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            return Place::get_closed();
-        }
-        else {
-            return Json(Vec::new());
-        }
-    }
-    else {
-        Json(Vec::new())
-    }
+pub async fn get_closed_places(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<Vec<Place>>, Error> {
+    _request_user.require(Permission::ReviewPlaces)?;
+    db_block(&pool, |_connection| Place::get_closed(_connection)).await
 }
 
-pub async fn create_place(req: HttpRequest, data: Json<PlaceJson>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        Place::create(
-            data.title.clone(),
-            data.user_id.clone(),
-            data.city_id.clone(),
-            data.type_id.clone(),
-            data.cord.clone(),
-        ); 
+pub async fn create_place(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<PlaceJson>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::CreatePlace)?;
+    if data.user_id != _request_user.id {
+        _request_user.require(Permission::ManageAnyPlace)?;
     }
-    HttpResponse::Ok()
-}
-pub async fn edit_place(req: HttpRequest, data: Json<PlaceJson>, id: web::Path<String>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
+    db_block(&pool, move |_connection| Place::create(
+        _connection,
+        data.title.clone(),
+        data.user_id.clone(),
+        data.city_id.clone(),
+        data.type_id.clone(),
+        data.cord.clone(),
+    )).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+pub async fn edit_place(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<PlaceJson>, id: web::Path<String>) -> Result<HttpResponse, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &id)?;
         Place::edit(
+            _connection,
             id.to_string(),
             data.title.clone(),
             data.type_id.clone(),
             data.cord.clone(),
-        ); 
-    }
-    HttpResponse::Ok()
+        )
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -221,23 +327,19 @@ pub struct CreateModuleType {
     pub types:       String,
     pub price:       String,
 } 
-pub async fn create_module_type(req: HttpRequest, data: Json<CreateModuleType>) -> Json<crate::utils::Info> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        let uuid = crate::models::ModuleType::create(
+pub async fn create_module_type(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<CreateModuleType>) -> Result<Json<crate::utils::Info>, Error> {
+    let uuid = db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &data.place_id)?;
+        crate::models::ModuleType::create(
+            _connection,
             data.place_id.clone(),
             data.title.clone(),
             data.description.clone(),
             data.types.clone(),
             data.price.clone(),
-        );  
-        return Json(uuid);
-    }
-    else {
-        return Json(crate::utils::Info {
-            text: "".to_string(),
-        });
-    }
+        )
+    }).await?;
+    Ok(Json(uuid))
 }
 #[derive(Deserialize, Serialize, Debug)]
 pub struct EditModuleType {
@@ -246,23 +348,41 @@ pub struct EditModuleType {
     pub types:       String,
     pub price:       String,
 } 
-pub async fn edit_module_type(req: HttpRequest, data: Json<EditModuleType>, id: web::Path<String>) -> Json<crate::utils::Info> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        let uuid = crate::models::ModuleType::edit(
+pub async fn edit_module_type(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<EditModuleType>, id: web::Path<String>) -> Result<Json<crate::utils::Info>, Error> {
+    let uuid = db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &ModuleType::get(_connection, id.to_string())?.place_id)?;
+        crate::models::ModuleType::edit(
+            _connection,
             id.to_string(),
             data.title.clone(),
             data.description.clone(),
             data.types.clone(),
             data.price.clone(),
-        ); 
-        return Json(uuid);
-    }
-    else {
-        return Json(crate::utils::Info {
-            text: "".to_string(),
-        });
-    }
+        )
+    }).await?;
+    Ok(Json(uuid))
+}
+
+pub async fn get_price_rules(pool: web::Data<Pool>, id: web::Path<String>) -> Result<Json<Vec<PriceRule>>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(PriceRule::get_for_module_type(_connection, &id)?))).await
+}
+pub async fn create_price_rule(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>, data: Json<PriceRuleJson>) -> Result<Json<PriceRule>, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &ModuleType::get(_connection, id.to_string())?.place_id)?;
+        Ok(Json(PriceRule::create(_connection, &id, &data)?))
+    }).await
+}
+pub async fn delete_price_rule(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<HttpResponse, Error> {
+    db_block(&pool, move |_connection| {
+        let rule = PriceRule::get(_connection, &id)?;
+        _request_user.require_place(_connection, &ModuleType::get(_connection, rule.module_type_id)?.place_id)?;
+        PriceRule::delete(_connection, &id)
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+/// цена модуля на время с учётом правил цены: /module/{id}/price/?time_start=...&time_end=...
+pub async fn get_module_price(pool: web::Data<Pool>, id: web::Path<String>, params: web::Query<PricePreviewParams>) -> Result<Json<PriceBreakdown>, Error> {
+    db_block(&pool, move |_connection| Ok(Json(quote_module(_connection, &id, &params.time_start, &params.time_end)?))).await
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -282,89 +402,71 @@ pub struct EditEvent {
     pub time_start:  String,
     pub time_end:    String,
 } 
-pub async fn create_event(req: HttpRequest, data: Json<CreateEvent>) -> Json<crate::utils::Info> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        let uuid = crate::models::Event::create(
-            _request_user.id,
+pub async fn create_event(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<CreateEvent>) -> Result<Json<crate::utils::Info>, Error> {
+    let uuid = db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &data.place_id)?;
+        crate::models::Event::create(
+            _connection,
+            _request_user.id.clone(),
             data.place_id.clone(),
             data.title.clone(), 
             data.description.clone(),
             data.price.clone(),
             data.time_start.clone(),
             data.time_end.clone(),
-        ); 
-        println!("uuid: {:?}", uuid);
-        return Json(uuid);
-    }
-    else {
-        return Json(crate::utils::Info {
-            text: "".to_string(),
-        });
-    }
-}
-pub async fn edit_event(req: HttpRequest, data: Json<EditEvent>, id: web::Path<String>) -> Json<crate::utils::Info> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        let uuid = crate::models::Event::edit(
+        )
+    }).await?;
+    println!("uuid: {:?}", uuid);
+    Ok(Json(uuid))
+}
+pub async fn edit_event(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<EditEvent>, id: web::Path<String>) -> Result<Json<crate::utils::Info>, Error> {
+    let uuid = db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &Event::get(_connection, id.to_string())?.place_id)?;
+        crate::models::Event::edit(
+            _connection,
             id.to_string(), 
             data.title.clone(),
             data.description.clone(),
             data.price.clone(),
             data.time_start.clone(),
             data.time_end.clone(),
-        );
-        return Json(uuid);
-    }
-    else {
-        return Json(crate::utils::Info {
-            text: "".to_string(),
-        });
-    }
+        )
+    }).await?;
+    Ok(Json(uuid))
 }
 
-pub async fn create_modules(req: HttpRequest, data: Json<CreateModuleJson>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        Module::create(data); 
-    }
-    HttpResponse::Ok()
+pub async fn create_modules(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<CreateModuleJson>) -> Result<HttpResponse, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &data.place_id)?;
+        Module::create(_connection, data)
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct CreateRegionJson { 
-    pub name: String,
-    pub cord: Option<String>,
+    pub name:        String,
+    pub cord:        Option<String>,
+    // id из /timezones/; без пояса объекты региона живут по Москве
+    pub timezone_id: Option<i32>,
 }
 
-pub async fn create_region(req: HttpRequest, data: Json<CreateRegionJson>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            Region::create(data.name.clone(), data.cord.clone());
-        }
-    }
-    HttpResponse::Ok()
+pub async fn create_region(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<CreateRegionJson>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::ManageGeo)?;
+    db_block(&pool, move |_connection| Region::create(_connection, data.name.clone(), data.cord.clone(), data.timezone_id)).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-pub async fn edit_region(req: HttpRequest, data: Json<CreateRegionJson>, id: web::Path<i32>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            Region::edit(*id, data.name.clone(), data.cord.clone());
-        }
-    }
-    HttpResponse::Ok()
+pub async fn edit_region(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<CreateRegionJson>, id: web::Path<i32>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::ManageGeo)?;
+    db_block(&pool, move |_connection| Region::edit(_connection, *id, data.name.clone(), data.cord.clone(), data.timezone_id)).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-pub async fn delete_region(req: HttpRequest, id: web::Path<i32>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            Region::delete(*id);
-        }
-    }
-    HttpResponse::Ok()
+pub async fn delete_region(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<i32>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::ManageGeo)?;
+    db_block(&pool, move |_connection| Region::delete(_connection, *id)).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -374,53 +476,35 @@ pub struct CreateCityJson {
     pub cord:      Option<String>,
 }
 
-pub async fn create_city(req: HttpRequest, data: Json<CreateCityJson>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            Citie::create(data.region_id.clone(), data.name.clone(), data.cord.clone());
-        }
-    }
-    HttpResponse::Ok()
+pub async fn create_city(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<CreateCityJson>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::ManageGeo)?;
+    db_block(&pool, move |_connection| Citie::create(_connection, data.region_id.clone(), data.name.clone(), data.cord.clone())).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-pub async fn edit_city(req: HttpRequest, data: Json<CreateCityJson>, id: web::Path<i32>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            Citie::edit(*id, data.region_id.clone(), data.name.clone(), data.cord.clone());
-        }
-    }
-    HttpResponse::Ok()
+pub async fn edit_city(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<CreateCityJson>, id: web::Path<i32>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::ManageGeo)?;
+    db_block(&pool, move |_connection| Citie::edit(_connection, *id, data.region_id.clone(), data.name.clone(), data.cord.clone())).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-pub async fn delete_city(req: HttpRequest, id: web::Path<i32>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            Citie::delete(*id);
-        }
-    }
-    HttpResponse::Ok()
+pub async fn delete_city(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<i32>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::ManageGeo)?;
+    db_block(&pool, move |_connection| Citie::delete(_connection, *id)).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-pub async fn delete_module_type(req: HttpRequest, id: web::Path<String>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        let place_id = id.clone();
-        if _request_user.is_can_work_in_object_with_id(&place_id) {
-            ModuleType::delete(place_id);
-        }
-    }
-    HttpResponse::Ok()
-}
-pub async fn delete_event(req: HttpRequest, id: web::Path<String>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        let place_id = id.clone();
-        if _request_user.is_can_work_in_object_with_id(&place_id) {
-            crate::models::Event::delete(place_id);
-        }
-    }
-    HttpResponse::Ok()
+pub async fn delete_module_type(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<HttpResponse, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &ModuleType::get(_connection, id.to_string())?.place_id)?;
+        ModuleType::delete(_connection, id.to_string())
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+pub async fn delete_event(pool: web::Data<Pool>, _request_user: RequestUser, id: web::Path<String>) -> Result<HttpResponse, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place(_connection, &Event::get(_connection, id.to_string())?.place_id)?;
+        crate::models::Event::delete(_connection, id.to_string())
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
 }
\ No newline at end of file
@@ -6,17 +6,12 @@ use actix_web::{
     web::Json,
 };
 use crate::models::{
-    User, Partner,
+    User, Partner, Permission, Role, UserBan,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::utils::{
-    is_signed_in,
-    get_current_user,
-    establish_connection,
-}; 
-use crate::views::AuthResp;
-use crate::schema;
+use crate::utils::{db_block, Pool, RequestUser};
+use crate::errors::Error;
 use std::borrow::BorrowMut;
 use actix_multipart::Multipart;
 
@@ -29,6 +24,7 @@ pub fn user_routes(config: &mut web::ServiceConfig) {
     config.route("/suggest/", web::get().to(get_suggest));
     config.route("/moderators/", web::get().to(get_moderators));
     config.route("/banned_users/", web::get().to(get_banned_users));
+    config.route("/user/{id}/bans/", web::get().to(get_user_bans));
 
     config.route("/block_user/", web::post().to(block_user));
     config.route("/unblock_user/", web::post().to(unblock_user));
@@ -49,202 +45,133 @@ pub struct ProfileJson {
     pub places:  Vec<crate::models::PlaceListJson>,
 } 
 
-pub async fn get_profile(req: HttpRequest) -> Json<ProfileJson> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.email == "interesnijsim49293@gmail.com".to_string() {
-            User::create_superuser(_request_user.id.clone());
-        }
-        return Json( ProfileJson { 
-            orders: _request_user.get_orders(),
-            places: _request_user.get_objects(),
-        });
-    }
-    else {
-        return Json( ProfileJson {
-            orders: Vec::new(),
-            places: Vec::new(),
-        });
-    }
+pub async fn get_profile(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<ProfileJson>, Error> {
+    db_block(&pool, move |_connection| {
+        return Ok(Json( ProfileJson { 
+            orders: _request_user.get_orders(_connection)?,
+            places: _request_user.get_objects(_connection)?,
+        }));
+    }).await
 }
-pub async fn get_orders(req: HttpRequest) -> Json<Vec<crate::models::RespOrderJson2>> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        return Json(_request_user.get_orders());
-    }
-    else {
-        Json(Vec::new())
-    }
+pub async fn get_orders(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<Vec<crate::models::RespOrderJson2>>, Error> {
+    return Ok(Json(db_block(&pool, move |_connection| _request_user.get_orders(_connection)).await?));
 }
 
-pub async fn get_admins(req: HttpRequest) -> Json<Vec<crate::models::UserJson>> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            return _request_user.get_admins();
-        }
-        else {
-            return Json(Vec::new());
-        }
-    }
-    else {
-        Json(Vec::new())
-    }
+pub async fn get_admins(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<Vec<crate::models::UserJson>>, Error> {
+    _request_user.require(Permission::ViewStaff)?;
+    db_block(&pool, move |_connection| _request_user.get_admins(_connection)).await
 }
 
-pub async fn get_users(req: HttpRequest) -> Json<Vec<crate::models::UserJson>> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.perm == 10 || _request_user.perm == 5 {
-            return _request_user.get_users();
-        } 
-        else {
-            return Json(Vec::new());
-        }
-    }
-    else {
-        Json(Vec::new())
-    }
+pub async fn get_users(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<Vec<crate::models::UserJson>>, Error> {
+    _request_user.require(Permission::ViewUsers)?;
+    db_block(&pool, move |_connection| _request_user.get_users(_connection)).await
 }
 
-pub async fn get_partners(req: HttpRequest) -> Json<Vec<crate::models::RespPartnerJson>> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req); 
-        if _request_user.is_superuser() {
-            return crate::models::Partner::get_partners();
-        } 
-        else {
-            return Json(Vec::new());
-        }
-    }
-    else {
-        Json(Vec::new())
-    }
+pub async fn get_partners(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<Vec<crate::models::RespPartnerJson>>, Error> {
+    _request_user.require(Permission::ManagePartners)?;
+    db_block(&pool, |_connection| crate::models::Partner::get_partners(_connection)).await
 }
-pub async fn get_suggest(req: HttpRequest) -> Json<Vec<crate::models::RespPartnerJson>> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            return crate::models::Partner::get_suggest();
-        } 
-        else {
-            return Json(Vec::new());
-        }
-    }
-    else {
-        Json(Vec::new())
-    }
+pub async fn get_suggest(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<Vec<crate::models::RespPartnerJson>>, Error> {
+    _request_user.require(Permission::ManagePartners)?;
+    db_block(&pool, |_connection| crate::models::Partner::get_suggest(_connection)).await
 }
 
-pub async fn get_moderators(req: HttpRequest) -> Json<Vec<crate::models::UserJson>> {
-    if is_signed_in(&req) { 
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            return _request_user.get_admins();
-        }
-        else {
-            return Json(Vec::new());
-        }
-    }
-    else {
-        Json(Vec::new())
-    }
+pub async fn get_moderators(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<Vec<crate::models::UserJson>>, Error> {
+    _request_user.require(Permission::ViewStaff)?;
+    db_block(&pool, move |_connection| _request_user.get_admins(_connection)).await
 }
 
-pub async fn get_banned_users(req: HttpRequest) -> Json<Vec<crate::models::UserJson>> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.is_superuser() {
-            return _request_user.get_banned_users();
-        }
-        else {
-            return Json(Vec::new());
-        }
-    }
-    else {
-        Json(Vec::new())
-    }
+pub async fn get_banned_users(pool: web::Data<Pool>, _request_user: RequestUser) -> Result<Json<Vec<crate::models::UserJson>>, Error> {
+    _request_user.require(Permission::BlockUsers)?;
+    db_block(&pool, move |_connection| _request_user.get_banned_users(_connection)).await
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct ItemId {
     pub id:  String,
 }
-pub async fn block_user(req: HttpRequest, data: Json<ItemId>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        User::create_user_block(data.id.clone());
-    }
-    HttpResponse::Ok()
-}
-pub async fn unblock_user(req: HttpRequest, data: Json<ItemId>) -> impl Responder {
-    let _request_user = get_current_user(&req);
-    if _request_user.perm == 10 {
-        User::delete_user_block(data.id.clone());
-    }
-    HttpResponse::Ok()
-}
-pub async fn create_manager(req: HttpRequest, data: Json<crate::models::PlaceManagerJson>) -> impl Responder {
-    if is_signed_in(&req) { 
-        let _request_user = get_current_user(&req);
-        _request_user.create_manager(data);
-    }
-    HttpResponse::Ok()
-}
-pub async fn delete_manager(req: HttpRequest, data: Json<crate::models::PlaceManagerJson>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        _request_user.delete_manager(data);
-    }
-    HttpResponse::Ok()
+pub async fn block_user(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<crate::models::BlockUserJson>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::BlockUsers)?;
+    db_block(&pool, move |_connection| {
+        let _user = User::get_user_with_id(_connection, &data.id)?;
+        // суперпользователя не блокируем, модераторов блокирует только суперпользователь
+        match _user.role() {
+            Role::Superuser => return Err(Error::Forbidden),
+            Role::Moderator => _request_user.require(Permission::BlockStaff)?,
+            _ => (),
+        }
+        User::create_user_block(
+            _connection,
+            data.id.clone(),
+            _request_user.id.clone(),
+            data.reason.clone(),
+            data.expires,
+        )
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+pub async fn unblock_user(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<ItemId>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::BlockUsers)?;
+    db_block(&pool, move |_connection| {
+        // вернуть роль модератора может только тот, кто может его заблокировать
+        if let Some(_ban) = crate::models::UserBan::get_active(_connection, &data.id)? {
+            if Role::from_perm(_ban.prev_perm) == Role::Moderator {
+                _request_user.require(Permission::BlockStaff)?;
+            }
+        }
+        User::delete_user_block(_connection, data.id.clone(), Some(_request_user.id.clone()))
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+pub async fn get_user_bans(pool: web::Data<Pool>, _request_user: RequestUser, user_id: web::Path<String>) -> Result<Json<Vec<UserBan>>, Error> {
+    _request_user.require(Permission::BlockUsers)?;
+    Ok(Json(db_block(&pool, move |_connection| UserBan::get_for_user(_connection, &user_id)).await?))
+}
+pub async fn create_manager(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<crate::models::PlaceManagerJson>) -> Result<HttpResponse, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place_owner(_connection, &data.place_id)?;
+        _request_user.create_manager(_connection, data)
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+pub async fn delete_manager(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<crate::models::PlaceManagerJson>) -> Result<HttpResponse, Error> {
+    db_block(&pool, move |_connection| {
+        _request_user.require_place_owner(_connection, &data.place_id)?;
+        _request_user.delete_manager(_connection, data)
+    }).await?;
+    Ok(HttpResponse::Ok().finish())
 } 
 
-pub async fn suggest_partner(req: HttpRequest, data: Json<crate::models::PartnerJson>) -> impl Responder {
-    if is_signed_in(&req) { 
-        let _request_user = get_current_user(&req);
-        //if _request_user.perm == 10 {
-            Partner::suggest_partner(data);
-        //} 
+pub async fn suggest_partner(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<crate::models::PartnerJson>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::SuggestPartner)?;
+    if data.user_id != _request_user.id {
+        _request_user.require(Permission::ManagePartners)?;
     }
-    HttpResponse::Ok()
+    db_block(&pool, move |_connection| Partner::suggest_partner(_connection, data)).await?;
+    Ok(HttpResponse::Ok().finish())
 }
-pub async fn create_partner(req: HttpRequest, user_id: web::Path<String>) -> impl Responder {
-    if is_signed_in(&req) { 
-        let _request_user = get_current_user(&req);
-        if _request_user.perm == 10 {
-            Partner::create_partner(user_id.to_string());
-        }
-    }
-    HttpResponse::Ok()
+pub async fn create_partner(pool: web::Data<Pool>, _request_user: RequestUser, user_id: web::Path<String>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::ManagePartners)?;
+    db_block(&pool, move |_connection| Partner::create_partner(_connection, user_id.to_string())).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-pub async fn delete_partner(req: HttpRequest, data: Json<ItemId>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.perm == 10 { 
-            Partner::delete(data.id.clone());
-        }
-    }
-    HttpResponse::Ok()
+pub async fn delete_partner(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<ItemId>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::ManagePartners)?;
+    db_block(&pool, move |_connection| Partner::delete(_connection, data.id.clone())).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-pub async fn edit_user(req: HttpRequest, data: Json<crate::models::EditUserJson>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        _request_user.edit(data);
-    }
-    HttpResponse::Ok()
+pub async fn edit_user(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<crate::models::EditUserJson>) -> Result<HttpResponse, Error> {
+    db_block(&pool, move |_connection| _request_user.edit(_connection, data)).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 
-pub async fn change_owner_partner(req: HttpRequest, data: Json<crate::models::EditOwnerPartnerJson>) -> impl Responder {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        if _request_user.perm == 10 { 
-            User::edit_owner_partner(data);
-        }
-    }
-    HttpResponse::Ok()
+pub async fn change_owner_partner(pool: web::Data<Pool>, _request_user: RequestUser, data: Json<crate::models::EditOwnerPartnerJson>) -> Result<HttpResponse, Error> {
+    _request_user.require(Permission::ManagePartners)?;
+    db_block(&pool, move |_connection| User::edit_owner_partner(_connection, data)).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 
@@ -253,65 +180,74 @@ struct ImageParams {
     pub types: Option<String>,
     pub id:    Option<String>,
 }
-pub async fn upload_files(mut payload: Multipart, req: HttpRequest) -> actix_web::Result<HttpResponse> {
-    if is_signed_in(&req) {
-        let _request_user = get_current_user(&req);
-        let params_some = web::Query::<ImageParams>::from_query(&req.query_string());
-        let types: String; 
-        let id: String;
-        if params_some.is_ok() {
-            let params = params_some.unwrap();
-            if params.types.is_some() {
-                types = params.types.as_deref().unwrap().to_string();
-            }
-            else {
-                types = "".to_string();
-            }
-            if params.id.is_some() {
-                id = params.id.as_deref().unwrap().to_string();
-            }
-            else {
-                id = "".to_string();
-            }
+pub async fn upload_files(mut payload: Multipart, req: HttpRequest, pool: web::Data<Pool>, _request_user: RequestUser) -> actix_web::Result<HttpResponse> {
+    let params_some = web::Query::<ImageParams>::from_query(&req.query_string());
+    let types: String; 
+    let id: String;
+    if params_some.is_ok() {
+        let params = params_some.unwrap();
+        if params.types.is_some() {
+            types = params.types.as_deref().unwrap().to_string();
         }
         else {
             types = "".to_string();
-            id = "".to_string();
         }
-
-        match types.as_str() {
-            "user_avatar" => {
-                println!("user_avatar upload");
-                let form = crate::utils::image_form(payload.borrow_mut()).await;
-                User::change_avatar(_request_user.id, Some(form.image.clone()));
-                return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("user_avatar"));
-            },
-            "place_avatar" => {
-                println!("place_avatar upload");
-                let form = crate::utils::image_form(payload.borrow_mut()).await;
-                crate::models::Place::change_avatar(id, Some(form.image.clone()));
-                return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("place_avatar"));
-            },
-            "place_background" => {
-                println!("place_background upload");
-                let form = crate::utils::image_form(payload.borrow_mut()).await;
-                crate::models::Place::change_background(id, Some(form.image.clone()));
-                return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("place_background"));
-            },
-            "module_type_avatar" => {
-                println!("module_type_avatar upload");
-                let form = crate::utils::image_form(payload.borrow_mut()).await;
-                crate::models::ModuleType::change_image(id, Some(form.image.clone()));
-                return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("user_avatar"));
-            },
-            "event_avatar" => {
-                println!("event_avatar upload");
-                let form = crate::utils::image_form(payload.borrow_mut()).await;
-                crate::models::Event::change_image(id, Some(form.image.clone()));
-                return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("user_avatar"));
-            },
-            _ => return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("")),
+        if params.id.is_some() {
+            id = params.id.as_deref().unwrap().to_string();
+        }
+        else {
+            id = "".to_string();
         }
     }
-    Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body(""))
+    else {
+        types = "".to_string();
+        id = "".to_string();
+    }
+
+    match types.as_str() {
+        "user_avatar" => {
+            println!("user_avatar upload");
+            let form = crate::utils::image_form(payload.borrow_mut()).await;
+            let user_id = _request_user.id.clone();
+            db_block(&pool, move |_connection| User::change_avatar(_connection, user_id, Some(form.image.clone()))).await?;
+            return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("user_avatar"));
+        },
+        "place_avatar" => {
+            println!("place_avatar upload");
+            let place_id = id.clone();
+            db_block(&pool, move |_connection| _request_user.require_place(_connection, &place_id)).await?;
+            let form = crate::utils::image_form(payload.borrow_mut()).await;
+            db_block(&pool, move |_connection| crate::models::Place::change_avatar(_connection, id, Some(form.image.clone()))).await?;
+            return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("place_avatar"));
+        },
+        "place_background" => {
+            println!("place_background upload");
+            let place_id = id.clone();
+            db_block(&pool, move |_connection| _request_user.require_place(_connection, &place_id)).await?;
+            let form = crate::utils::image_form(payload.borrow_mut()).await;
+            db_block(&pool, move |_connection| crate::models::Place::change_background(_connection, id, Some(form.image.clone()))).await?;
+            return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("place_background"));
+        },
+        "module_type_avatar" => {
+            println!("module_type_avatar upload");
+            let module_type_id = id.clone();
+            db_block(&pool, move |_connection| {
+                _request_user.require_place(_connection, &crate::models::ModuleType::get(_connection, module_type_id)?.place_id)
+            }).await?;
+            let form = crate::utils::image_form(payload.borrow_mut()).await;
+            db_block(&pool, move |_connection| crate::models::ModuleType::change_image(_connection, id, Some(form.image.clone()))).await?;
+            return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("user_avatar"));
+        },
+        "event_avatar" => {
+            println!("event_avatar upload");
+            let event_id = id.clone();
+            db_block(&pool, move |_connection| {
+                _request_user.require_place(_connection, &crate::models::Event::get(_connection, event_id)?.place_id)
+            }).await?;
+            let form = crate::utils::image_form(payload.borrow_mut()).await;
+            db_block(&pool, move |_connection| crate::models::Event::change_image(_connection, id, Some(form.image.clone()))).await?;
+            return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("user_avatar"));
+        },
+        _ => return Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body("")),
+    }
 }
\ No newline at end of file